@@ -0,0 +1,84 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+/// Record where the binary was built from, for `nuprompt version`.
+fn main() {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let checkout = checkout(Path::new(&manifest_dir));
+    println!("cargo:rustc-env=NUPROMPT_BUILD_COMMIT={}", checkout.as_ref().map_or("", |c| c.commit.as_str()));
+
+    // any rerun directive turns off the rerun on every change to the package, so the sources and the files that move
+    // when a commit is made or checked out are listed here
+    println!("cargo:rerun-if-changed=src");
+    if let Some(checkout) = &checkout {
+        for file in &checkout.watched {
+            println!("cargo:rerun-if-changed={}", file.display());
+        }
+    }
+
+    // SOURCE_DATE_EPOCH keeps reproducible builds reproducible
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let epoch = std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|s| s.trim().parse().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_secs()));
+    println!("cargo:rustc-env=NUPROMPT_BUILD_DATE={}", utc_timestamp(epoch));
+
+    println!("cargo:rustc-env=NUPROMPT_BUILD_TARGET={}", std::env::var("TARGET").unwrap_or_default());
+
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(k, _)| Some(k.strip_prefix("CARGO_FEATURE_")?.to_ascii_lowercase().replace('_', "-")))
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=NUPROMPT_BUILD_FEATURES={}", features.join(","));
+}
+
+/// Checkout is the commit the crate is built from, and the files in the git dir that change along with it.
+struct Checkout {
+    commit: String,
+    watched: Vec<PathBuf>,
+}
+
+/// The checkout the crate is built in. A crate unpacked from a tarball inside some other repo must not report that
+/// repo's commit, so the top level of the checkout has to be the crate itself.
+fn checkout(manifest_dir: &Path) -> Option<Checkout> {
+    let output = Command::new("git").arg("-C").arg(manifest_dir)
+        .args(["rev-parse", "--show-toplevel", "--git-dir", "--git-common-dir", "HEAD", "--symbolic-full-name", "HEAD"])
+        .output().ok()
+        .filter(|o| o.status.success())?;
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let lines: Vec<&str> = stdout.lines().map(str::trim).collect();
+    let [toplevel, git_dir, common_dir, commit, head_ref] = lines[..] else {
+        return None;
+    };
+    if Path::new(toplevel).canonicalize().ok()? != manifest_dir.canonicalize().ok()? {
+        return None;
+    }
+    // the git dirs are relative to where git ran when they are below it
+    let (git_dir, common_dir) = (manifest_dir.join(git_dir), manifest_dir.join(common_dir));
+    let mut watched = vec![git_dir.join("HEAD"), common_dir.join("packed-refs")];
+    // a detached HEAD has no ref, and its commit is in HEAD itself. The directory of the ref is watched rather than the
+    // ref, since a ref that is only packed is written there on the next commit, and a directory counts as changed when
+    // anything in it does.
+    if head_ref.starts_with("refs/") {
+        watched.extend(common_dir.join(head_ref).parent().map(Path::to_path_buf));
+    }
+    // cargo reruns on every build for a file that doesn't exist
+    watched.retain(|f| f.exists());
+    Some(Checkout { commit: commit.to_owned(), watched })
+}
+
+/// Format the unix time as a UTC ISO 8601 timestamp. The date comes from the day number with Howard Hinnant's
+/// civil_from_days.
+fn utc_timestamp(epoch: u64) -> String {
+    let (days, secs) = ((epoch / 86400) as i64, epoch % 86400);
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, secs / 3600, secs % 3600 / 60, secs % 60)
+}
@@ -0,0 +1,51 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use log::debug;
+use crate::config::env_map;
+use crate::process::output_with_timeout;
+
+const BOOKMARKS_ENVVAR: &str = "NUPROMPT_BOOKMARKS";
+pub const ZOXIDE_ENVVAR: &str = "NUPROMPT_ZOXIDE";
+
+/// We only ask zoxide if it answers quickly, since it is an external process on every prompt.
+const ZOXIDE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Resolve the cwd to a bookmark name and the path remaining below the bookmark. The bookmarks in $NUPROMPT_BOOKMARKS
+/// are checked first and the longest matching path wins. Then, if enabled, we check whether zoxide's top match for the
+/// directory name is this directory, in which case `z <name>` would jump here.
+pub fn resolve(cwd: &Path) -> Option<(String, PathBuf)> {
+    let mut best: Option<(String, PathBuf, usize)> = None;
+    for (name, raw) in env_map(BOOKMARKS_ENVVAR) {
+        let target = expand_home(&raw);
+        if let Ok(rest) = cwd.strip_prefix(&target) {
+            let depth = target.components().count();
+            if best.as_ref().is_none_or(|(_, _, d)| depth > *d) {
+                best = Some((name, rest.to_path_buf(), depth));
+            }
+        }
+    }
+    if let Some((name, rest, _)) = best {
+        return Some((name, rest));
+    }
+    if std::env::var_os(ZOXIDE_ENVVAR).is_some() {
+        return zoxide(cwd).map(|name| (name, PathBuf::new()));
+    }
+    None
+}
+
+fn expand_home(raw: &str) -> PathBuf {
+    match (raw.strip_prefix("~"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest.trim_start_matches('/')),
+        _ => PathBuf::from(raw),
+    }
+}
+
+fn zoxide(cwd: &Path) -> Option<String> {
+    let name = cwd.file_name()?.to_str()?;
+    let out = output_with_timeout(Command::new("zoxide").args(["query", "--", name]), ZOXIDE_TIMEOUT)
+        .inspect_err(|e| debug!("zoxide query failed: {}", e))
+        .ok()?;
+    let top = String::from_utf8(out.stdout).ok()?;
+    Some(name.to_owned()).filter(|_| out.status.success() && Path::new(top.trim_end()) == cwd)
+}
@@ -0,0 +1,206 @@
+use std::fs;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use log::debug;
+use crate::host::fnv1a;
+use crate::state;
+
+/// The total size in KiB the cache may grow to before the least recently written entries are removed.
+const MAX_SIZE_ENVVAR: &str = "NUPROMPT_CACHE_MAX_KB";
+const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+
+/// The cache keeps one file per entry under the state dir, named by the namespace and a hash of the key. The file
+/// holds the length of the key as a big endian u32, the key, and then the value, so that a hash collision reads as a
+/// miss rather than another entry's value.
+fn dir() -> PathBuf {
+    state::state_dir().join(format!("NUPROMPT_{}_cache", users::get_current_uid()))
+}
+
+/// Whether the file or dir is our own and has exactly the given permissions. The state dir is often the temp dir that
+/// all the users of the machine share, where anyone could have put it in place before us to read what we keep in it or
+/// to plant entries of their choosing.
+fn ours(meta: &fs::Metadata, mode: u32) -> bool {
+    meta.uid() == users::get_effective_uid() && meta.mode() & 0o777 == mode
+}
+
+/// Whether the cache dir is a real directory of ours, checked without following a symlink.
+fn private(dir: &Path) -> bool {
+    let private = fs::symlink_metadata(dir).is_ok_and(|m| m.file_type().is_dir() && ours(&m, 0o700));
+    if !private {
+        debug!("ignoring the cache in {:?}, which is not a private dir of ours", dir);
+    }
+    private
+}
+
+fn entry_path(dir: &Path, namespace: &str, key: &[u8]) -> PathBuf {
+    dir.join(format!("{}_{:016x}", namespace, fnv1a(key)))
+}
+
+/// Read the value for the key, if there is one that was written less than the ttl ago.
+pub fn get(namespace: &str, key: &[u8], ttl: Option<Duration>) -> Option<Vec<u8>> {
+    if crate::fault::injected("cache") {
+        return None;
+    }
+    get_in(&dir(), namespace, key, ttl)
+}
+
+/// Write the value for the key through a temporary file and a rename, so that readers never see part of it, and then
+/// trim the cache back to its size bound.
+pub fn put(namespace: &str, key: &[u8], value: &[u8]) -> Result<(), anyhow::Error> {
+    crate::fault::check("cache")?;
+    let max_size = std::env::var(MAX_SIZE_ENVVAR).ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map_or(DEFAULT_MAX_SIZE, |kb| kb * 1024);
+    put_in(&dir(), namespace, key, value, max_size)
+}
+
+pub fn remove(namespace: &str, key: &[u8]) {
+    let _ = fs::remove_file(entry_path(&dir(), namespace, key));
+}
+
+fn get_in(dir: &Path, namespace: &str, key: &[u8], ttl: Option<Duration>) -> Option<Vec<u8>> {
+    if !private(dir) {
+        return None;
+    }
+    let path = entry_path(dir, namespace, key);
+    if let Some(ttl) = ttl {
+        let age = fs::metadata(&path).and_then(|m| m.modified()).ok()
+            .and_then(|t| SystemTime::now().duration_since(t).ok())?;
+        if age >= ttl {
+            debug!("cache entry {:?} expired", path);
+            return None;
+        }
+    }
+    let contents = fs::read(&path).ok()?;
+    let (len, rest) = contents.split_first_chunk::<4>()?;
+    let (stored_key, value) = rest.split_at_checked(u32::from_be_bytes(*len) as usize)?;
+    (stored_key == key).then(|| value.to_vec())
+}
+
+fn put_in(dir: &Path, namespace: &str, key: &[u8], value: &[u8], max_size: u64) -> Result<(), anyhow::Error> {
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    if !private(dir) {
+        anyhow::bail!("the cache dir {:?} is not a private dir of ours", dir);
+    }
+    let path = entry_path(dir, namespace, key);
+    let mut contents = Vec::with_capacity(4 + key.len() + value.len());
+    contents.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    contents.extend_from_slice(key);
+    contents.extend_from_slice(value);
+    let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, &path)?;
+    trim(dir, max_size)
+}
+
+/// Remove the oldest entries until the cache fits in the size bound.
+fn trim(dir: &Path, max_size: u64) -> Result<(), anyhow::Error> {
+    let mut entries: Vec<(SystemTime, u64, PathBuf)> = fs::read_dir(dir)?
+        .filter_map(|e| {
+            let e = e.ok()?;
+            // another writer is about to rename it into place
+            if e.path().extension().is_some_and(|x| x == "tmp") {
+                return None;
+            }
+            let meta = e.metadata().ok()?;
+            Some((meta.modified().ok()?, meta.len(), e.path()))
+        })
+        .collect();
+    let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+    if total <= max_size {
+        return Ok(());
+    }
+    entries.sort_unstable();
+    for (_, len, path) in entries {
+        if total <= max_size {
+            break;
+        }
+        debug!("evicting cache entry {:?}", path);
+        match fs::remove_file(&path) {
+            // a trim running at the same time got to it first
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => total -= len,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nuprompt-cache-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn age(dir: &Path, namespace: &str, key: &[u8], by: Duration) {
+        let path = entry_path(dir, namespace, key);
+        File::options().write(true).open(path).unwrap().set_modified(SystemTime::now() - by).unwrap();
+    }
+
+    #[test]
+    fn roundtrip_and_ttl() {
+        let dir = test_dir("ttl");
+        put_in(&dir, "unpushed", b"/src/a", b"3", DEFAULT_MAX_SIZE).unwrap();
+        assert_eq!(get_in(&dir, "unpushed", b"/src/a", None).as_deref(), Some(b"3".as_slice()));
+        assert_eq!(get_in(&dir, "unpushed", b"/src/b", None), None);
+        assert_eq!(get_in(&dir, "strategy", b"/src/a", None), None);
+        age(&dir, "unpushed", b"/src/a", Duration::from_secs(600));
+        assert_eq!(get_in(&dir, "unpushed", b"/src/a", Some(Duration::from_secs(300))), None);
+        assert_eq!(get_in(&dir, "unpushed", b"/src/a", None).as_deref(), Some(b"3".as_slice()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// The oldest entries go first once the cache is over its size bound.
+    #[test]
+    fn trims_to_size() {
+        let dir = test_dir("trim");
+        let value = [0u8; 100];
+        for (i, key) in [b"a", b"b", b"c"].iter().enumerate() {
+            put_in(&dir, "x", *key, &value, u64::MAX).unwrap();
+            age(&dir, "x", *key, Duration::from_secs(100 - i as u64));
+        }
+        put_in(&dir, "x", b"d", &value, 250).unwrap();
+        let kept: Vec<bool> = [b"a", b"b", b"c", b"d"].iter().map(|k| get_in(&dir, "x", *k, None).is_some()).collect();
+        assert_eq!(kept, [false, false, true, true]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// The files other writers are about to rename into place are left alone.
+    #[test]
+    fn trim_skips_temporary_files() {
+        let dir = test_dir("tmp");
+        put_in(&dir, "x", b"a", &[0u8; 100], u64::MAX).unwrap();
+        let tmp = entry_path(&dir, "x", b"b").with_extension("4242.tmp");
+        fs::write(&tmp, [0u8; 100]).unwrap();
+        File::options().write(true).open(&tmp).unwrap().set_modified(SystemTime::now() - Duration::from_secs(100)).unwrap();
+        trim(&dir, 150).unwrap();
+        assert!(tmp.exists());
+        assert!(get_in(&dir, "x", b"a", None).is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A cache dir that someone else could have set up is not read from or written to.
+    #[test]
+    fn ignores_shared_dir() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = test_dir("shared");
+        put_in(&dir, "x", b"a", b"1", DEFAULT_MAX_SIZE).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+        assert_eq!(get_in(&dir, "x", b"a", None), None);
+        assert!(put_in(&dir, "x", b"b", b"2", DEFAULT_MAX_SIZE).is_err());
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+        assert_eq!(get_in(&dir, "x", b"a", None).as_deref(), Some(b"1".as_slice()));
+
+        let link = test_dir("link");
+        std::os::unix::fs::symlink(&dir, &link).unwrap();
+        assert_eq!(get_in(&link, "x", b"a", None), None);
+        assert!(put_in(&link, "x", b"b", b"2", DEFAULT_MAX_SIZE).is_err());
+        fs::remove_file(&link).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
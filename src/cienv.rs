@@ -0,0 +1,66 @@
+use crate::git::GitBits;
+
+/// Set to take the git branch from the variables a CI system exports instead of scanning the checkout, the same as
+/// `nuprompt ps1 --from-env`.
+pub const FROM_ENV_ENVVAR: &str = "NUPROMPT_GIT_FROM_ENV";
+
+/// The variables that name the branch or tag being built, in order of preference. A pull request build names the
+/// branch it came from first, since the ref of the build itself is a merge ref like `refs/pull/12/merge`.
+const REF_VARS: &[&str] = &[
+    "GITHUB_HEAD_REF",
+    "GITHUB_REF_NAME",
+    "GITHUB_REF",
+    "CI_MERGE_REQUEST_SOURCE_BRANCH_NAME",
+    "CI_COMMIT_BRANCH",
+    "CI_COMMIT_TAG",
+    "BUILDKITE_BRANCH",
+    "CIRCLE_BRANCH",
+    "TRAVIS_BRANCH",
+    "BRANCH_NAME",
+    "GIT_BRANCH",
+];
+
+/// The variables with the commit being built, shown abbreviated when no branch is known, as for a detached HEAD.
+const SHA_VARS: &[&str] = &["GITHUB_SHA", "CI_COMMIT_SHA", "BUILDKITE_COMMIT", "CIRCLE_SHA1", "TRAVIS_COMMIT", "GIT_COMMIT"];
+
+/// Git info from the CI environment, for debug sessions inside CI jobs where the checkout is too large or too shallow
+/// to scan. Only the branch is known, so the status is marked as partial.
+pub fn git_bits() -> Option<GitBits> {
+    git_bits_from(|name| std::env::var(name).ok())
+}
+
+fn git_bits_from(lookup: impl Fn(&str) -> Option<String>) -> Option<GitBits> {
+    let value = |vars: &[&str]| vars.iter().find_map(|v| lookup(v).map(|s| s.trim().to_owned()).filter(|s| !s.is_empty()));
+    let head_ref = match value(REF_VARS) {
+        Some(r) => {
+            let short = r.strip_prefix("refs/heads/").or_else(|| r.strip_prefix("refs/tags/"))
+                // Jenkins names the remote branch
+                .or_else(|| r.strip_prefix("origin/"))
+                .unwrap_or(&r);
+            short.to_owned()
+        },
+        None => value(SHA_VARS)?.chars().take(7).collect(),
+    };
+    Some(GitBits { head_ref: head_ref.into_bytes(), partial: true, ..GitBits::default() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn head_ref(vars: &[(&str, &str)]) -> Option<String> {
+        let bits = git_bits_from(|name| vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string()))?;
+        Some(String::from_utf8(bits.head_ref).unwrap())
+    }
+
+    #[test]
+    fn head_ref_examples() {
+        assert_eq!(head_ref(&[("GITHUB_REF", "refs/heads/main")]).as_deref(), Some("main"));
+        assert_eq!(head_ref(&[("GITHUB_HEAD_REF", ""), ("GITHUB_REF", "refs/tags/v1.2")]).as_deref(), Some("v1.2"));
+        assert_eq!(head_ref(&[("GITHUB_HEAD_REF", "fix-it"), ("GITHUB_REF", "refs/pull/12/merge")]).as_deref(), Some("fix-it"));
+        assert_eq!(head_ref(&[("CI_COMMIT_BRANCH", "feature/x")]).as_deref(), Some("feature/x"));
+        assert_eq!(head_ref(&[("GIT_BRANCH", "origin/develop")]).as_deref(), Some("develop"));
+        assert_eq!(head_ref(&[("CI_COMMIT_SHA", "0123456789abcdef")]).as_deref(), Some("0123456"));
+        assert_eq!(head_ref(&[]), None);
+    }
+}
@@ -0,0 +1,196 @@
+#[cfg(target_os = "linux")]
+use std::sync::OnceLock;
+use coarsetime::Duration;
+use log::debug;
+
+/// Clock is the source of the ticks that ps0 records and ps1 measures from. Production code uses [SystemClock], tests
+/// can step a fake one to suspend, wrap, and skew at will.
+pub trait Clock {
+    fn now_ticks(&self) -> u64;
+}
+
+/// SystemClock reads the coarse monotonic clock, which is cheap and doesn't jump when the wall clock is changed. Where
+/// the coarse clock only moves every few milliseconds it reads the precise one instead, in the same ticks.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ticks(&self) -> u64 {
+        #[cfg(target_os = "linux")]
+        if coarse_too_coarse() {
+            return precise_ticks();
+        }
+        coarsetime::Instant::now().as_ticks()
+    }
+}
+
+/// The coarse clock follows the kernel timer tick, which is 10ms or more on some virtualized hosts and would make short
+/// durations wildly wrong. A coarser resolution than this switches to the precise clock.
+#[cfg(target_os = "linux")]
+const MAX_COARSE_RESOLUTION_NANOS: i64 = 1_000_000;
+
+#[cfg(target_os = "linux")]
+fn coarse_too_coarse() -> bool {
+    static TOO_COARSE: OnceLock<bool> = OnceLock::new();
+    *TOO_COARSE.get_or_init(|| {
+        // SAFETY: timespec is plain data that clock_getres fills in, and the pointer is valid for the call.
+        let mut res: libc::timespec = unsafe { std::mem::zeroed() };
+        if unsafe { libc::clock_getres(libc::CLOCK_MONOTONIC_COARSE, &mut res) } != 0 {
+            return false;
+        }
+        let too_coarse = res.tv_sec > 0 || res.tv_nsec > MAX_COARSE_RESOLUTION_NANOS;
+        debug!("coarse clock resolution is {}.{:09}s, using the {} clock", res.tv_sec, res.tv_nsec, if too_coarse { "precise" } else { "coarse" });
+        too_coarse
+    })
+}
+
+/// Read the precise monotonic clock. It counts from the same point as the coarse one, so a start recorded with either
+/// can be measured with the other, and the state file holds the same ticks whichever was used.
+#[cfg(target_os = "linux")]
+fn precise_ticks() -> u64 {
+    // SAFETY: timespec is plain data that clock_gettime fills in, and the pointer is valid for the call.
+    let mut now: libc::timespec = unsafe { std::mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    ticks(now.tv_sec as u64, now.tv_nsec as u64)
+}
+
+/// The ticks of coarsetime, with the seconds in the upper 32 bits and the fraction of a second in the lower ones.
+#[cfg(target_os = "linux")]
+fn ticks(secs: u64, nanos: u64) -> u64 {
+    (secs << 32) | ((nanos << 32) / 1_000_000_000)
+}
+
+/// Anything longer than this is not a command that ran but a clock that moved under us.
+const MAX_ELAPSED_SECS: u64 = 366 * 24 * 3600;
+
+/// How long ago the ticks were recorded. The difference wraps so that a counter that overflowed in between still gives
+/// the right answer, and a start in the future, like one recorded with a clock that has since been stepped back,
+/// gives None rather than a bogus duration.
+pub fn elapsed_since(clock: &dyn Clock, ticks: u64) -> Option<Duration> {
+    let now_ticks = clock.now_ticks();
+    debug!("read start time from pid file: {} now={}", ticks, now_ticks);
+    let elapsed = Duration::from_ticks(now_ticks.wrapping_sub(ticks));
+    if elapsed.as_secs() > MAX_ELAPSED_SECS {
+        debug!("ignoring start time that is {}s away", elapsed.as_secs());
+        return None;
+    }
+    Some(elapsed)
+}
+
+fn local_tm(secs: i64) -> Option<libc::tm> {
+    let t = secs as libc::time_t;
+    // SAFETY: tm is plain data that localtime_r fills in, and both pointers are valid for the call.
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&t, &mut tm).is_null() {
+            return None;
+        }
+        Some(tm)
+    }
+}
+
+/// Format the unix time as the local `HH:MM`.
+pub fn local_hour_minute(secs: i64) -> Option<String> {
+    local_tm(secs).map(|tm| format!("{:02}:{:02}", tm.tm_hour, tm.tm_min))
+}
+
+/// Format the unix time as a local ISO 8601 timestamp with seconds and the UTC offset, e.g.
+/// `2024-03-09T14:03:27+01:00`.
+pub fn local_iso8601(secs: i64) -> Option<String> {
+    local_tm(secs).map(|tm| iso8601(&tm))
+}
+
+fn iso8601(tm: &libc::tm) -> String {
+    let offset = tm.tm_gmtoff / 60;
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}",
+        tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec,
+        sign, offset.abs() / 60, offset.abs() % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use super::*;
+    use crate::state::State;
+
+    const SEC: u64 = 1 << 32;
+
+    struct FakeClock(Cell<u64>);
+
+    impl FakeClock {
+        fn advance(&self, secs: u64) {
+            self.0.set(self.0.get().wrapping_add(secs * SEC));
+        }
+
+        fn rewind(&self, secs: u64) {
+            self.0.set(self.0.get().wrapping_sub(secs * SEC));
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now_ticks(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    /// run a command through the same state transitions as ps0 and ps1 and return the elapsed time ps1 shows.
+    fn run(clock: &FakeClock, during: impl FnOnce(&FakeClock)) -> Option<u64> {
+        let st = State::default().started(clock.now_ticks(), Some(1), Some(b"sleep".to_vec()), None);
+        during(clock);
+        st.start_ticks.and_then(|t| elapsed_since(clock, t)).map(|d| d.as_secs())
+    }
+
+    #[test]
+    fn elapsed_examples() {
+        let clock = FakeClock(Cell::new(1000 * SEC));
+        assert_eq!(run(&clock, |_| {}), Some(0));
+        assert_eq!(run(&clock, |c| c.advance(42)), Some(42));
+    }
+
+    /// a laptop suspended overnight with a command running comes back to the whole duration.
+    #[test]
+    fn elapsed_across_suspend() {
+        let clock = FakeClock(Cell::new(1000 * SEC));
+        assert_eq!(run(&clock, |c| c.advance(9 * 3600)), Some(9 * 3600));
+        assert_eq!(run(&clock, |c| c.advance(400 * 24 * 3600)), None);
+    }
+
+    #[test]
+    fn elapsed_across_wraparound() {
+        let clock = FakeClock(Cell::new(u64::MAX - 3 * SEC));
+        assert_eq!(run(&clock, |c| c.advance(10)), Some(10));
+    }
+
+    /// a clock stepped back while the command ran must not show a huge or negative duration.
+    #[test]
+    fn elapsed_with_skew() {
+        let clock = FakeClock(Cell::new(1000 * SEC));
+        assert_eq!(run(&clock, |c| c.rewind(5)), None);
+        assert_eq!(run(&clock, |c| {
+            c.rewind(5);
+            c.advance(8);
+        }), Some(3));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn precise_ticks_match_coarse_ticks() {
+        assert_eq!(ticks(3, 500_000_000), 3 * SEC + SEC / 2);
+        assert_eq!(ticks(0, 999_999_999), SEC - 5);
+        let (coarse, precise) = (coarsetime::Instant::now().as_ticks(), precise_ticks());
+        assert!(precise.abs_diff(coarse) < SEC / 10, "coarse {} and precise {} ticks are apart", coarse, precise);
+    }
+
+    #[test]
+    fn iso8601_examples() {
+        // SAFETY: tm is plain data.
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        (tm.tm_year, tm.tm_mon, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec) = (124, 2, 9, 14, 3, 27);
+        tm.tm_gmtoff = 3600;
+        assert_eq!(iso8601(&tm), "2024-03-09T14:03:27+01:00");
+        tm.tm_gmtoff = -(9 * 3600 + 30 * 60);
+        assert_eq!(iso8601(&tm), "2024-03-09T14:03:27-09:30");
+        tm.tm_gmtoff = 0;
+        assert_eq!(iso8601(&tm), "2024-03-09T14:03:27+00:00");
+    }
+}
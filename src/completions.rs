@@ -0,0 +1,180 @@
+use crate::{import, shell};
+
+/// What the positional argument after a subcommand is.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Argument {
+    None,
+    Shell,
+    /// a shell we have completions for
+    CompletionShell,
+    /// a prompt whose config we can import
+    ImportSource,
+    Directory,
+}
+
+/// Subcommand describes a subcommand, the positional argument it takes, and its flags.
+struct Subcommand {
+    name: &'static str,
+    argument: Argument,
+    flags: &'static [&'static str],
+}
+
+const SUBCOMMANDS: &[Subcommand] = &[
+    Subcommand { name: "init", argument: Argument::Shell, flags: &["--print-full-init"] },
+    Subcommand { name: "ps1", argument: Argument::None, flags: &["--shell=", "--debug-timings", "--minimal", "--history=", "--from-env", "--segment=", "--argv0=", "--shell-flags=", "--color="] },
+    Subcommand { name: "ps2", argument: Argument::None, flags: &["--shell=", "--color="] },
+    Subcommand { name: "ps0", argument: Argument::None, flags: &["--seq="] },
+    Subcommand { name: "read", argument: Argument::None, flags: &[] },
+    Subcommand { name: "warm", argument: Argument::Directory, flags: &["--print-hooks"] },
+    Subcommand { name: "watch", argument: Argument::Directory, flags: &[] },
+    Subcommand { name: "git-info", argument: Argument::Directory, flags: &[] },
+    Subcommand { name: "scan", argument: Argument::Directory, flags: &["--json"] },
+    Subcommand { name: "dirs", argument: Argument::None, flags: &[] },
+    Subcommand { name: "doctor", argument: Argument::None, flags: &[] },
+    Subcommand { name: "import", argument: Argument::ImportSource, flags: &[] },
+    Subcommand { name: "use", argument: Argument::None, flags: &[] },
+    Subcommand { name: "version", argument: Argument::None, flags: &["--json"] },
+    Subcommand { name: "updates", argument: Argument::None, flags: &[] },
+    Subcommand { name: "self-update", argument: Argument::None, flags: &["--check"] },
+    Subcommand { name: "completions", argument: Argument::CompletionShell, flags: &[] },
+];
+
+/// The shells we can write completions for.
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+fn names(argument: Argument) -> String {
+    SUBCOMMANDS.iter().filter(|s| s.argument == argument).map(|s| s.name).collect::<Vec<_>>().join("|")
+}
+
+fn words<'a>(items: impl Iterator<Item = &'a str>) -> String {
+    items.collect::<Vec<_>>().join(" ")
+}
+
+fn shell_names() -> String {
+    words(shell::SHELLS.iter().map(|s| s.name))
+}
+
+/// The completion script for the shell, or None if we don't support it.
+pub fn script(shell: &str) -> Option<String> {
+    match shell {
+        "bash" => Some(bash()),
+        "zsh" => Some(zsh()),
+        "fish" => Some(fish()),
+        _ => None,
+    }
+}
+
+fn bash() -> String {
+    let mut flags = String::new();
+    for s in SUBCOMMANDS.iter().filter(|s| !s.flags.is_empty()) {
+        flags.push_str(&format!("        {}) flags=\"{}\" ;;\n", s.name, s.flags.join(" ")));
+    }
+    format!(r#"_nuprompt() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" flags=""
+    if [[ $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=($(compgen -W "{subcommands} {shells}" -- "$cur"))
+        return
+    fi
+    case "${{COMP_WORDS[1]}}" in
+{flags}    esac
+    if [[ $cur == --* ]]; then
+        COMPREPLY=($(compgen -W "$flags" -- "$cur"))
+        [[ ${{COMPREPLY[0]}} == *= ]] && compopt -o nospace
+        return
+    fi
+    if [[ $COMP_CWORD -eq 2 ]]; then
+        case "${{COMP_WORDS[1]}}" in
+            {shell_commands}) COMPREPLY=($(compgen -W "{shells}" -- "$cur")) ;;
+            {completion_commands}) COMPREPLY=($(compgen -W "{completion_shells}" -- "$cur")) ;;
+            {import_commands}) COMPREPLY=($(compgen -W "{import_sources}" -- "$cur")) ;;
+            {dir_commands}) COMPREPLY=($(compgen -d -- "$cur")) ;;
+        esac
+    fi
+}}
+complete -F _nuprompt nuprompt"#,
+        subcommands = words(SUBCOMMANDS.iter().map(|s| s.name)),
+        shells = shell_names(),
+        flags = flags,
+        shell_commands = names(Argument::Shell),
+        completion_commands = names(Argument::CompletionShell),
+        completion_shells = SHELLS.join(" "),
+        import_commands = names(Argument::ImportSource),
+        import_sources = import::SOURCES.join(" "),
+        dir_commands = names(Argument::Directory))
+}
+
+fn zsh() -> String {
+    let mut flags = String::new();
+    for s in SUBCOMMANDS.iter().filter(|s| !s.flags.is_empty()) {
+        flags.push_str(&format!("        {}) compadd -S '' -- {} ;;\n", s.name, s.flags.join(" ")));
+    }
+    format!(r#"#compdef nuprompt
+_nuprompt() {{
+    if (( CURRENT == 2 )); then
+        compadd -- {subcommands} {shells}
+        return
+    fi
+    if [[ $PREFIX == --* ]]; then
+        case $words[2] in
+{flags}        esac
+        return
+    fi
+    if (( CURRENT == 3 )); then
+        case $words[2] in
+            {shell_commands}) compadd -- {shells} ;;
+            {completion_commands}) compadd -- {completion_shells} ;;
+            {import_commands}) compadd -- {import_sources} ;;
+            {dir_commands}) _files -/ ;;
+        esac
+    fi
+}}
+compdef _nuprompt nuprompt"#,
+        subcommands = words(SUBCOMMANDS.iter().map(|s| s.name)),
+        shells = shell_names(),
+        flags = flags,
+        shell_commands = names(Argument::Shell),
+        completion_commands = names(Argument::CompletionShell),
+        completion_shells = SHELLS.join(" "),
+        import_commands = names(Argument::ImportSource),
+        import_sources = import::SOURCES.join(" "),
+        dir_commands = names(Argument::Directory))
+}
+
+fn fish() -> String {
+    let mut out = String::from("complete -c nuprompt -f\n");
+    out.push_str(&format!("complete -c nuprompt -n __fish_use_subcommand -a '{} {}'\n",
+        words(SUBCOMMANDS.iter().map(|s| s.name)), shell_names()));
+    for s in SUBCOMMANDS {
+        let condition = format!("__fish_seen_subcommand_from {}", s.name);
+        match s.argument {
+            Argument::Shell => out.push_str(&format!("complete -c nuprompt -n '{}' -a '{}'\n", condition, shell_names())),
+            Argument::CompletionShell => out.push_str(&format!("complete -c nuprompt -n '{}' -a '{}'\n", condition, SHELLS.join(" "))),
+            Argument::ImportSource => out.push_str(&format!("complete -c nuprompt -n '{}' -a '{}'\n", condition, import::SOURCES.join(" "))),
+            Argument::Directory => out.push_str(&format!("complete -c nuprompt -n '{}' -a '(__fish_complete_directories)'\n", condition)),
+            Argument::None => {},
+        }
+        for flag in s.flags {
+            let name = flag.trim_start_matches('-');
+            match name.strip_suffix('=') {
+                Some(name) => out.push_str(&format!("complete -c nuprompt -n '{}' -l {} -r\n", condition, name)),
+                None => out.push_str(&format!("complete -c nuprompt -n '{}' -l {}\n", condition, name)),
+            }
+        }
+    }
+    out.trim_end().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every flag we complete must be one the argument parser accepts.
+    #[test]
+    fn completed_flags_are_known() {
+        for s in SUBCOMMANDS {
+            for flag in s.flags {
+                assert!(crate::KNOWN_FLAGS.contains(flag), "{} {} is not a known flag", s.name, flag);
+            }
+        }
+    }
+}
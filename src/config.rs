@@ -0,0 +1,374 @@
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use log::debug;
+use crate::cache;
+
+/// Read a comma separated list of `key=value` pairs from the given environment variable. Entries without an `=` are
+/// skipped and leading or trailing whitespace is trimmed from both keys and values.
+pub fn env_map(name: &str) -> Vec<(String, String)> {
+    match std::env::var_os(name).map(OsString::into_string) {
+        Some(Ok(raw)) => raw.split(',')
+            .filter_map(|entry| match entry.split_once('=') {
+                Some((k, v)) => Some((k.trim().to_owned(), v.trim().to_owned())),
+                None => {
+                    debug!("skipping {} entry without '=': {:?}", name, entry);
+                    None
+                }
+            })
+            .collect(),
+        Some(Err(_)) => {
+            debug!("ignoring non-utf8 value for {}", name);
+            vec![]
+        },
+        None => vec![],
+    }
+}
+
+/// Read a comma separated selection of names from the given environment variable. When the variable is unset,
+/// everything in `all` is selected. An empty value selects nothing.
+pub fn env_selection(name: &str, all: &[&'static str]) -> Vec<&'static str> {
+    match std::env::var(name) {
+        Ok(raw) => {
+            let wanted: Vec<&str> = raw.split(',').map(str::trim).filter(|x| !x.is_empty()).collect();
+            all.iter().copied().filter(|n| wanted.contains(n)).collect()
+        },
+        Err(_) => all.to_vec(),
+    }
+}
+
+/// The path of the config file, which defaults to `$XDG_CONFIG_HOME/nuprompt/config.toml`.
+pub const CONFIG_ENVVAR: &str = "NUPROMPT_CONFIG";
+/// Set to parse the config file again even though it has not changed since the cached parse.
+const RELOAD_ENVVAR: &str = "NUPROMPT_CONFIG_RELOAD";
+
+/// The parsed config files are kept in the cache by path. An entry starts with this line, followed by the path, mtime,
+/// and length of the file it came from.
+const CACHE_NAMESPACE: &str = "config";
+const CACHE_MAGIC: &str = "nuprompt-config-3";
+/// A file copied over with its old mtime and the same length looks unchanged, so even an entry that matches is parsed
+/// again once it is this old.
+const CACHE_TTL: Duration = Duration::from_secs(24 * 3600);
+
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(CONFIG_ENVVAR).filter(|p| !p.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+    let base = std::env::var_os("XDG_CONFIG_HOME").filter(|p| !p.is_empty()).map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").filter(|p| !p.is_empty()).map(|h| PathBuf::from(h).join(".config")))?;
+    Some(base.join("nuprompt").join("config.toml"))
+}
+
+/// Pick a profile for the session. Profiles are `[profile.<name>]` sections of the config file.
+pub const PROFILE_ENVVAR: &str = "NUPROMPT_PROFILE";
+const PROFILE_SECTION: &str = "profile.";
+
+/// Profiles that exist without being in the config file. A config file section of the same name adds to or overrides
+/// their keys.
+const BUILTIN_PROFILES: &[(&str, &[(&str, &str)])] = &[
+    // for terminals whose scrollback ends up in incident reports
+    ("audit", &[("audit", "true")]),
+];
+
+/// Config entries are keys with a value, or None for a key that is set to false.
+type Entries = Vec<(String, Option<String>)>;
+
+/// Apply the config file. Every top level key is the name of a `NUPROMPT_` variable without the prefix, so
+/// `git_counts = true` is `NUPROMPT_GIT_COUNTS=true`, and the keys of other sections are joined to the section name
+/// with underscores, so `side` under `[segment.git]` is `NUPROMPT_SEGMENT_GIT_SIDE`. Variables set in the environment
+/// win over the file, and the overlay for the machine in `config.d` wins over the rest of the file. The keys of the
+/// active profile win over the top level ones, and a false value there turns a top level setting off. The profile comes
+/// from `$NUPROMPT_PROFILE`, then the one picked for the shell session, then the top level `profile` key. This must run
+/// before any other thread starts, since it sets environment variables.
+pub fn load(session_profile: Option<String>) {
+    // a built in profile can still be picked without a config file
+    let entries = match config_path().map(|path| (cached_entries(&path), path)) {
+        Some((Ok(entries), path)) => with_host_overlay(entries.unwrap_or_default(), &path),
+        Some((Err(e), path)) => {
+            debug!("could not load config {:?}: {}", path, e);
+            return;
+        },
+        None => vec![],
+    };
+    let profile = std::env::var(PROFILE_ENVVAR).ok()
+        .or(session_profile)
+        .or_else(|| entries.iter().find(|(k, _)| k == "profile").and_then(|(_, v)| v.clone()))
+        .filter(|p| !p.is_empty());
+    for (key, value) in settings(&entries, profile.as_deref()) {
+        let name = format!("NUPROMPT_{}", key.replace('.', "_").to_ascii_uppercase());
+        if let (None, Some(value)) = (std::env::var_os(&name), value) {
+            std::env::set_var(name, value);
+        }
+    }
+}
+
+/// The overlays for a machine live in `config.d/<hostname>.toml` next to the config file, named by the full hostname or
+/// the part before the first dot, so that the same dotfiles can turn segments on for some machines only.
+const OVERLAY_DIR: &str = "config.d";
+
+/// Lay the overlay for this machine over the entries of the config file, if there is one.
+fn with_host_overlay(entries: Entries, config: &Path) -> Entries {
+    let Some(host) = crate::host::hostname().and_then(|h| h.into_string().ok()) else {
+        return entries;
+    };
+    let dir = config.parent().unwrap_or(Path::new(".")).join(OVERLAY_DIR);
+    let short = host.split('.').next().unwrap_or_default();
+    let names = [host.as_str(), short];
+    // a hostname is not a path, and one that looks like it must not reach outside the dir
+    for name in names.iter().filter(|n| !n.is_empty() && !n.starts_with('.') && !n.contains('/')) {
+        let path = dir.join(format!("{}.toml", name));
+        match cached_entries(&path) {
+            Ok(Some(overlay)) => {
+                debug!("applying config overlay {:?}", path);
+                return merge(entries, overlay);
+            },
+            Ok(None) => {},
+            Err(e) => {
+                debug!("could not load config overlay {:?}: {}", path, e);
+                return entries;
+            },
+        }
+    }
+    entries
+}
+
+/// Lay the overlay entries over the base ones. A key in both takes the value of the overlay, false included, so an
+/// overlay can switch off what the base turns on, and keys only in the overlay are added after the base ones.
+fn merge(base: Entries, overlay: Entries) -> Entries {
+    let mut out = base;
+    for (key, value) in overlay {
+        match out.iter_mut().find(|(k, _)| *k == key) {
+            Some(entry) => entry.1 = value,
+            None => out.push((key, value)),
+        }
+    }
+    out
+}
+
+/// The top level keys with the keys of the profile laid over them.
+fn settings<'a>(entries: &'a Entries, profile: Option<&str>) -> Vec<(&'a str, Option<&'a str>)> {
+    let mut out: Vec<(&str, Option<&str>)> = vec![];
+    if let Some(profile) = profile {
+        let prefix = format!("{}{}.", PROFILE_SECTION, profile);
+        out.extend(entries.iter().filter_map(|(k, v)| Some((k.strip_prefix(&prefix)?, v.as_deref()))));
+        let builtin = BUILTIN_PROFILES.iter().find(|(name, _)| *name == profile).map_or(&[][..], |(_, keys)| keys);
+        for (key, value) in builtin {
+            if !out.iter().any(|(k, _)| k == key) {
+                out.push((*key, Some(*value)));
+            }
+        }
+        if out.is_empty() {
+            debug!("config has no profile named {:?}", profile);
+        }
+    }
+    for (key, value) in entries.iter().filter(|(k, _)| !k.starts_with(PROFILE_SECTION)) {
+        if !out.iter().any(|(k, _)| k == key) {
+            out.push((key, value.as_deref()));
+        }
+    }
+    out
+}
+
+/// The names of the built in profiles and the profiles in the config file.
+pub fn profile_names() -> Vec<String> {
+    let entries = config_path()
+        .and_then(|p| Some(with_host_overlay(cached_entries(&p).ok()?.unwrap_or_default(), &p)))
+        .unwrap_or_default();
+    let mut names: Vec<String> = BUILTIN_PROFILES.iter().map(|(name, _)| name.to_string()).collect();
+    for (key, _) in &entries {
+        if let Some((name, _)) = key.strip_prefix(PROFILE_SECTION).and_then(|k| k.split_once('.')) {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_owned());
+            }
+        }
+    }
+    names
+}
+
+/// The parsed entries of a config file, from the cache when the file has the same mtime and length as when the entry
+/// was written. Returns None when there is no such file.
+fn cached_entries(path: &Path) -> Result<Option<Entries>, anyhow::Error> {
+    crate::fault::check("config")?;
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mtime = meta.modified()?.duration_since(SystemTime::UNIX_EPOCH)?.as_nanos();
+    let header = format!("{}\n{}\n{}\n{}\n", CACHE_MAGIC, path.display(), mtime, meta.len());
+    let key = path.as_os_str().as_bytes();
+    if std::env::var_os(RELOAD_ENVVAR).is_none() {
+        let cached = cache::get(CACHE_NAMESPACE, key, Some(CACHE_TTL)).and_then(|c| String::from_utf8(c).ok());
+        if let Some(body) = cached.as_deref().and_then(|c| c.strip_prefix(&header)) {
+            debug!("using cached config for {:?}", path);
+            return Ok(Some(decode_entries(body)));
+        }
+    }
+    debug!("parsing config {:?}", path);
+    let entries = parse(&fs::read_to_string(path)?);
+    if let Err(e) = cache::put(CACHE_NAMESPACE, key, (header + &encode_entries(&entries)).as_bytes()) {
+        debug!("could not cache config: {}", e);
+    }
+    Ok(Some(entries))
+}
+
+/// Entries are stored as key NUL value NUL, since values may hold newlines. A false key is stored with a leading !,
+/// which can't start a real key.
+fn encode_entries(entries: &[(String, Option<String>)]) -> String {
+    entries.iter().flat_map(|(k, v)| match v {
+        Some(v) => ["", k.as_str(), "\0", v.as_str(), "\0"],
+        None => ["!", k.as_str(), "\0", "", "\0"],
+    }).collect()
+}
+
+fn decode_entries(body: &str) -> Entries {
+    let mut parts = body.split('\0');
+    let mut entries = vec![];
+    while let (Some(k), Some(v)) = (parts.next(), parts.next()) {
+        entries.push(match k.strip_prefix('!') {
+            Some(k) => (k.to_owned(), None),
+            None => (k.to_owned(), Some(v.to_owned())),
+        });
+    }
+    entries
+}
+
+/// Parse the subset of TOML the config uses: `[section]` headers, and `key = value` lines where the value is a basic
+/// or literal string, a boolean, or a bare word such as a number. Keys in a section are returned as `section.key`.
+/// A false value is None, since the variables it maps to are switched on by being set at all. Lines that don't parse
+/// are skipped.
+fn parse(contents: &str) -> Entries {
+    let mut section = String::new();
+    let mut entries = vec![];
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_owned();
+            continue;
+        }
+        let parsed = line.split_once('=')
+            .map(|(k, v)| (k.trim(), v.trim()))
+            .filter(|(k, _)| !k.is_empty() && k.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-'))
+            .and_then(|(k, v)| Some((k, parse_value(v)?)));
+        match parsed {
+            Some((key, value)) if section.is_empty() => entries.push((key.to_owned(), value)),
+            Some((key, value)) => entries.push((format!("{}.{}", section, key), value)),
+            None => debug!("skipping invalid config line {}: {:?}", n + 1, line),
+        }
+    }
+    entries
+}
+
+/// Parse a value, returning Some(None) for false.
+fn parse_value(raw: &str) -> Option<Option<String>> {
+    if let Some(rest) = raw.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = rest.chars();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => out.push(match chars.next()? {
+                    'n' => '\n',
+                    't' => '\t',
+                    c @ ('"' | '\\') => c,
+                    _ => return None,
+                }),
+                c => out.push(c),
+            }
+        }
+        return trailing_comment(chars.as_str()).then_some(Some(out));
+    }
+    if let Some(rest) = raw.strip_prefix('\'') {
+        let (value, rest) = rest.split_once('\'')?;
+        return trailing_comment(rest).then(|| Some(value.to_owned()));
+    }
+    let value = raw.split('#').next().unwrap_or_default().trim();
+    match value {
+        "" => None,
+        "false" => Some(None),
+        v => Some(Some(v.to_owned())),
+    }
+}
+
+/// Whether only whitespace and an optional comment follow a string value.
+fn trailing_comment(rest: &str) -> bool {
+    let rest = rest.trim_start();
+    rest.is_empty() || rest.starts_with('#')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_examples() {
+        let entries = parse(r#"
+# colors and counts
+git_counts = true
+ascii = false
+elapsed_scale = "2,30"   # seconds
+host_rules = '^(\w+)\.corp$=$1'
+notify_secs = 30
+bad line
+"quoted" = 1
+[profile.demo]
+minimal = true
+"#);
+        let expected = [
+            ("git_counts", Some("true")),
+            ("ascii", None),
+            ("elapsed_scale", Some("2,30")),
+            ("host_rules", Some(r"^(\w+)\.corp$=$1")),
+            ("notify_secs", Some("30")),
+            ("profile.demo.minimal", Some("true")),
+        ];
+        let entries: Vec<(&str, Option<&str>)> = entries.iter().map(|(k, v)| (k.as_str(), v.as_deref())).collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn profile_settings() {
+        let entries = parse("git_counts = true\nascii = true\n[profile.demo]\nascii = false\nminimal = true\n");
+        assert_eq!(settings(&entries, None), [("git_counts", Some("true")), ("ascii", Some("true"))]);
+        assert_eq!(settings(&entries, Some("demo")), [("ascii", None), ("minimal", Some("true")), ("git_counts", Some("true"))]);
+        assert_eq!(settings(&entries, Some("missing")), settings(&entries, None));
+        assert_eq!(settings(&entries, Some("audit")), [("audit", Some("true")), ("git_counts", Some("true")), ("ascii", Some("true"))]);
+        let entries = parse("[profile.audit]\naudit = false\nascii = true\n");
+        assert_eq!(settings(&entries, Some("audit")), [("audit", None), ("ascii", Some("true"))]);
+        let entries = parse("[segment.git]\nside = \"right\"\n[profile.demo.segment.git]\nside = \"left\"\n");
+        assert_eq!(settings(&entries, None), [("segment.git.side", Some("right"))]);
+        assert_eq!(settings(&entries, Some("demo")), [("segment.git.side", Some("left"))]);
+    }
+
+    #[test]
+    fn overlay_merge() {
+        let base = parse("git_counts = true\nascii = true\n[segment.git]\nside = \"right\"\n");
+        let overlay = parse("ascii = false\nbattery = true\n[segment.git]\nside = \"left\"\n");
+        let merged = merge(base, overlay);
+        let merged: Vec<(&str, Option<&str>)> = merged.iter().map(|(k, v)| (k.as_str(), v.as_deref())).collect();
+        assert_eq!(merged, [("git_counts", Some("true")), ("ascii", None), ("segment.git.side", Some("left")), ("battery", Some("true"))]);
+    }
+
+    #[test]
+    fn parse_string_escapes() {
+        assert_eq!(parse_value(r#""a\"b\\c\nd""#), Some(Some(String::from("a\"b\\c\nd"))));
+        assert_eq!(parse_value(r#""unterminated"#), None);
+        assert_eq!(parse_value(r#""x" trailing"#), None);
+        assert_eq!(parse_value(r#"'lit\n' # comment"#), Some(Some(String::from(r"lit\n"))));
+    }
+
+    #[test]
+    fn cache_roundtrip() {
+        let entries = vec![
+            (String::from("a"), Some(String::from("multi\nline"))),
+            (String::from("b.c"), Some(String::from(""))),
+            (String::from("profile.demo.ascii"), None),
+        ];
+        assert_eq!(decode_entries(&encode_entries(&entries)), entries);
+    }
+}
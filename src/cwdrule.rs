@@ -0,0 +1,101 @@
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::str::FromStr;
+use log::debug;
+use termcolor::Color;
+
+/// A `;` separated list of `glob=style` rules for the cwd, where the style is a color, a marker, or a color followed by
+/// a space and a marker, e.g. `~/work/**=blue;/etc/**=red ⚠`. The first rule that matches wins.
+pub const CWD_RULES_ENVVAR: &str = "NUPROMPT_CWD_RULES";
+
+/// CwdStyle is how a rule asks for the cwd to be shown.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CwdStyle {
+    pub color: Option<Color>,
+    /// shown before the cwd
+    pub marker: Option<String>,
+}
+
+impl CwdStyle {
+
+    fn parse(raw: &str) -> CwdStyle {
+        let raw = raw.trim();
+        let (first, rest) = raw.split_once(' ').unwrap_or((raw, ""));
+        match Color::from_str(first) {
+            Ok(color) => CwdStyle { color: Some(color), marker: Some(rest.trim().to_owned()).filter(|m| !m.is_empty()) },
+            Err(_) => CwdStyle { color: None, marker: Some(raw.to_owned()).filter(|m| !m.is_empty()) },
+        }
+    }
+
+}
+
+/// The style of the first rule whose glob matches the cwd.
+pub fn style(cwd: &Path) -> Option<CwdStyle> {
+    let rules = std::env::var(CWD_RULES_ENVVAR).ok()?;
+    let home = std::env::var_os("HOME").filter(|h| !h.is_empty());
+    for (pattern, raw_style) in rules.split(';').filter_map(|r| r.split_once('=')) {
+        let pattern = pattern.trim();
+        let expanded = match (pattern.strip_prefix('~'), &home) {
+            (Some(rest), Some(home)) => [home.as_bytes(), rest.as_bytes()].concat(),
+            _ => pattern.as_bytes().to_vec(),
+        };
+        if glob_match(&expanded, cwd.as_os_str().as_bytes()) {
+            debug!("cwd matches rule {:?}", pattern);
+            return Some(CwdStyle::parse(raw_style));
+        }
+    }
+    None
+}
+
+/// Match a path against a glob, component by component. `*` and `?` match within a component and `**` matches any
+/// number of whole components, including none, so `/etc/**` matches `/etc` itself too.
+fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    let pattern: Vec<&[u8]> = pattern.split(|b| *b == b'/').filter(|c| !c.is_empty()).collect();
+    let path: Vec<&[u8]> = path.split(|b| *b == b'/').filter(|c| !c.is_empty()).collect();
+    match_components(&pattern, &path)
+}
+
+fn match_components(pattern: &[&[u8]], path: &[&[u8]]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&b"**", rest)) => (0..=path.len()).any(|skip| match_components(rest, &path[skip..])),
+        Some((first, rest)) => path.split_first().is_some_and(|(p, path)| match_component(first, p) && match_components(rest, path)),
+    }
+}
+
+fn match_component(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_component(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && match_component(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_component(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_match_examples() {
+        assert!(glob_match(b"/etc/**", b"/etc"));
+        assert!(glob_match(b"/etc/**", b"/etc/ssh/sshd_config.d"));
+        assert!(!glob_match(b"/etc/**", b"/etcetera"));
+        assert!(glob_match(b"/home/*/work/**", b"/home/ben/work/api"));
+        assert!(!glob_match(b"/home/*/work/**", b"/home/ben/play/work"));
+        assert!(glob_match(b"/srv/**/logs", b"/srv/a/b/logs"));
+        assert!(glob_match(b"/srv/**/logs", b"/srv/logs"));
+        assert!(glob_match(b"/tmp/build-??", b"/tmp/build-42"));
+        assert!(!glob_match(b"/tmp/build-??", b"/tmp/build-420"));
+        assert!(glob_match(b"/", b"/"));
+        assert!(!glob_match(b"/", b"/tmp"));
+    }
+
+    #[test]
+    fn parse_styles() {
+        assert_eq!(CwdStyle::parse("blue"), CwdStyle { color: Some(Color::Blue), marker: None });
+        assert_eq!(CwdStyle::parse("red ⚠"), CwdStyle { color: Some(Color::Red), marker: Some(String::from("⚠")) });
+        assert_eq!(CwdStyle::parse("208 prod"), CwdStyle { color: Some(Color::Ansi256(208)), marker: Some(String::from("prod")) });
+        assert_eq!(CwdStyle::parse("⚠"), CwdStyle { color: None, marker: Some(String::from("⚠")) });
+    }
+}
@@ -0,0 +1,108 @@
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use log::debug;
+use crate::cache;
+use crate::config::env_selection;
+
+/// The comma separated list of detectors to run. When unset, all detectors run. When empty, none run.
+pub const DETECT_ENVVAR: &str = "NUPROMPT_DETECT";
+
+/// Detector recognises a kind of project by a marker file in the cwd or one of its parents, and describes it.
+pub struct Detector {
+    pub name: &'static str,
+    pub marker: &'static str,
+    /// describe the project given the path to the marker file
+    pub describe: fn(&Path) -> Option<String>,
+}
+
+pub const DETECTORS: &[Detector] = &[
+    Detector { name: "go", marker: "go.mod", describe: describe_go },
+    Detector { name: "rust", marker: "Cargo.toml", describe: describe_rust },
+];
+
+/// Run the enabled detectors from the cwd upwards and return the description from each one that matched.
+pub fn detect(cwd: &Path) -> Vec<String> {
+    let all: Vec<&'static str> = DETECTORS.iter().map(|d| d.name).collect();
+    let enabled = env_selection(DETECT_ENVVAR, &all);
+    DETECTORS.iter()
+        .filter(|d| enabled.contains(&d.name))
+        .filter_map(|d| {
+            let marker = find_upwards(cwd, d.marker)?;
+            debug!("detector {} found {:?}", d.name, marker);
+            (d.describe)(&marker)
+        })
+        .collect()
+}
+
+fn find_upwards(cwd: &Path, marker: &str) -> Option<PathBuf> {
+    cwd.ancestors().map(|a| a.join(marker)).find(|p| p.is_file())
+}
+
+/// Show the go version required by the module, and whether GOFLAGS or GOWORK change how the go command behaves here.
+fn describe_go(go_mod: &Path) -> Option<String> {
+    let contents = fs::read_to_string(go_mod).ok()?;
+    let mut out = String::from("go");
+    if let Some(v) = contents.lines().find_map(|l| l.trim().strip_prefix("go ")) {
+        out.push_str(v.trim());
+    }
+    if std::env::var_os("GOFLAGS").is_some_and(|v| !v.is_empty()) {
+        out.push_str("+flags");
+    }
+    if std::env::var_os("GOWORK").is_some_and(|v| !v.is_empty()) {
+        out.push_str("+work");
+    }
+    Some(out)
+}
+
+/// Show the name of the crate whose manifest is nearest, which in a workspace is the member being edited. A virtual
+/// workspace manifest has no package and shows nothing. The name is remembered along with the mtime of the manifest.
+fn describe_rust(cargo_toml: &Path) -> Option<String> {
+    let mtime = fs::metadata(cargo_toml).and_then(|m| m.modified()).ok()?
+        .duration_since(SystemTime::UNIX_EPOCH).ok()?.as_nanos().to_string();
+    let key = cargo_toml.as_os_str().as_bytes();
+    let cached = cache::get("crate", key, None)
+        .and_then(|c| Some(String::from_utf8(c).ok()?.strip_prefix(&mtime)?.strip_prefix(' ')?.to_owned()));
+    let name = match cached {
+        Some(name) => name,
+        None => {
+            let name = package_name(&fs::read_to_string(cargo_toml).ok()?).unwrap_or_default();
+            if let Err(e) = cache::put("crate", key, format!("{} {}", mtime, name).as_bytes()) {
+                debug!("failed to remember crate name: {}", e);
+            }
+            name
+        },
+    };
+    Some(format!("rust:{}", name)).filter(|_| !name.is_empty())
+}
+
+/// The name in the `[package]` table of a manifest.
+fn package_name(manifest: &str) -> Option<String> {
+    let mut in_package = false;
+    for line in manifest.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_package = line == "[package]";
+            continue;
+        }
+        let value = line.strip_prefix("name").map(str::trim_start).and_then(|l| l.strip_prefix('='));
+        if let (true, Some(value)) = (in_package, value) {
+            let value = value.split('#').next().unwrap_or_default().trim();
+            return Some(value.trim_matches(|c| c == '"' || c == '\'').to_owned());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_name_examples() {
+        assert_eq!(package_name("[package]\nname = \"nuprompt\"\nversion = \"0.4.7\"\n"), Some(String::from("nuprompt")));
+        assert_eq!(package_name("[workspace]\nmembers = [\"a\"]\n[workspace.package]\nname = \"x\"\n"), None);
+        assert_eq!(package_name("[dependencies]\nname = \"1\"\n[package]\nversion = \"1\"\nname='core' # the core\n"), Some(String::from("core")));
+        assert_eq!(package_name("[package]\nnamespace = 3\n"), None);
+    }
+}
@@ -0,0 +1,178 @@
+use std::fs;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::debug;
+
+/// Set to record each directory a prompt is shown in, for `nuprompt dirs` to rank by frecency.
+pub const DIR_HISTORY_ENVVAR: &str = "NUPROMPT_DIR_HISTORY";
+
+/// The least used directories are forgotten beyond this many.
+const MAX_ENTRIES: usize = 1000;
+
+/// Entry is a directory with the number of times it was visited and when the last visit was, in unix seconds.
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    visits: u64,
+    last: u64,
+    path: Vec<u8>,
+}
+
+/// The history lives with the user's data rather than in the state dir, since the temp dir is cleared on reboot.
+fn history_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_DATA_HOME").filter(|p| !p.is_empty()).map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").filter(|p| !p.is_empty()).map(|h| PathBuf::from(h).join(".local/share")))?;
+    Some(base.join("nuprompt").join("dirs"))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Count a visit to the directory. Every prompt shown in the directory of the latest visit is part of that visit, so
+/// the file is only written when the directory changes.
+pub fn record(cwd: &Path) -> Result<(), anyhow::Error> {
+    let path = history_path().ok_or_else(|| anyhow::anyhow!("no home directory for the directory history"))?;
+    let mut entries = fs::read(&path).map(|raw| decode(&raw)).unwrap_or_default();
+    if !visit(&mut entries, cwd.as_os_str().as_bytes(), now()) {
+        return Ok(());
+    }
+    fs::create_dir_all(path.parent().unwrap_or(Path::new("/")))?;
+    let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&tmp, encode(&entries))?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+/// Add the visit and return whether anything changed. The entries are kept in the order of their last visit, so that
+/// two visits in the same second still tell which came last.
+fn visit(entries: &mut Vec<Entry>, path: &[u8], now: u64) -> bool {
+    // a newline would split the entry in two
+    if path.contains(&b'\n') || entries.last().is_some_and(|e| e.path == path) {
+        return false;
+    }
+    let visits = match entries.iter().position(|e| e.path == path) {
+        Some(i) => entries.remove(i).visits + 1,
+        None => 1,
+    };
+    entries.push(Entry { visits, last: now, path: path.to_vec() });
+    if entries.len() > MAX_ENTRIES {
+        let least = (0..entries.len()).min_by_key(|i| frecency(&entries[*i], now)).unwrap_or(0);
+        entries.remove(least);
+    }
+    true
+}
+
+/// The visits weighted by how recent the last one was, the same buckets as z and zoxide use.
+fn frecency(e: &Entry, now: u64) -> u64 {
+    let age = now.saturating_sub(e.last);
+    match age {
+        a if a < 3600 => e.visits * 16,
+        a if a < 86400 => e.visits * 8,
+        a if a < 7 * 86400 => e.visits * 2,
+        _ => e.visits,
+    }
+}
+
+/// One line per directory with the visits, the time of the last one, and the path, which goes last since it can have
+/// spaces.
+fn encode(entries: &[Entry]) -> Vec<u8> {
+    let mut out = vec![];
+    for e in entries {
+        out.extend_from_slice(format!("{} {} ", e.visits, e.last).as_bytes());
+        out.extend_from_slice(&e.path);
+        out.push(b'\n');
+    }
+    out
+}
+
+fn decode(raw: &[u8]) -> Vec<Entry> {
+    raw.split(|b| *b == b'\n').filter_map(|line| {
+        let mut fields = line.splitn(3, |b| *b == b' ');
+        let visits = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+        let last = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+        let path = fields.next().filter(|p| !p.is_empty())?.to_vec();
+        Some(Entry { visits, last, path })
+    }).collect()
+}
+
+/// The directories that match every word of the query, most frecent first.
+fn ranked<'a>(entries: &'a [Entry], query: &[String], now: u64) -> Vec<&'a Entry> {
+    let query: Vec<String> = query.iter().map(|q| q.to_lowercase()).collect();
+    let mut matching: Vec<&Entry> = entries.iter()
+        .filter(|e| {
+            let path = String::from_utf8_lossy(&e.path).to_lowercase();
+            query.iter().all(|q| path.contains(q.as_str()))
+        })
+        .collect();
+    matching.sort_by_key(|e| (std::cmp::Reverse(frecency(e, now)), std::cmp::Reverse(e.last)));
+    matching
+}
+
+/// Print the recorded directories that still exist and match the query, most frecent first, e.g. for
+/// `cd "$(nuprompt dirs proj | head -1)"`.
+pub fn print(query: &[String]) -> Result<(), anyhow::Error> {
+    let Some(path) = history_path() else {
+        return Ok(());
+    };
+    let entries = match fs::read(&path) {
+        Ok(raw) => decode(&raw),
+        Err(e) => {
+            debug!("no directory history at {}: {}", path.display(), e);
+            vec![]
+        },
+    };
+    let mut out = std::io::stdout().lock();
+    for e in ranked(&entries, query, now()) {
+        if Path::new(std::ffi::OsStr::from_bytes(&e.path)).is_dir() {
+            out.write_all(&e.path)?;
+            out.write_all(b"\n")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(visits: u64, last: u64, path: &str) -> Entry {
+        Entry { visits, last, path: path.as_bytes().to_vec() }
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        let entries = vec![entry(3, 1700000000, "/home/ben/my project"), entry(1, 1700000100, "/tmp")];
+        assert_eq!(decode(&encode(&entries)), entries);
+        assert_eq!(decode(b"x 1 /a\n1 2\n2 3 /b\n"), vec![entry(2, 3, "/b")]);
+    }
+
+    #[test]
+    fn visits_are_counted_once_per_change() {
+        let mut entries = vec![];
+        assert!(visit(&mut entries, b"/a", 100));
+        assert!(!visit(&mut entries, b"/a", 200));
+        assert!(visit(&mut entries, b"/b", 300));
+        assert!(visit(&mut entries, b"/a", 400));
+        assert!(!visit(&mut entries, b"/c\n", 500));
+        assert_eq!(entries, vec![entry(1, 300, "/b"), entry(2, 400, "/a")]);
+    }
+
+    #[test]
+    fn ranked_by_frecency() {
+        let now = 100 * 86400;
+        let entries = vec![
+            entry(10, now - 30 * 86400, "/srv/old"),
+            entry(2, now - 60, "/home/ben/Projects/nuprompt"),
+            entry(5, now - 2 * 3600, "/home/ben/projects/other"),
+        ];
+        let paths = |query: &[&str]| -> Vec<String> {
+            let query: Vec<String> = query.iter().map(|q| q.to_string()).collect();
+            ranked(&entries, &query, now).iter().map(|e| String::from_utf8_lossy(&e.path).into_owned()).collect()
+        };
+        assert_eq!(paths(&[]), ["/home/ben/projects/other", "/home/ben/Projects/nuprompt", "/srv/old"]);
+        assert_eq!(paths(&["PROJ", "nu"]), ["/home/ben/Projects/nuprompt"]);
+        assert_eq!(paths(&["nothing"]), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,56 @@
+use log::debug;
+
+/// The comma separated exit codes that are shown dimmed instead of red, as numbers, ranges such as `128-159`, or
+/// signal names such as `SIGINT` for the code of a command killed by that signal.
+pub const QUIET_ENVVAR: &str = "NUPROMPT_QUIET_EXIT_CODES";
+
+const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP), ("INT", libc::SIGINT), ("QUIT", libc::SIGQUIT), ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP), ("ABRT", libc::SIGABRT), ("BUS", libc::SIGBUS), ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL), ("USR1", libc::SIGUSR1), ("SEGV", libc::SIGSEGV), ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE), ("ALRM", libc::SIGALRM), ("TERM", libc::SIGTERM), ("TSTP", libc::SIGTSTP),
+];
+
+/// Whether the exit code is in the list. Shells report a command killed by signal N as 128 + N.
+pub fn is_quiet(exit_code: &str, list: &str) -> bool {
+    let Ok(code) = exit_code.parse::<i32>() else {
+        return false;
+    };
+    list.split(',').map(str::trim).filter(|e| !e.is_empty()).any(|entry| match parse_entry(entry) {
+        Some((low, high)) => (low..=high).contains(&code),
+        None => {
+            debug!("ignoring invalid entry {:?} in {}", entry, QUIET_ENVVAR);
+            false
+        }
+    })
+}
+
+fn parse_entry(entry: &str) -> Option<(i32, i32)> {
+    let upper = entry.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    if let Some((_, signal)) = SIGNALS.iter().find(|(n, _)| *n == name) {
+        return Some((128 + signal, 128 + signal));
+    }
+    match entry.split_once('-') {
+        Some((low, high)) => Some((low.trim().parse().ok()?, high.trim().parse().ok()?)),
+        None => entry.parse().ok().map(|c| (c, c)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_quiet_examples() {
+        let list = "130, SIGPIPE,tstp, 200-210";
+        for code in ["130", "141", "148", "200", "205", "210"] {
+            assert!(is_quiet(code, list), "{}", code);
+        }
+        for code in ["1", "127", "143", "199", "211", "x"] {
+            assert!(!is_quiet(code, list), "{}", code);
+        }
+        assert!(!is_quiet("130", ""));
+        assert!(!is_quiet("130", "nonsense,5-x"));
+    }
+}
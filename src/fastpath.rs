@@ -0,0 +1,102 @@
+use std::io::{ErrorKind, Write};
+use termcolor::{Ansi, ColorChoice, NoColor};
+use crate::render::{self, PromptContext};
+use crate::shell::Shell;
+
+/// Room for any minimal prompt short of a very long path, which falls back to the heap buffer.
+const CAPACITY: usize = 4096;
+
+/// StackBuffer is a fixed size buffer on the stack that refuses writes beyond its capacity.
+struct StackBuffer {
+    bytes: [u8; CAPACITY],
+    len: usize,
+}
+
+impl Write for StackBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let end = self.len + buf.len();
+        if end > CAPACITY {
+            return Err(ErrorKind::WriteZero.into());
+        }
+        self.bytes[self.len..end].copy_from_slice(buf);
+        self.len = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Collect the prompt in a stack buffer and write it to stdout with a single write(2), skipping the termcolor
+/// BufferWriter and its locking. The segments still format their text on the heap as they render, so what this saves
+/// is the buffer and the lock, in the minimal mode path where that overhead is most of the run time. Returns false
+/// without writing anything when the prompt does not fit.
+pub fn print(ctx: &PromptContext, shell: &'static Shell, color: ColorChoice) -> Result<bool, std::io::Error> {
+    let mut buf = StackBuffer { bytes: [0; CAPACITY], len: 0 };
+    if !render_into(ctx, shell, color, &mut buf)? {
+        return Ok(false);
+    }
+    write_stdout(&buf.bytes[..buf.len])?;
+    Ok(true)
+}
+
+fn render_into(ctx: &PromptContext, shell: &'static Shell, color: ColorChoice, buf: &mut StackBuffer) -> Result<bool, std::io::Error> {
+    let rendered = match color {
+        ColorChoice::Never => render::render(ctx, shell, &mut NoColor::new(&mut *buf)),
+        _ => render::render(ctx, shell, &mut Ansi::new(&mut *buf)),
+    };
+    match rendered {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::WriteZero => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Write straight to the stdout fd. Only a pipe that is nearly full splits this into more than one write.
+fn write_stdout(mut bytes: &[u8]) -> Result<(), std::io::Error> {
+    while !bytes.is_empty() {
+        // SAFETY: the pointer and length come from a valid slice.
+        let n = unsafe { libc::write(libc::STDOUT_FILENO, bytes.as_ptr().cast(), bytes.len()) };
+        if n < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        bytes = &bytes[n as usize..];
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+    use coarsetime::Duration;
+    use termcolor::Buffer;
+    use crate::shell::SHELLS;
+
+    /// The stack buffer holds exactly what the heap buffer would, in both color modes.
+    #[test]
+    fn stack_buffer_matches_heap_buffer() {
+        let ctx = PromptContext::new(Some(OsString::from("130")), Some(Duration::from_millis(1490)), Some(PathBuf::from("~/it's/漢字")));
+        for shell in SHELLS {
+            for (color, mut heap) in [(ColorChoice::Always, Buffer::ansi()), (ColorChoice::Never, Buffer::no_color())] {
+                let mut buf = StackBuffer { bytes: [0; CAPACITY], len: 0 };
+                assert!(render_into(&ctx, shell, color, &mut buf).unwrap());
+                render::render(&ctx, shell, &mut heap).unwrap();
+                assert_eq!(&buf.bytes[..buf.len], heap.as_slice(), "{} {:?}", shell.name, color);
+            }
+        }
+    }
+
+    #[test]
+    fn long_prompt_does_not_fit() {
+        let ctx = PromptContext::new(None, None, Some(PathBuf::from("/a".repeat(CAPACITY))));
+        let mut buf = StackBuffer { bytes: [0; CAPACITY], len: 0 };
+        assert!(!render_into(&ctx, SHELLS[0], ColorChoice::Never, &mut buf).unwrap());
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::OnceLock;
+use log::debug;
+
+/// A comma separated list of internal operations to fail on purpose, for the tests that check the prompt still renders
+/// when each of them goes wrong. This is not meant for users and so isn't documented.
+const FAULT_ENVVAR: &str = "NUPROMPT_FAULT";
+
+/// The operations a fault can be injected into. `git_stall` makes the full status scan hang instead of fail.
+pub const OPERATIONS: &[&str] = &["cwd", "config", "state_read", "state_write", "git_open", "git_status", "cache", "hostname", "git_stall"];
+
+fn faults() -> &'static [String] {
+    static FAULTS: OnceLock<Vec<String>> = OnceLock::new();
+    FAULTS.get_or_init(|| {
+        let Some(raw) = std::env::var_os(FAULT_ENVVAR) else {
+            return vec![];
+        };
+        raw.to_string_lossy().split(',').map(str::trim).filter(|f| !f.is_empty()).filter_map(|f| {
+            if !OPERATIONS.contains(&f) {
+                debug!("ignoring unknown fault {:?}", f);
+                return None;
+            }
+            Some(f.to_owned())
+        }).collect()
+    })
+}
+
+/// Whether the operation should fail.
+pub fn injected(operation: &str) -> bool {
+    faults().iter().any(|f| f == operation)
+}
+
+/// An error for the operation when it should fail, for the operations that fail with an io error anyway.
+pub fn check(operation: &str) -> Result<(), std::io::Error> {
+    match injected(operation) {
+        true => Err(std::io::Error::other(format!("injected fault in {}", operation))),
+        false => Ok(()),
+    }
+}
@@ -0,0 +1,55 @@
+use std::path::Path;
+
+/// FsKind describes a file system where git and file watching behave differently from a local disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FsKind {
+    Nfs,
+    Cifs,
+    Fuse,
+    Ecryptfs,
+}
+
+impl FsKind {
+
+    pub fn marker(&self) -> &'static str {
+        match self {
+            FsKind::Nfs => "nfs",
+            FsKind::Cifs => "cifs",
+            FsKind::Fuse => "fuse",
+            FsKind::Ecryptfs => "ecryptfs",
+        }
+    }
+
+    /// whether this is a network file system, where walking the worktree for untracked files is slow.
+    pub fn is_network(&self) -> bool {
+        matches!(self, FsKind::Nfs | FsKind::Cifs)
+    }
+
+}
+
+/// Detect the kind of file system the path is on via statfs(2). Local file systems return None.
+#[cfg(target_os = "linux")]
+pub fn detect(path: &Path) -> Option<FsKind> {
+    use std::os::unix::ffi::OsStrExt;
+    let c = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statfs is plain old data and is fully written by a successful call.
+    let mut st: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: the path is nul-terminated and st is valid for writes.
+    if unsafe { libc::statfs(c.as_ptr(), &mut st) } != 0 {
+        log::debug!("statfs {:?} failed: {}", path, std::io::Error::last_os_error());
+        return None;
+    }
+    // the magic numbers from linux/magic.h and the cifs sources
+    match st.f_type as u32 {
+        0x6969 => Some(FsKind::Nfs),
+        0xFF534D42 | 0xFE534D42 | 0x517B => Some(FsKind::Cifs),
+        0x65735546 => Some(FsKind::Fuse),
+        0xF15F => Some(FsKind::Ecryptfs),
+        _ => None,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn detect(_path: &Path) -> Option<FsKind> {
+    None
+}
@@ -0,0 +1,689 @@
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+use git2::{Repository, RepositoryState, Status, StatusOptions, StatusShow};
+use log::debug;
+use regex_lite::Regex;
+use termcolor::Color;
+use crate::glyph::Glyphs;
+use crate::shell::PromptWriter;
+use crate::signing::Signing;
+use crate::strategy::Strategy;
+use crate::watchdog;
+
+/// Set to open repos owned by other users, which libgit2 refuses by default.
+const TRUST_ALL_ENVVAR: &str = "NUPROMPT_GIT_TRUST_ALL";
+pub const DEFAULT_BRANCH_ENVVAR: &str = "NUPROMPT_DEFAULT_BRANCH";
+/// Set to show the number of changed files instead of single letters.
+pub const COUNTS_ENVVAR: &str = "NUPROMPT_GIT_COUNTS";
+/// Set to also mark files with both staged and unstaged changes, which `git commit` without `-a` only half commits.
+pub const PARTIALLY_STAGED_ENVVAR: &str = "NUPROMPT_GIT_PARTIALLY_STAGED";
+/// Set to show in a linked worktree whether the main worktree has staged changes.
+pub const MAIN_WORKTREE_ENVVAR: &str = "NUPROMPT_MAIN_WORKTREE";
+
+/// A `;` separated list of `regex=color` rules for the branch name, where the first rule that matches picks the color.
+/// There are no rules unless it is set, so the branch keeps its usual color.
+pub const BRANCH_COLORS_ENVVAR: &str = "NUPROMPT_BRANCH_COLORS";
+
+/// The cached form of a status holds this version, the staged, modified, untracked, and partially staged counts as big
+/// endian u32s, a byte of flags for a partial scan, and then the head ref.
+const CACHE_VERSION: u8 = 4;
+
+/// Counts above this are shown as e.g. `?99+`.
+const COUNT_CAP: usize = 99;
+
+/// GitBits holds the result of scanning the git repo for current status.
+#[derive(Default)]
+pub struct GitBits {
+    pub head_ref: Vec<u8>,
+    /// the number of files with staged, unstaged, and untracked changes
+    pub index_modified: usize,
+    pub worktree_modified: usize,
+    pub untracked_files: usize,
+    /// the number of files that are counted as both staged and unstaged
+    pub partially_staged: usize,
+    /// set when the strategy skipped part of the status, so the flags above may be incomplete
+    pub partial: bool,
+    /// set when only HEAD was compared with the index, so nothing at all is known about the worktree
+    pub worktree_unknown: bool,
+    /// the shorthand of the default branch and how many commits HEAD is ahead of it
+    pub default_branch_ahead: Option<(Vec<u8>, usize)>,
+    pub signing: Option<Signing>,
+    /// the address commits get when it is not the global one, or its alias
+    pub email: Option<String>,
+    /// a description of the operation in progress, such as a merge or bisect
+    pub operation: Option<String>,
+    /// how long the operation has been in progress, once that is long enough to deserve a nudge
+    pub operation_age: Option<Duration>,
+    /// set when only part of the tree is checked out, or objects are fetched on demand
+    pub sparse_checkout: bool,
+    pub partial_clone: bool,
+    /// set when the history was cut off by a shallow clone, which changes what describe, blame, and pushes can do
+    pub shallow: bool,
+    /// set in a linked worktree when the main worktree has staged changes
+    pub main_worktree_dirty: bool,
+    /// the number of local branches with commits that are not on their upstream
+    pub unpushed_branches: usize,
+    /// set when the HEAD commit is not on any remote-tracking ref
+    pub head_unpushed: bool,
+    /// the name of a lock file that has been left behind, such as index.lock
+    pub stale_lock: Option<&'static str>,
+}
+
+impl GitBits {
+
+    /// scan the repo status. Skipping untracked files avoids walking the whole worktree, which matters on slow file
+    /// systems, and the cheaper strategies skip the worktree or the whole scan for large repos.
+    pub fn from_repo(r: &Repository, include_untracked: bool, strategy: Strategy) -> Result<GitBits, anyhow::Error> {
+        let short_ref = r.head()
+            .map(|h| h.shorthand_bytes().to_vec())
+            .unwrap_or_else(|e| {
+                debug!("error reading head ref: {}", e);
+               b"NO HEAD".to_vec()
+            });
+        let mut gb = GitBits{
+            head_ref: short_ref,
+            partial: strategy != Strategy::Full,
+            worktree_unknown: strategy == Strategy::IndexOnly,
+            ..GitBits::default()
+        };
+        if strategy == Strategy::HeadOnly {
+            return Ok(gb);
+        }
+        let status_options = || {
+            let mut opts = StatusOptions::new();
+            opts.include_ignored(false)
+                .include_untracked(include_untracked)
+                .exclude_submodules(true)
+                .include_unreadable(false);
+            if strategy == Strategy::IndexOnly {
+                opts.show(StatusShow::Index).include_untracked(false).no_refresh(true);
+            }
+            opts
+        };
+        let mut opts = status_options();
+        let sparse = sparse_checkout(r);
+        if sparse {
+            for pathspec in sparse_pathspecs(r) {
+                opts.pathspec(pathspec);
+            }
+        }
+        let threads = status_threads();
+        let counts = if strategy == Strategy::Full && !sparse && threads > 1 {
+            let (counts, complete) = parallel_counts(r, threads, status_options)?;
+            if !complete {
+                debug!("out of time for the status scan, the counts are incomplete");
+                gb.partial = true;
+            }
+            counts
+        } else {
+            let index = if sparse { Some(r.index()?) } else { None };
+            count_statuses(r, &mut opts, index.as_ref())?
+        };
+        (gb.index_modified, gb.worktree_modified, gb.untracked_files, gb.partially_staged) = counts;
+        Ok(gb)
+    }
+
+    /// write everything after the branch name. With counts, the changes are shown as `?3 ~7 +2` for untracked,
+    /// modified, and staged files instead of `:sdu`. With partially_staged, the files that are both get a `p` or a
+    /// count of their own too. A worktree that wasn't looked at is a `?` in place of the modified files, `~?` with
+    /// counts.
+    pub fn write_elements(&self, w: &mut PromptWriter, counts: bool, partially_staged: bool, glyphs: &Glyphs) -> Result<(), std::io::Error> {
+        let partially_staged = if partially_staged { self.partially_staged } else { 0 };
+        if counts {
+            for (prefix, n) in [("?", self.untracked_files), ("~", self.worktree_modified), ("+", self.index_modified), (glyphs.plus_minus, partially_staged)] {
+                match n {
+                    0 => {},
+                    n if n > COUNT_CAP => write!(w, " {}{}+", prefix, COUNT_CAP)?,
+                    n => write!(w, " {}{}", prefix, n)?,
+                }
+            }
+            if self.worktree_unknown {
+                w.write_all(b" ~?")?;
+            }
+        } else if self.index_modified > 0 || self.worktree_modified > 0 || self.untracked_files > 0 || self.worktree_unknown {
+            w.write_all(b":")?;
+            if self.index_modified > 0 {
+                w.write_all(b"s")?;
+            }
+            if self.worktree_modified > 0 {
+                w.write_all(b"d")?;
+            }
+            if self.untracked_files > 0 {
+                w.write_all(b"u")?;
+            }
+            if partially_staged > 0 {
+                w.write_all(b"p")?;
+            }
+            if self.worktree_unknown {
+                w.write_all(b"?")?;
+            }
+        }
+        // the unknown worktree already says the status is partial
+        if self.partial && !self.worktree_unknown {
+            w.write_all(glyphs.partial.as_bytes())?;
+        }
+        if let Some((branch, ahead)) = &self.default_branch_ahead {
+            w.write_all(b" ")?;
+            w.write_literal(branch)?;
+            write!(w, "+{}", ahead)?;
+        }
+        if let Some(signing) = self.signing {
+            write!(w, " {}", signing.marker())?;
+        }
+        if let Some(email) = &self.email {
+            w.write_all(b" <")?;
+            w.write_literal(email.as_bytes())?;
+            w.write_all(b">")?;
+        }
+        if let Some(operation) = &self.operation {
+            write!(w, " {}", operation)?;
+            if let Some(age) = self.operation_age {
+                write!(w, " for {}", compact_age(age))?;
+            }
+        }
+        if self.sparse_checkout {
+            write!(w, " {}sparse", glyphs.notice)?;
+        }
+        if self.partial_clone {
+            write!(w, " {}partial", glyphs.notice)?;
+        }
+        if self.shallow {
+            write!(w, " {}shallow", glyphs.notice)?;
+        }
+        if self.unpushed_branches > 0 {
+            write!(w, " {}{} unpushed", glyphs.unpushed, self.unpushed_branches)?;
+        }
+        if let Some(lock) = self.stale_lock {
+            write!(w, " {}{}", glyphs.notice, lock)?;
+        }
+        if self.head_unpushed {
+            write!(w, " {} unpushed", glyphs.cross)?;
+        }
+        if self.main_worktree_dirty {
+            w.write_all(b" main-wt:s")?;
+        }
+        Ok(())
+    }
+
+    /// The counts, the flags of a partial scan, and the head ref, for the watcher and warm caches to hand to a prompt.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![CACHE_VERSION];
+        for n in [self.index_modified, self.worktree_modified, self.untracked_files, self.partially_staged] {
+            out.extend_from_slice(&(n.min(u32::MAX as usize) as u32).to_be_bytes());
+        }
+        out.push(u8::from(self.partial) | u8::from(self.worktree_unknown) << 1);
+        out.extend_from_slice(&self.head_ref);
+        out
+    }
+
+    pub fn decode(contents: &[u8]) -> Option<GitBits> {
+        let [CACHE_VERSION, rest @ ..] = contents else {
+            return None;
+        };
+        let (counts, rest) = rest.split_first_chunk::<16>()?;
+        let (flags, head_ref) = rest.split_first()?;
+        let count = |i: usize| u32::from_be_bytes([counts[i], counts[i + 1], counts[i + 2], counts[i + 3]]) as usize;
+        Some(GitBits {
+            head_ref: head_ref.to_vec(),
+            index_modified: count(0),
+            worktree_modified: count(4),
+            untracked_files: count(8),
+            partially_staged: count(12),
+            partial: flags & 1 != 0,
+            worktree_unknown: flags & 2 != 0,
+            ..GitBits::default()
+        })
+    }
+
+}
+
+/// Open the repo containing the path.
+pub fn open(path: impl AsRef<Path>) -> Result<Repository, git2::Error> {
+    if crate::fault::injected("git_open") {
+        return Err(git2::Error::from_str("injected fault in git_open"));
+    }
+    Repository::open_ext(path, git2::RepositoryOpenFlags::empty(), &[] as &[PathBuf])
+}
+
+/// Turn the owner check of libgit2 off when repos owned by other users should be opened anyway. The check is a global
+/// option of libgit2, so this is done once for the whole process, and [`foreign`] tells such repos apart afterwards.
+pub fn trust_all_if_configured() {
+    if std::env::var_os(TRUST_ALL_ENVVAR).is_none() {
+        return;
+    }
+    // SAFETY: main calls this once on its own thread before it spawns any other thread or opens any repo, so nothing
+    // can be using libgit2 while the option changes.
+    if let Err(e) = unsafe { git2::opts::set_verify_owner_validation(false) } {
+        debug!("could not disable the repository owner check: {}", e);
+    }
+}
+
+/// Whether the git dir or worktree of the repo belongs to another user, which means it was only opened through
+/// `$NUPROMPT_GIT_TRUST_ALL`. Such a repo is strictly read: its index is never refreshed or written.
+pub fn foreign(r: &Repository) -> bool {
+    foreign_to(r, users::get_effective_uid())
+}
+
+fn foreign_to(r: &Repository, uid: u32) -> bool {
+    [Some(r.path()), r.workdir()].into_iter().flatten().any(|p| std::fs::metadata(p).is_ok_and(|m| m.uid() != uid))
+}
+
+/// Whether the worktree only has part of the tree checked out.
+pub fn sparse_checkout(r: &Repository) -> bool {
+    r.config().and_then(|c| c.get_bool("core.sparseCheckout")).unwrap_or(false)
+}
+
+/// Whether the repo was cloned with a filter, so that missing objects are fetched from a promisor remote on demand.
+pub fn partial_clone(r: &Repository) -> bool {
+    let Ok(config) = r.config() else {
+        return false;
+    };
+    if config.get_string("extensions.partialClone").is_ok() {
+        return true;
+    }
+    let Ok(mut entries) = config.entries(Some(r"remote\..*\.promisor")) else {
+        return false;
+    };
+    let mut promisor = false;
+    while let Some(Ok(entry)) = entries.next() {
+        promisor |= entry.value().is_some_and(|v| v == "true");
+    }
+    promisor
+}
+
+/// Whether the repo is a shallow clone, which git marks with a non-empty shallow file in the git dir. This is only a
+/// stat, so it is cheap enough to check on every prompt.
+pub fn shallow(r: &Repository) -> bool {
+    r.is_shallow()
+}
+
+/// Turn the cone mode patterns of info/sparse-checkout into status pathspecs, so that the scan skips directories
+/// that are still on disk but outside the cone. Cone mode always includes the files at the top level, which are
+/// added one by one from the index. Patterns that are not cone mode can't be expressed as pathspecs, so those return
+/// nothing and the whole worktree is scanned.
+fn sparse_pathspecs(r: &Repository) -> Vec<Vec<u8>> {
+    if !r.config().and_then(|c| c.get_bool("core.sparseCheckoutCone")).unwrap_or(false) {
+        return vec![];
+    }
+    let Ok(patterns) = std::fs::read(r.path().join("info").join("sparse-checkout")) else {
+        return vec![];
+    };
+    let mut pathspecs: Vec<Vec<u8>> = patterns.split(|b| *b == b'\n')
+        .filter(|p| !p.starts_with(b"!") && !p.starts_with(b"#") && *p != b"/*")
+        .filter_map(|p| p.strip_prefix(b"/")?.strip_suffix(b"/"))
+        .filter(|p| !p.is_empty())
+        .map(<[u8]>::to_vec)
+        .collect();
+    if pathspecs.is_empty() {
+        return vec![];
+    }
+    if let Ok(index) = r.index() {
+        pathspecs.extend(index.iter().map(|e| e.path).filter(|p| !p.contains(&b'/')));
+    }
+    debug!("restricting status to {} sparse pathspecs", pathspecs.len());
+    pathspecs
+}
+
+/// Whether the index marks the path as intentionally absent from the worktree.
+fn skip_worktree(index: &git2::Index, path: &[u8]) -> bool {
+    const SKIP_WORKTREE: u16 = 1 << 14;
+    index.get_path(Path::new(std::ffi::OsStr::from_bytes(path)), 0)
+        .is_some_and(|e| e.flags_extended & SKIP_WORKTREE != 0)
+}
+
+/// The number of threads for the full status scan, a number or `auto` for one per CPU. Hashing the worktree files
+/// is CPU bound on fast disks, so large repos on many cores scan faster when the top level entries are split up.
+const THREADS_ENVVAR: &str = "NUPROMPT_GIT_THREADS";
+
+fn status_threads() -> usize {
+    match std::env::var(THREADS_ENVVAR).as_deref().map(str::trim) {
+        Ok("auto") => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        Ok(n) => n.parse().unwrap_or_else(|_| {
+            debug!("ignoring invalid {}: {:?}", THREADS_ENVVAR, n);
+            1
+        }),
+        Err(_) => 1,
+    }
+}
+
+/// The staged, modified, untracked, and partially staged files.
+type Counts = (usize, usize, usize, usize);
+
+/// Count the staged, modified, and untracked files in the statuses, and those that are both staged and modified,
+/// skipping files outside a sparse checkout when the index is given.
+fn count_statuses(r: &Repository, opts: &mut StatusOptions, sparse_index: Option<&git2::Index>) -> Result<Counts, git2::Error> {
+    let statuses = r.statuses(Some(opts))?;
+    let wt_modified: Status = Status::WT_MODIFIED | Status::WT_DELETED | Status::WT_TYPECHANGE | Status::WT_RENAMED;
+    let index_modified: Status = Status::INDEX_NEW | Status::INDEX_MODIFIED | Status::INDEX_TYPECHANGE | Status::INDEX_RENAMED | Status::INDEX_DELETED;
+    let (mut staged, mut modified, mut untracked, mut partially_staged) = (0, 0, 0, 0);
+    for x in statuses.iter() {
+        debug!("git status {:?}: {:?}", x.path(), x.status());
+        let st = x.status();
+        // libgit2 doesn't know about sparse checkouts, so the files outside the patterns look deleted
+        if st == Status::WT_DELETED && sparse_index.is_some_and(|i| skip_worktree(i, x.path_bytes())) {
+            continue;
+        }
+        if st.intersects(wt_modified) {
+            modified += 1;
+        }
+        if st.intersects(index_modified) {
+            staged += 1;
+        }
+        if st.intersects(wt_modified) && st.intersects(index_modified) {
+            partially_staged += 1;
+        }
+        if st.contains(Status::WT_NEW) {
+            untracked += 1;
+        }
+    }
+    Ok((staged, modified, untracked, partially_staged))
+}
+
+/// Split the status scan over threads by top level entry, which each become the pathspec of a scan. The entries come
+/// from the worktree, the index, and the HEAD tree, so that deleted files are counted too. Each thread opens its own
+/// handle since a repository can't be shared, and takes the next entry until there are none left or the prompt is out
+/// of time. The counts are returned with whether every entry was scanned.
+fn parallel_counts(r: &Repository, threads: usize, status_options: impl Fn() -> StatusOptions + Sync) -> Result<(Counts, bool), anyhow::Error> {
+    let workdir = r.workdir().ok_or_else(|| anyhow::anyhow!("repository has no working directory"))?;
+    let mut names: Vec<Vec<u8>> = std::fs::read_dir(workdir)?
+        .filter_map(|e| e.ok().map(|e| e.file_name().as_bytes().to_vec()))
+        .filter(|n| n != b".git")
+        .collect();
+    names.extend(r.index()?.iter().map(|e| e.path.split(|b| *b == b'/').next().unwrap_or_default().to_vec()));
+    if let Ok(tree) = r.head().and_then(|h| h.peel_to_tree()) {
+        names.extend(tree.iter().map(|e| e.name_bytes().to_vec()));
+    }
+    names.sort_unstable();
+    names.dedup();
+    // a name with glob characters would match other entries too, and libgit2 fails to match escaped ones
+    if names.iter().any(|n| n.iter().any(|b| matches!(b, b'*' | b'?' | b'[' | b'\\'))) {
+        debug!("top level entries have glob characters, scanning serially");
+        return Ok((count_statuses(r, &mut status_options(), None)?, true));
+    }
+    let threads = threads.min(names.len()).max(1);
+    debug!("scanning {} top level entries with {} threads", names.len(), threads);
+    let (next, cut_short) = (AtomicUsize::new(0), AtomicBool::new(false));
+    let counts = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads).map(|_| {
+            let (status_options, names, next, cut_short) = (&status_options, &names, &next, &cut_short);
+            scope.spawn(move || -> Result<Counts, git2::Error> {
+                let r = Repository::open(workdir)?;
+                let mut counts = (0, 0, 0, 0);
+                while let Some(name) = names.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    if watchdog::expired() {
+                        cut_short.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                    let mut opts = status_options();
+                    opts.pathspec(name);
+                    let (s, m, u, p) = count_statuses(&r, &mut opts, None)?;
+                    counts = (counts.0 + s, counts.1 + m, counts.2 + u, counts.3 + p);
+                }
+                Ok(counts)
+            })
+        }).collect();
+        handles.into_iter().try_fold((0, 0, 0, 0), |(s, m, u, p), h| {
+            let (ts, tm, tu, tp) = h.join().map_err(|_| anyhow::anyhow!("status thread panicked"))??;
+            Ok::<_, anyhow::Error>((s + ts, m + tm, u + tu, p + tp))
+        })
+    })?;
+    Ok((counts, !cut_short.into_inner()))
+}
+
+/// Lock files older than this were most likely left behind by a git command that crashed or was killed.
+const STALE_LOCK_AGE: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// The name of a lock file in the git dir that is older than a few seconds. A left behind index.lock or HEAD.lock
+/// makes every later git command that writes fail until it is removed.
+pub fn stale_lock(r: &Repository) -> Option<&'static str> {
+    ["index.lock", "HEAD.lock"].into_iter().find(|name| {
+        std::fs::metadata(r.path().join(name))
+            .and_then(|m| m.modified())
+            .is_ok_and(|t| t.elapsed().is_ok_and(|age| age > STALE_LOCK_AGE))
+    })
+}
+
+/// Whether the main worktree of a linked worktree has staged changes. This only compares its HEAD with its index,
+/// which stays cheap however large the other worktree is. Returns false outside a linked worktree.
+pub fn main_worktree_dirty(r: &Repository) -> bool {
+    if !r.is_worktree() {
+        return false;
+    }
+    // the git dir of a linked worktree names the shared git dir of the main worktree, usually as ../..
+    let Ok(commondir) = std::fs::read_to_string(r.path().join("commondir")) else {
+        return false;
+    };
+    let main = match Repository::open(r.path().join(commondir.trim_end())) {
+        Ok(main) if main.workdir().is_some() => main,
+        Ok(_) => return false,
+        Err(e) => {
+            debug!("could not open main worktree: {}", e);
+            return false;
+        }
+    };
+    GitBits::from_repo(&main, false, Strategy::IndexOnly)
+        .inspect_err(|e| debug!("could not read main worktree status: {}", e))
+        .is_ok_and(|gb| gb.index_modified > 0)
+}
+
+/// Count the commits on HEAD that are not on the default branch. The default branch is the given ref name, or when that
+/// is empty, whatever refs/remotes/origin/HEAD points at, falling back to origin/main. Returns None when HEAD is the
+/// default branch or is not ahead of it.
+pub fn default_branch_ahead(r: &Repository, configured: &str) -> Option<(Vec<u8>, usize)> {
+    let reference = if configured.is_empty() {
+        r.find_reference("refs/remotes/origin/HEAD")
+            .and_then(|h| h.resolve())
+            .or_else(|_| r.resolve_reference_from_short_name("origin/main"))
+    } else {
+        r.resolve_reference_from_short_name(configured)
+    };
+    let reference = reference
+        .inspect_err(|e| debug!("could not resolve default branch: {}", e))
+        .ok()?;
+    let default_oid = reference.target()?;
+    let head_oid = r.head().ok()?.target()?;
+    if head_oid == default_oid {
+        return None;
+    }
+    let (ahead, _) = r.graph_ahead_behind(head_oid, default_oid)
+        .inspect_err(|e| debug!("could not compare with default branch: {}", e))
+        .ok()?;
+    let name = reference.shorthand_bytes().to_vec();
+    Some((name, ahead)).filter(|(_, a)| *a > 0)
+}
+
+/// Describe the operation in progress in the repo, if any. An interrupted merge, rebase, or bisect is easy to forget
+/// about and leaves the repo in a confusing state.
+pub fn operation(r: &Repository) -> Option<String> {
+    let label = match r.state() {
+        RepositoryState::Clean => return None,
+        RepositoryState::Merge => "MERGING",
+        RepositoryState::Revert | RepositoryState::RevertSequence => "REVERTING",
+        RepositoryState::CherryPick | RepositoryState::CherryPickSequence => "CHERRY-PICKING",
+        RepositoryState::Bisect => return Some(match bisect_steps_left(r) {
+            Some(steps) => format!("BISECTING (~{} steps left)", steps),
+            None => String::from("BISECTING"),
+        }),
+        RepositoryState::Rebase | RepositoryState::RebaseInteractive | RepositoryState::RebaseMerge => "REBASING",
+        RepositoryState::ApplyMailbox | RepositoryState::ApplyMailboxOrRebase => "AM",
+    };
+    Some(label.to_owned())
+}
+
+/// Operations in progress for at least this many seconds show how long they have been going on for. The default is
+/// an hour.
+const OPERATION_AGE_ENVVAR: &str = "NUPROMPT_OPERATION_AGE_SECS";
+const DEFAULT_OPERATION_AGE: Duration = Duration::from_secs(3600);
+
+/// How long the operation in progress has been going on for, from the file in the git dir that it starts by
+/// writing. Returns None when there is no operation or it is younger than the threshold.
+pub fn operation_age(r: &Repository) -> Option<Duration> {
+    let started_by: &[&str] = match r.state() {
+        RepositoryState::Clean => return None,
+        RepositoryState::Merge => &["MERGE_HEAD"],
+        RepositoryState::Revert | RepositoryState::RevertSequence => &["REVERT_HEAD", "sequencer"],
+        RepositoryState::CherryPick | RepositoryState::CherryPickSequence => &["CHERRY_PICK_HEAD", "sequencer"],
+        RepositoryState::Bisect => &["BISECT_START"],
+        // the rebase dirs are rewritten at every step, but the head name is written once at the start
+        RepositoryState::Rebase | RepositoryState::RebaseInteractive | RepositoryState::RebaseMerge =>
+            &["rebase-merge/head-name", "rebase-apply/head-name", "rebase-merge", "rebase-apply"],
+        RepositoryState::ApplyMailbox | RepositoryState::ApplyMailboxOrRebase => &["rebase-apply"],
+    };
+    let threshold = std::env::var(OPERATION_AGE_ENVVAR).ok()
+        .and_then(|v| v.trim().parse().ok())
+        .map_or(DEFAULT_OPERATION_AGE, Duration::from_secs);
+    let age = started_by.iter()
+        .find_map(|name| std::fs::metadata(r.path().join(name)).and_then(|m| m.modified()).ok())?
+        .elapsed().ok()?;
+    Some(age).filter(|a| *a >= threshold)
+}
+
+/// The age in its largest whole unit, e.g. `3d`, `5h`, or `12m`.
+fn compact_age(age: Duration) -> String {
+    match age.as_secs() {
+        s if s >= 86400 => format!("{}d", s / 86400),
+        s if s >= 3600 => format!("{}h", s / 3600),
+        s => format!("{}m", s / 60),
+    }
+}
+
+/// Estimate the remaining bisect steps from the commits between the bad ref and the good refs, the same way git
+/// itself does. Returns None until both a bad and a good commit have been marked.
+fn bisect_steps_left(r: &Repository) -> Option<u32> {
+    let bad = r.refname_to_id("refs/bisect/bad").ok()?;
+    let mut walk = r.revwalk().ok()?;
+    walk.push(bad).ok()?;
+    let mut any_good = false;
+    for reference in r.references_glob("refs/bisect/good-*").ok()?.flatten() {
+        if let Some(oid) = reference.target() {
+            walk.hide(oid).ok()?;
+            any_good = true;
+        }
+    }
+    if !any_good {
+        return None;
+    }
+    let remaining = walk.count() as u32;
+    debug!("bisect has {} candidate commits", remaining);
+    Some(estimate_bisect_steps(remaining))
+}
+
+/// This mirrors estimate_bisect_steps in git's bisect.c.
+fn estimate_bisect_steps(all: u32) -> u32 {
+    if all < 3 {
+        return 0;
+    }
+    let n = all.ilog2();
+    let e = 1 << n;
+    let x = all - e;
+    if e < 3 * x { n } else { n - 1 }
+}
+
+/// The color of the first branch color rule that matches the branch, if any.
+pub fn branch_color(head_ref: &[u8]) -> Option<Color> {
+    let rules = std::env::var(BRANCH_COLORS_ENVVAR).ok()?;
+    match_branch_rules(&rules, &String::from_utf8_lossy(head_ref))
+}
+
+/// The regex may contain `=` itself, so the color is whatever follows the last one.
+fn match_branch_rules(rules: &str, branch: &str) -> Option<Color> {
+    for (pattern, color) in rules.split(';').filter_map(|r| r.rsplit_once('=')) {
+        match Regex::new(pattern.trim()) {
+            Ok(re) if re.is_match(branch) => match Color::from_str(color.trim()) {
+                Ok(color) => return Some(color),
+                Err(e) => debug!("invalid color in branch rule {:?}: {}", pattern, e),
+            },
+            Ok(_) => {},
+            Err(e) => debug!("invalid branch rule {:?}: {}", pattern, e),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn branch_rule_examples() {
+        let rules = "^(main|master)$=red;^release/=magenta";
+        assert_eq!(match_branch_rules(rules, "main"), Some(Color::Red));
+        assert_eq!(match_branch_rules(rules, "mainline"), None);
+        assert_eq!(match_branch_rules(rules, "release/1.2"), Some(Color::Magenta));
+        assert_eq!(match_branch_rules(rules, "feature/x"), None);
+        assert_eq!(match_branch_rules("^x{1,2}=y$=214; ^f=bogus; .=green", "xx=y"), Some(Color::Ansi256(214)));
+        assert_eq!(match_branch_rules("^x{1,2}=y$=214; ^f=bogus; .=green", "fix"), Some(Color::Green));
+        assert_eq!(match_branch_rules("", "main"), None);
+    }
+
+    #[test]
+    fn compact_age_examples() {
+        assert_eq!(compact_age(Duration::from_secs(59)), "0m");
+        assert_eq!(compact_age(Duration::from_secs(12 * 60 + 5)), "12m");
+        assert_eq!(compact_age(Duration::from_secs(5 * 3600 + 59 * 60)), "5h");
+        assert_eq!(compact_age(Duration::from_secs(3 * 86400 + 23 * 3600)), "3d");
+    }
+
+    #[test]
+    fn foreign_repos() {
+        let dir = std::env::temp_dir().join(format!("nuprompt-git-foreign-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let r = Repository::init(&dir).unwrap();
+        let uid = users::get_effective_uid();
+        assert!(!foreign_to(&r, uid));
+        assert!(foreign_to(&r, uid.wrapping_add(1)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Splitting the scan over threads counts every kind of change exactly once, including deleted directories that
+    /// only the index and HEAD know about.
+    #[test]
+    fn parallel_counts_match_serial() {
+        let dir = std::env::temp_dir().join(format!("nuprompt-git-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let r = Repository::init(&dir).unwrap();
+        let write = |path: &str, contents: &str| {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        for path in ["a/b/one", "a/two", "c/three", "d/four", "five", "six"] {
+            write(path, path);
+        }
+        let mut index = r.index().unwrap();
+        index.add_all(["*"], git2::IndexAddOption::DEFAULT, None).unwrap();
+        index.write().unwrap();
+        let tree = r.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        r.commit(Some("HEAD"), &sig, &sig, "initial", &tree, &[]).unwrap();
+
+        write("a/b/one", "changed");
+        write("six", "changed");
+        write("five", "staged");
+        index.add_path(Path::new("five")).unwrap();
+        write("five", "staged and changed");
+        std::fs::remove_dir_all(dir.join("c")).unwrap();
+        index.remove_dir(Path::new("d"), 0).unwrap();
+        write("seven", "new");
+        index.add_path(Path::new("seven")).unwrap();
+        index.write().unwrap();
+        write("new/eight", "untracked");
+
+        let options = || {
+            let mut opts = StatusOptions::new();
+            opts.include_untracked(true).exclude_submodules(true);
+            opts
+        };
+        let serial = count_statuses(&r, &mut options(), None).unwrap();
+        assert_eq!(serial, (3, 4, 2, 1));
+        for threads in [2, 3, 16] {
+            assert_eq!(parallel_counts(&r, threads, options).unwrap(), (serial, true), "with {} threads", threads);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use std::process::Command;
+use std::time::Duration;
+use anyhow::anyhow;
+use git2::{Config, Repository};
+use log::debug;
+use crate::git::GitBits;
+use crate::process::output_with_timeout;
+use crate::strategy::Strategy;
+
+/// Which status backend to use, `libgit2` or `cli`. The `nuprompt.backend` git config of a repo takes precedence.
+pub const BACKEND_ENVVAR: &str = "NUPROMPT_GIT_BACKEND";
+const BACKEND_CONFIG: &str = "nuprompt.backend";
+const TIMEOUT_ENVVAR: &str = "NUPROMPT_GIT_CLI_TIMEOUT_MS";
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Whether the status of this repo should come from the git CLI rather than libgit2. The CLI honours fsmonitor,
+/// sparse checkouts, and newer worktree features that libgit2 ignores. Without a configured backend, repos that
+/// have fsmonitor or the untracked cache turned on use the CLI, since libgit2 would walk the whole worktree.
+pub fn wanted(r: &Repository) -> bool {
+    let config = r.config().ok();
+    let configured = config.as_ref().and_then(|c| c.get_string(BACKEND_CONFIG).ok());
+    let env = std::env::var(BACKEND_ENVVAR).ok();
+    choose_cli(configured.as_deref(), env.as_deref(), || config.as_ref().is_some_and(has_status_cache))
+}
+
+/// The repo config wins over the environment, and the status cache only decides when neither names a backend.
+fn choose_cli(configured: Option<&str>, env: Option<&str>, has_status_cache: impl FnOnce() -> bool) -> bool {
+    match configured.or(env).map(str::trim) {
+        Some("cli") => true,
+        Some("libgit2") => false,
+        None => has_status_cache(),
+        Some(other) => {
+            debug!("unknown git backend {:?}, using libgit2", other);
+            false
+        }
+    }
+}
+
+/// Whether the status comes from the git CLI and git keeps data that lets it skip the worktree walk, which makes the
+/// full status cheap regardless of the size of the repo.
+pub fn uses_status_cache(r: &Repository) -> bool {
+    wanted(r) && r.config().is_ok_and(|c| has_status_cache(&c))
+}
+
+/// Whether git keeps data that lets `git status` skip the worktree walk. `core.fsmonitor` is either a boolean for the
+/// builtin daemon or the path of a hook, and `feature.manyFiles` turns the untracked cache on.
+fn has_status_cache(config: &Config) -> bool {
+    let fsmonitor = config.get_string("core.fsmonitor").ok()
+        .is_some_and(|v| !matches!(v.trim().to_ascii_lowercase().as_str(), "" | "false" | "no" | "off" | "0"));
+    let untracked_cache = config.get_bool("core.untrackedCache")
+        .or_else(|_| config.get_bool("feature.manyFiles"))
+        .unwrap_or(false);
+    debug!("fsmonitor={} untracked_cache={}", fsmonitor, untracked_cache);
+    fsmonitor || untracked_cache
+}
+
+/// Scan the status with the backend the repo wants for the strategy.
+pub fn status(r: &Repository, include_untracked: bool, strategy: Strategy) -> Result<GitBits, anyhow::Error> {
+    // git status has no mode that leaves the worktree alone, so only libgit2 can compare just HEAD with the index
+    if strategy != Strategy::Full || !wanted(r) {
+        return GitBits::from_repo(r, include_untracked, strategy);
+    }
+    // a slow or broken git CLI still leaves us with the branch
+    from_cli(r, include_untracked, strategy).or_else(|e| {
+        debug!("git cli status failed: {}", e);
+        GitBits::from_repo(r, include_untracked, Strategy::HeadOnly)
+    })
+}
+
+/// Scan the status with `git status --porcelain=v2 --branch`.
+pub fn from_cli(r: &Repository, include_untracked: bool, strategy: Strategy) -> Result<GitBits, anyhow::Error> {
+    let workdir = r.workdir().ok_or_else(|| anyhow!("repository has no working directory"))?;
+    let timeout = std::env::var(TIMEOUT_ENVVAR).ok()
+        .and_then(|v| v.trim().parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_TIMEOUT);
+    let mut cmd = Command::new("git");
+    // optional locks would make our background status fight with the user's own git commands over index.lock
+    cmd.arg("--no-optional-locks").arg("-C").arg(workdir)
+        .args(["status", "--porcelain=v2", "--branch", "--ignore-submodules=all"]);
+    if !include_untracked || strategy != Strategy::Full {
+        cmd.arg("--untracked-files=no");
+    }
+    let output = output_with_timeout(&mut cmd, timeout)?;
+    if !output.status.success() {
+        return Err(anyhow!("git status exited with {}", output.status));
+    }
+    let mut gb = parse_porcelain_v2(&output.stdout);
+    gb.partial = strategy != Strategy::Full;
+    Ok(gb)
+}
+
+/// Parse the output of `git status --porcelain=v2 --branch`. Changed entries start with `1`, `2` (renamed or copied),
+/// or `u` (unmerged) followed by the index and worktree states, where `.` means unchanged, and untracked entries
+/// start with `?`.
+fn parse_porcelain_v2(output: &[u8]) -> GitBits {
+    let mut gb = GitBits { head_ref: b"NO HEAD".to_vec(), ..GitBits::default() };
+    for line in output.split(|b| *b == b'\n') {
+        match line {
+            [b'#', b' ', header @ ..] => if let Some(head) = header.strip_prefix(b"branch.head ") {
+                gb.head_ref = if head == b"(detached)" { b"HEAD".to_vec() } else { head.to_vec() };
+            },
+            [b'1' | b'2', b' ', x, y, ..] => {
+                if *x != b'.' {
+                    gb.index_modified += 1;
+                }
+                if *y != b'.' {
+                    gb.worktree_modified += 1;
+                }
+                if *x != b'.' && *y != b'.' {
+                    gb.partially_staged += 1;
+                }
+            },
+            [b'u', b' ', ..] => gb.worktree_modified += 1,
+            [b'?', b' ', ..] => gb.untracked_files += 1,
+            _ => {},
+        }
+    }
+    gb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(gb: &GitBits) -> (usize, usize, usize, usize) {
+        (gb.index_modified, gb.worktree_modified, gb.partially_staged, gb.untracked_files)
+    }
+
+    #[test]
+    fn parse_changed_entries() {
+        let entry = |kind: &str, xy: &str| match kind {
+            "1" => format!("1 {} N... 100644 100644 100644 3b18e5 3b18e5 file\n", xy),
+            "2" => format!("2 {} N... 100644 100644 100644 3b18e5 3b18e5 R100 new\told\n", xy),
+            _ => unreachable!(),
+        };
+        // every state a side can be in, staged on the left and unstaged on the right
+        let states = [".", "M", "T", "A", "D", "R", "C"];
+        for kind in ["1", "2"] {
+            for x in states {
+                for y in states {
+                    let gb = parse_porcelain_v2(entry(kind, &format!("{}{}", x, y)).as_bytes());
+                    let (staged, unstaged) = (x != ".", y != ".");
+                    let expected = (usize::from(staged), usize::from(unstaged), usize::from(staged && unstaged), 0);
+                    assert_eq!(counts(&gb), expected, "{} {}{}", kind, x, y);
+                }
+            }
+        }
+        for xy in ["DD", "AU", "UD", "UA", "DU", "AA", "UU"] {
+            let line = format!("u {} N... 100644 100644 100644 100644 3b18e5 3b18e5 3b18e5 file\n", xy);
+            assert_eq!(counts(&parse_porcelain_v2(line.as_bytes())), (0, 1, 0, 0), "u {}", xy);
+        }
+    }
+
+    #[test]
+    fn parse_full_output() {
+        let output = b"# branch.oid 4a1c0e1f6b6e2d4f8f9d2a3b5c6d7e8f9a0b1c2d
+# branch.head topic
+# branch.upstream origin/topic
+# branch.ab +3 -1
+1 M. N... 100644 100644 100644 3b18e5 8d0e41 staged
+1 .M N... 100644 100644 100644 3b18e5 3b18e5 unstaged
+1 MM N... 100644 100644 100644 3b18e5 8d0e41 both
+2 R. N... 100644 100644 100644 3b18e5 3b18e5 R100 moved\tsrc/old
+u UU N... 100644 100644 100644 100644 3b18e5 8d0e41 9c2f1a conflicted
+? new file
+? dir/
+";
+        let gb = parse_porcelain_v2(output);
+        assert_eq!(gb.head_ref, b"topic");
+        assert_eq!(counts(&gb), (3, 3, 1, 2));
+    }
+
+    #[test]
+    fn parse_branch_headers() {
+        let detached = parse_porcelain_v2(b"# branch.oid 4a1c0e1f6b6e2d4f8f9d2a3b5c6d7e8f9a0b1c2d\n# branch.head (detached)\n");
+        assert_eq!(detached.head_ref, b"HEAD");
+        // a branch without an upstream has no upstream and ab lines at all
+        let local = parse_porcelain_v2(b"# branch.oid (initial)\n# branch.head main\n? a\n");
+        assert_eq!((local.head_ref.as_slice(), counts(&local)), (b"main".as_slice(), (0, 0, 0, 1)));
+        let ahead = parse_porcelain_v2(b"# branch.head main\n# branch.upstream origin/main\n# branch.ab +12 -0\n");
+        assert_eq!((ahead.head_ref.as_slice(), counts(&ahead)), (b"main".as_slice(), (0, 0, 0, 0)));
+        assert_eq!(parse_porcelain_v2(b"").head_ref, b"NO HEAD");
+    }
+
+    #[test]
+    fn backend_selection() {
+        assert!(choose_cli(Some("cli"), Some("libgit2"), || false));
+        assert!(!choose_cli(Some("libgit2"), Some("cli"), || true));
+        assert!(choose_cli(None, Some(" cli "), || false));
+        assert!(!choose_cli(None, Some("libgit2"), || true));
+        assert!(choose_cli(None, None, || true));
+        assert!(!choose_cli(None, None, || false));
+        assert!(!choose_cli(Some("bogus"), Some("cli"), || true));
+    }
+}
@@ -0,0 +1,84 @@
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use git2::{Config, Repository};
+use log::debug;
+use crate::cache;
+use crate::config::env_map;
+use crate::host::fnv1a;
+
+/// Set to show the `user.email` of the repo when it is not the global one, such as a work address in an open source
+/// checkout, or the other way around.
+pub const GIT_EMAIL_ENVVAR: &str = "NUPROMPT_GIT_EMAIL";
+/// Comma separated `address=alias` pairs that show a short name in place of the address, e.g. `ben@corp.example=work`.
+const ALIASES_ENVVAR: &str = "NUPROMPT_GIT_EMAIL_ALIASES";
+
+/// The answer is remembered per repo until one of the config files changes, and for no longer than this, since files
+/// pulled in with `include` are not watched.
+const TTL: Duration = Duration::from_secs(300);
+
+/// The address this repo commits with, or its alias, when it differs from the one in the global config. Without a
+/// global address any address of the repo differs.
+pub fn differing(r: &Repository) -> Option<String> {
+    let key = r.path().as_os_str().as_bytes();
+    let fingerprint = fingerprint(r);
+    let cached = cache::get("gitemail", key, Some(TTL))
+        .and_then(|c| String::from_utf8(c).ok())
+        .and_then(|c| c.strip_prefix(&format!("{}\n", fingerprint)).map(str::to_owned));
+    let email = match cached {
+        Some(email) => {
+            debug!("using remembered git email {:?}", email);
+            email
+        },
+        None => {
+            let email = compute(r).unwrap_or_default();
+            if let Err(e) = cache::put("gitemail", key, format!("{}\n{}", fingerprint, email).as_bytes()) {
+                debug!("failed to remember the git email: {}", e);
+            }
+            email
+        },
+    };
+    (!email.is_empty()).then(|| alias(&email, &env_map(ALIASES_ENVVAR)))
+}
+
+fn compute(r: &Repository) -> Option<String> {
+    let effective = r.config().ok()?.get_string("user.email").ok()?;
+    // the default config knows nothing of the repo, so it also leaves out includeIf sections for its directory
+    let global = Config::open_default().and_then(|c| c.get_string("user.email")).ok();
+    (global.as_deref() != Some(effective.as_str())).then_some(effective)
+}
+
+/// The config files that decide the address, by their modification times.
+fn fingerprint(r: &Repository) -> String {
+    let files: Vec<PathBuf> = [Config::find_system(), Config::find_xdg(), Config::find_global()].into_iter()
+        .filter_map(Result::ok)
+        .chain([r.path().join("config")])
+        .collect();
+    let mut stamp = Vec::new();
+    for file in files {
+        let mtime = std::fs::metadata(&file).and_then(|m| m.modified()).ok()
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos());
+        stamp.extend_from_slice(file.as_os_str().as_bytes());
+        stamp.extend_from_slice(&mtime.to_be_bytes());
+    }
+    format!("{:016x}", fnv1a(&stamp))
+}
+
+/// The alias of the address, compared without regard to case since addresses are not case sensitive in practice.
+fn alias(email: &str, aliases: &[(String, String)]) -> String {
+    aliases.iter().find(|(address, _)| address.eq_ignore_ascii_case(email))
+        .map_or_else(|| email.to_owned(), |(_, alias)| alias.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alias_examples() {
+        let aliases = vec![(String::from("Ben@Corp.example"), String::from("work"))];
+        assert_eq!(alias("ben@corp.example", &aliases), "work");
+        assert_eq!(alias("ben@home.example", &aliases), "ben@home.example");
+    }
+}
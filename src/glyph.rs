@@ -0,0 +1,80 @@
+/// Set to use plain ASCII glyphs even in a UTF-8 locale.
+const ASCII_ENVVAR: &str = "NUPROMPT_ASCII";
+
+/// Glyphs holds every non-alphanumeric symbol the prompt uses so that the character set is chosen in one place.
+pub struct Glyphs {
+    /// the separator between the prompt and the command line
+    pub prompt_end: &'static str,
+    /// appended to text that was shortened
+    pub ellipsis: &'static str,
+    /// joins a hint to the explanation of an exit code
+    pub dash: &'static str,
+    /// marks a git status that skipped part of the scan
+    pub partial: &'static str,
+    /// precedes unusual repo layouts such as sparse checkouts
+    pub notice: &'static str,
+    /// precede the exit codes of a command that was not found (127) or could not be executed (126)
+    pub not_found: &'static str,
+    pub not_executable: &'static str,
+    /// precedes the number of exported credentials
+    pub key: &'static str,
+    /// precedes the number of branches with unpushed commits
+    pub unpushed: &'static str,
+    /// precedes the marker of a HEAD commit that is on no remote
+    pub cross: &'static str,
+    /// precedes the number of files with both staged and unstaged changes
+    pub plus_minus: &'static str,
+    /// precedes the number of times in a row the same command failed the same way
+    pub times: &'static str,
+}
+
+pub const UNICODE: Glyphs = Glyphs {
+    prompt_end: "\u{27EB}",
+    ellipsis: "\u{2026}",
+    dash: "\u{2014}",
+    partial: "\u{2248}",
+    notice: "\u{25B2}",
+    not_found: "?",
+    not_executable: "\u{2298}",
+    key: "\u{1F511}",
+    unpushed: "\u{21E1}",
+    cross: "\u{2717}",
+    plus_minus: "\u{B1}",
+    times: "\u{D7}",
+};
+
+pub const ASCII: Glyphs = Glyphs {
+    prompt_end: ">",
+    ellipsis: "...",
+    dash: "-",
+    partial: "~~",
+    notice: "^",
+    not_found: "?",
+    not_executable: "!",
+    key: "key:",
+    unpushed: "^",
+    cross: "x",
+    plus_minus: "+-",
+    times: "x",
+};
+
+/// Pick the glyphs for the environment. Unicode is only used when the locale says the terminal speaks UTF-8.
+pub fn select() -> &'static Glyphs {
+    if std::env::var_os(ASCII_ENVVAR).is_some() || !locale_is_utf8() {
+        &ASCII
+    } else {
+        &UNICODE
+    }
+}
+
+/// The effective character set comes from the first non-empty of LC_ALL, LC_CTYPE, and LANG, as in setlocale(3). With
+/// none of them set, the C locale is ASCII only.
+fn locale_is_utf8() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"].iter()
+        .filter_map(|n| std::env::var(n).ok())
+        .find(|v| !v.is_empty())
+        .is_some_and(|v| {
+            let v = v.to_ascii_lowercase();
+            v.contains("utf-8") || v.contains("utf8")
+        })
+}
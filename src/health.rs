@@ -0,0 +1,62 @@
+use std::os::unix::fs::FileTypeExt;
+use crate::config::env_selection;
+use crate::host::in_ssh_session;
+
+/// The comma separated list of health checks to run. When unset, all checks run. When empty, none run.
+pub const HEALTH_ENVVAR: &str = "NUPROMPT_HEALTH";
+/// The comma separated list of variables that count as a proxy for the proxy check.
+const PROXY_VARS_ENVVAR: &str = "NUPROMPT_PROXY_VARS";
+const DEFAULT_PROXY_VARS: &str = "http_proxy,https_proxy,all_proxy,HTTP_PROXY,HTTPS_PROXY,ALL_PROXY";
+
+/// Check looks for a problem in the shell environment that tends to make other tools fail mysteriously. It returns a
+/// short marker when the problem is present.
+pub struct Check {
+    pub name: &'static str,
+    pub run: fn() -> Option<&'static str>,
+}
+
+pub const CHECKS: &[Check] = &[
+    Check { name: "ssh_agent", run: check_ssh_agent },
+    Check { name: "gpg_tty", run: check_gpg_tty },
+    Check { name: "proxy", run: check_proxy },
+];
+
+/// Run the enabled checks and return the markers of those that failed.
+pub fn markers() -> Vec<&'static str> {
+    let all: Vec<&'static str> = CHECKS.iter().map(|c| c.name).collect();
+    let enabled = env_selection(HEALTH_ENVVAR, &all);
+    CHECKS.iter()
+        .filter(|c| enabled.contains(&c.name))
+        .filter_map(|c| (c.run)())
+        .collect()
+}
+
+/// A socket path that doesn't exist is always broken, while no socket at all only matters over ssh where we expect a
+/// forwarded agent.
+fn check_ssh_agent() -> Option<&'static str> {
+    match std::env::var_os("SSH_AUTH_SOCK") {
+        Some(sock) if !sock.is_empty() => match std::fs::metadata(&sock) {
+            Ok(m) if m.file_type().is_socket() => None,
+            _ => Some("!agent"),
+        },
+        _ if in_ssh_session() => Some("!agent"),
+        _ => None,
+    }
+}
+
+/// Without GPG_TTY, pinentry can't prompt for a passphrase in an ssh session and signing fails.
+fn check_gpg_tty() -> Option<&'static str> {
+    match std::env::var_os("GPG_TTY") {
+        None if in_ssh_session() => Some("!gpgtty"),
+        _ => None,
+    }
+}
+
+/// A proxy left over from another network makes downloads hang or fail with errors that don't mention it. `no_proxy`
+/// isn't in the defaults since it only matters alongside one of the others.
+fn check_proxy() -> Option<&'static str> {
+    let vars = std::env::var(PROXY_VARS_ENVVAR).unwrap_or_else(|_| DEFAULT_PROXY_VARS.to_owned());
+    vars.split(',').map(str::trim).filter(|v| !v.is_empty())
+        .any(|v| std::env::var_os(v).is_some_and(|value| !value.is_empty()))
+        .then_some("proxy")
+}
@@ -0,0 +1,39 @@
+use std::io::Write;
+use termcolor::{BufferWriter, Color, ColorChoice, ColorSpec, WriteColor};
+use crate::glyph::Glyphs;
+
+pub const HINTS_ENVVAR: &str = "NUPROMPT_HINTS";
+
+/// The per exit code override variable prefix, e.g. $NUPROMPT_HINT_127. An empty value disables the hint for that code.
+const HINT_ENVVAR_PREFIX: &str = "NUPROMPT_HINT_";
+
+/// The built in hints as the exit code, what it means, and optional advice.
+const DEFAULT_HINTS: &[(&str, &str, Option<&str>)] = &[
+    ("126", "command not executable", Some("check permissions or chmod +x")),
+    ("127", "command not found", Some("check PATH or typo")),
+    ("137", "killed by SIGKILL", Some("possibly out of memory")),
+    ("139", "segmentation fault", None),
+];
+
+/// Look up the hint for the exit code, preferring the environment over the built in table.
+pub fn hint_for(exit_code: &str, glyphs: &Glyphs) -> Option<String> {
+    match std::env::var(format!("{}{}", HINT_ENVVAR_PREFIX, exit_code)) {
+        Ok(h) => Some(h),
+        Err(_) => DEFAULT_HINTS.iter().find(|(c, _, _)| *c == exit_code).map(|(_, meaning, advice)| match advice {
+            Some(advice) => format!("{} {} {}", meaning, glyphs.dash, advice),
+            None => meaning.to_string(),
+        }),
+    }.filter(|h| !h.is_empty())
+}
+
+/// Write the hint line to stderr. The prompt hook only captures stdout, so this appears above the prompt without
+/// affecting the width calculation of PS1.
+pub fn print_hint(exit_code: &str, hint: &str, color: ColorChoice) -> Result<(), std::io::Error> {
+    let buf_writer = BufferWriter::stderr(color);
+    let mut buffer = buf_writer.buffer();
+    buffer.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_dimmed(true))?;
+    write!(buffer, "exit {}: {}", exit_code, hint)?;
+    buffer.reset()?;
+    buffer.write_all(b"\n")?;
+    buf_writer.print(&buffer)
+}
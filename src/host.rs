@@ -0,0 +1,150 @@
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::str::FromStr;
+use log::debug;
+use regex_lite::Regex;
+use termcolor::Color;
+use crate::config::env_map;
+
+const HOST_COLORS_ENVVAR: &str = "NUPROMPT_HOST_COLORS";
+pub const SHOW_HOST_ENVVAR: &str = "NUPROMPT_SHOW_HOST";
+const HOST_RULES_ENVVAR: &str = "NUPROMPT_HOST_RULES";
+
+/// The colors we pick from when hashing the hostname. Red and yellow are left out since they already mean something
+/// elsewhere in the prompt.
+const PALETTE: &[Color] = &[
+    Color::Cyan,
+    Color::Green,
+    Color::Blue,
+    Color::Magenta,
+    Color::Ansi256(37),
+    Color::Ansi256(71),
+    Color::Ansi256(105),
+    Color::Ansi256(141),
+    Color::Ansi256(168),
+    Color::Ansi256(208),
+];
+
+/// Return the hostname of this machine as reported by gethostname(2).
+pub fn hostname() -> Option<OsString> {
+    if crate::fault::injected("hostname") {
+        return None;
+    }
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for the given length and gethostname does not retain the pointer.
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if rc != 0 {
+        debug!("gethostname failed: {}", std::io::Error::last_os_error());
+        return None;
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    Some(OsString::from_vec(buf[..len].to_vec()))
+}
+
+/// Whether we are running in a shell on the far end of an ssh connection.
+pub fn in_ssh_session() -> bool {
+    std::env::var_os("SSH_CONNECTION").is_some() || std::env::var_os("SSH_TTY").is_some()
+}
+
+/// Abbreviate the hostname for display. The rules in $NUPROMPT_HOST_RULES are a `;` separated list of
+/// `regex=replacement` pairs and the first one that matches is applied, with `$1` style references to capture groups.
+/// Without a matching rule the domain part of the hostname is dropped.
+pub fn abbreviate(hostname: &[u8]) -> OsString {
+    abbreviate_with(std::env::var(HOST_RULES_ENVVAR).ok().as_deref(), hostname)
+}
+
+fn abbreviate_with(rules: Option<&str>, hostname: &[u8]) -> OsString {
+    if let Some(rules) = rules {
+        let lossy = String::from_utf8_lossy(hostname);
+        for (pattern, replacement) in rules.split(';').filter_map(|r| r.split_once('=')) {
+            match Regex::new(pattern) {
+                Ok(re) if re.is_match(&lossy) => return OsString::from(re.replace(&lossy, replacement).into_owned()),
+                Ok(_) => {},
+                Err(e) => debug!("invalid host rule {:?}: {}", pattern, e),
+            }
+        }
+    }
+    let short = hostname.split(|b| *b == b'.').next().unwrap_or_default();
+    OsString::from_vec(short.to_vec())
+}
+
+/// Pick the color for the user/host segment. An entry for the hostname in $NUPROMPT_HOST_COLORS wins, otherwise the
+/// color is chosen from the palette by a stable hash of the hostname.
+pub fn host_color(hostname: &[u8]) -> Color {
+    host_color_with(env_map(HOST_COLORS_ENVVAR), hostname)
+}
+
+fn host_color_with(overrides: Vec<(String, String)>, hostname: &[u8]) -> Color {
+    for (host, color) in overrides {
+        if host.as_bytes() == hostname {
+            match Color::from_str(&color) {
+                Ok(c) => return c,
+                Err(e) => debug!("invalid color for host {:?}: {}", host, e),
+            }
+        }
+    }
+    palette_color(hostname)
+}
+
+/// A color from the palette, always the same one for the same key.
+pub fn palette_color(key: &[u8]) -> Color {
+    PALETTE[(fnv1a(key) % PALETTE.len() as u64) as usize]
+}
+
+/// FNV-1a is tiny, fast, and stable across builds and platforms, unlike the std hasher.
+pub fn fnv1a(input: &[u8]) -> u64 {
+    input.iter().fold(0xcbf29ce484222325, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abbreviations() {
+        let aws = r"^ip-(\d+)-(\d+)-(\d+)-(\d+)\..*compute\.internal$=aws:$1.$2.$3.$4";
+        let cases: &[(Option<&str>, &str, &str)] = &[
+            (None, "laptop", "laptop"),
+            (None, "build01.example.com", "build01"),
+            (None, "ip-10-0-3-12.eu-west-1.compute.internal", "ip-10-0-3-12"),
+            (Some(aws), "ip-10-0-3-12.eu-west-1.compute.internal", "aws:10.0.3.12"),
+            (Some(aws), "build01.example.com", "build01"),
+            (Some("^db=first;^db=second"), "db1.example.com", "first1.example.com"),
+            (Some("(unclosed=x;example=sample"), "build01.example.com", "build01.sample.com"),
+            (Some("no equals sign"), "build01.example.com", "build01"),
+        ];
+        for (rules, hostname, expected) in cases {
+            let got = abbreviate_with(*rules, hostname.as_bytes());
+            assert_eq!(got, OsString::from(expected), "{:?} {}", rules, hostname);
+        }
+    }
+    #[test]
+    fn palette_is_stable() {
+        // The FNV-1a reference values pin the hash, and the hosts below pin the palette order, so a machine keeps its
+        // color across runs and releases.
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        let cases: &[(&str, Color)] = &[
+            ("laptop", Color::Ansi256(71)),
+            ("prod-db", Color::Ansi256(208)),
+            ("build01", Color::Blue),
+            ("ip-10-0-3-12", Color::Green),
+        ];
+        for (hostname, expected) in cases {
+            assert_eq!(host_color_with(vec![], hostname.as_bytes()), *expected, "{}", hostname);
+        }
+    }
+
+    #[test]
+    fn overrides_win_over_the_hash() {
+        let overrides = || vec![
+            ("prod-db".to_owned(), "red".to_owned()),
+            ("laptop".to_owned(), "not-a-color".to_owned()),
+            ("build01".to_owned(), "202".to_owned()),
+        ];
+        assert_eq!(host_color_with(overrides(), b"prod-db"), Color::Red);
+        assert_eq!(host_color_with(overrides(), b"build01"), Color::Ansi256(202));
+        assert_eq!(host_color_with(overrides(), b"laptop"), Color::Ansi256(71));
+        assert_eq!(host_color_with(overrides(), b"prod-db.example.com"), palette_color(b"prod-db.example.com"));
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::Path;
+use std::os::unix::ffi::OsStrExt;
+use git2::Repository;
+use log::debug;
+
+/// Set to make the path a `file://` link and the branch a link to its page on the forge, with OSC 8 sequences. This
+/// is off by default since some terminals print the sequences instead of hiding them.
+pub const HYPERLINKS_ENVVAR: &str = "NUPROMPT_HYPERLINKS";
+
+pub fn enabled() -> bool {
+    std::env::var_os(HYPERLINKS_ENVVAR).is_some()
+}
+
+/// The link for a directory. The host is part of it so that a terminal can tell a path on a machine we ssh'd into from
+/// a local one.
+pub fn file_url(host: &[u8], path: &Path) -> String {
+    let mut url = String::from("file://");
+    percent_encode(host, &mut url);
+    percent_encode(path.as_os_str().as_bytes(), &mut url);
+    url
+}
+
+/// The web page of the checked out branch, from the url of its upstream remote, or origin when it has none.
+pub fn branch_url(r: &Repository) -> Option<String> {
+    let head = r.head().ok().filter(|h| h.is_branch())?;
+    let branch = head.shorthand()?;
+    let remote = r.config().ok()
+        .and_then(|c| c.get_string(&format!("branch.{}.remote", branch)).ok())
+        .unwrap_or_else(|| String::from("origin"));
+    let remote = r.find_remote(&remote).map_err(|e| debug!("no remote {:?} to link the branch to: {}", remote, e)).ok()?;
+    let (host, project) = parse_remote(remote.url()?)?;
+    Some(forge_branch_url(&host, &project, branch))
+}
+
+/// Split a remote url into the host and the project path, without the `.git` suffix. This takes the scp-like
+/// `git@host:owner/repo.git` as well as `ssh://`, `git://`, `http://`, and `https://` urls. Local paths have no web
+/// page.
+fn parse_remote(url: &str) -> Option<(String, String)> {
+    let (host, path) = match url.split_once("://") {
+        Some((scheme, rest)) if matches!(scheme, "ssh" | "git" | "http" | "https" | "git+ssh") => {
+            let (authority, path) = rest.split_once('/')?;
+            let host = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+            // an ssh port has nothing to do with the port of the web page
+            let host = if scheme == "http" || scheme == "https" { host } else { host.split(':').next()? };
+            (host, path)
+        },
+        Some(_) => return None,
+        None => {
+            let (authority, path) = url.split_once(':')?;
+            if authority.contains('/') {
+                return None;
+            }
+            (authority.rsplit_once('@').map_or(authority, |(_, h)| h), path)
+        },
+    };
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    (!host.is_empty() && !path.is_empty()).then(|| (host.to_owned(), path.to_owned()))
+}
+
+/// The branch page on the forge. GitLab and Bitbucket have their own paths, and GitHub, Gitea, Forgejo, and most others
+/// use `/tree/<branch>`.
+fn forge_branch_url(host: &str, project: &str, branch: &str) -> String {
+    let mut url = format!("https://{}/{}", host, project);
+    url.push_str(if host.contains("gitlab") {
+        "/-/tree/"
+    } else if host == "bitbucket.org" {
+        "/branch/"
+    } else {
+        "/tree/"
+    });
+    percent_encode(branch.as_bytes(), &mut url);
+    url
+}
+
+/// Escape everything but the unreserved characters and `/`, which also keeps the sequence free of anything the
+/// shells would expand.
+fn percent_encode(input: &[u8], out: &mut String) {
+    for b in input {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(b) {
+            out.push(*b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(url: &str) -> Option<String> {
+        parse_remote(url).map(|(host, project)| format!("{} {}", host, project))
+    }
+
+    #[test]
+    fn parse_remote_examples() {
+        assert_eq!(parsed("git@github.com:astromechza/nuprompt.git").as_deref(), Some("github.com astromechza/nuprompt"));
+        assert_eq!(parsed("https://github.com/astromechza/nuprompt").as_deref(), Some("github.com astromechza/nuprompt"));
+        assert_eq!(parsed("https://user@gitlab.example.com:8443/group/sub/project.git/").as_deref(), Some("gitlab.example.com:8443 group/sub/project"));
+        assert_eq!(parsed("ssh://git@codeberg.org:2222/owner/repo.git").as_deref(), Some("codeberg.org owner/repo"));
+        assert_eq!(parsed("/srv/git/repo.git").as_deref(), None);
+        assert_eq!(parsed("file:///srv/git/repo.git").as_deref(), None);
+        assert_eq!(parsed("../sibling").as_deref(), None);
+        assert_eq!(parsed("./has:colon/repo").as_deref(), None);
+    }
+
+    #[test]
+    fn branch_and_file_urls() {
+        assert_eq!(forge_branch_url("github.com", "o/r", "feature/x y"), "https://github.com/o/r/tree/feature/x%20y");
+        assert_eq!(forge_branch_url("gitlab.com", "g/p", "main"), "https://gitlab.com/g/p/-/tree/main");
+        assert_eq!(forge_branch_url("bitbucket.org", "t/r", "main"), "https://bitbucket.org/t/r/branch/main");
+        assert_eq!(file_url(b"box", Path::new("/home/ben/it's $HOME")), "file://box/home/ben/it%27s%20%24HOME");
+    }
+}
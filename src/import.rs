@@ -0,0 +1,621 @@
+use std::path::Path;
+use anyhow::anyhow;
+use regex_lite::Regex;
+use crate::detect::DETECTORS;
+use crate::layout::Part;
+use crate::segment::SEGMENTS;
+
+/// The prompts whose config `nuprompt import` can translate.
+pub const SOURCES: &[&str] = &["starship", "p10k"];
+
+/// The starship modules that have a counterpart, in the order of starship's default format, with the part that shows
+/// the same thing. The character turns red after a failure, which is what the exit code is for here.
+const STARSHIP_MODULES: &[(&str, Part)] = &[
+    ("username", Part::User), ("hostname", Part::User), ("directory", Part::Cwd), ("git_branch", Part::Git),
+    ("git_commit", Part::Git), ("git_state", Part::Git), ("git_status", Part::Git), ("golang", Part::Detect),
+    ("rust", Part::Detect), ("cmd_duration", Part::Elapsed), ("status", Part::ExitCode), ("character", Part::ExitCode),
+];
+
+/// The powerlevel10k prompt elements that have a counterpart, with the part that shows the same thing.
+const P10K_ELEMENTS: &[(&str, Part)] = &[
+    ("context", Part::User), ("dir", Part::Cwd), ("vcs", Part::Git), ("go_version", Part::Detect),
+    ("rust_version", Part::Detect), ("command_execution_time", Part::Elapsed), ("status", Part::ExitCode),
+    ("prompt_char", Part::ExitCode),
+];
+
+/// The modules and elements that show a language, with the detector of that language.
+const LANGUAGES: &[(&str, &str)] = &[("golang", "go"), ("rust", "rust"), ("go_version", "go"), ("rust_version", "rust")];
+
+/// Imported is a config translated from another prompt, with the settings that have no counterpart.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Imported {
+    /// the section, key, and value of each setting, where the top level section is empty
+    pub settings: Vec<(String, String, String)>,
+    pub unmapped: Vec<String>,
+}
+
+impl Imported {
+
+    /// Set the key unless an earlier setting already did, so that the first of several sources of a color wins.
+    fn set(&mut self, section: &str, key: &str, value: impl Into<String>) {
+        if !self.settings.iter().any(|(s, k, _)| s == section && k == key) {
+            self.settings.push((section.to_owned(), key.to_owned(), value.into()));
+        }
+    }
+
+    fn not_mapped(&mut self, what: String) {
+        if !self.unmapped.contains(&what) {
+            self.unmapped.push(what);
+        }
+    }
+
+    /// The settings as a config file, with the top level keys before the sections.
+    pub fn to_toml(&self) -> String {
+        let mut sections: Vec<&str> = vec![""];
+        for (section, _, _) in &self.settings {
+            if !sections.contains(&section.as_str()) {
+                sections.push(section);
+            }
+        }
+        let mut out = String::new();
+        for name in sections {
+            if !name.is_empty() {
+                out.push_str(&format!("\n[{}]\n", name));
+            }
+            for (_, key, value) in self.settings.iter().filter(|(s, _, _)| s == name) {
+                out.push_str(&format!("{} = {}\n", key, quote(value)));
+            }
+        }
+        out
+    }
+
+    /// Set the format to the parts on each side, leaving out those of the right side that are on the left side too.
+    fn set_format(&mut self, left: &[Part], right: &[Part]) {
+        let names = |parts: &[Part]| parts.iter().map(|p| p.name()).collect::<Vec<_>>().join(" ");
+        let right: Vec<Part> = right.iter().filter(|p| !left.contains(p)).copied().collect();
+        match right.is_empty() {
+            true => self.set("", "format", names(left)),
+            false => self.set("", "format", format!("{} | {}", names(left), names(&right))),
+        }
+    }
+
+    /// Pick the detectors of the languages that are shown, when that is not all of them.
+    fn set_detectors(&mut self, shown: &[&str]) {
+        let mut detectors: Vec<&str> = LANGUAGES.iter().filter(|(m, _)| shown.contains(m)).map(|(_, d)| *d).collect();
+        detectors.dedup();
+        if !detectors.is_empty() && detectors.len() < DETECTORS.len() {
+            self.set("", "detect", detectors.join(","));
+        }
+    }
+
+}
+
+/// Translate the config file of the prompt and print it as a nuprompt config, with what couldn't be translated on
+/// stderr.
+pub fn run(source: &str, path: &Path) -> Result<(), anyhow::Error> {
+    let translate = match source {
+        "starship" => starship,
+        "p10k" => p10k,
+        _ => return Err(anyhow!("unknown prompt {:?}, expected one of {}", source, SOURCES.join(", "))),
+    };
+    let contents = std::fs::read_to_string(path).map_err(|e| anyhow!("could not read {:?}: {}", path, e))?;
+    let imported = translate(&contents);
+    print!("# imported from {}\n{}", path.display(), imported.to_toml());
+    for what in &imported.unmapped {
+        eprintln!("not mapped: {}", what);
+    }
+    Ok(())
+}
+
+/// Quote the value as a basic string, leaving booleans and numbers bare.
+fn quote(value: &str) -> String {
+    if value == "true" || (!value.is_empty() && value.bytes().all(|b| b.is_ascii_digit())) {
+        return value.to_owned();
+    }
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A value in the config of another prompt, as far as the import cares.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Str(String),
+    Bool(bool),
+    List(Vec<String>),
+    /// numbers, tables, and anything else, as written
+    Other(String),
+}
+
+impl Value {
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) | Value::Other(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Translate a starship.toml. The modules of the top level `format` and `right_format` pick the parts, and so does
+/// `disabled`, while `style` gives a part its color and the symbol of the branch or status goes before the part.
+fn starship(contents: &str) -> Imported {
+    let entries = toml_entries(contents);
+    let mut out = Imported::default();
+    // the schema only helps editors
+    let mut used: Vec<String> = vec![String::from("format"), String::from("right_format"), String::from("$schema")];
+    let get = |key: &str| entries.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+    let disabled = |module: &str| get(&format!("{}.disabled", module)) == Some(&Value::Bool(true));
+
+    let left = get("format").and_then(Value::as_str).unwrap_or("$all");
+    let right = get("right_format").and_then(Value::as_str).unwrap_or_default();
+    let (left_modules, right_modules) = (modules(left), modules(right));
+    if left.trim() == "$all" && right.trim().is_empty() {
+        // without a format of their own, the parts of nuprompt that starship doesn't have stay where they are
+        let mut disable: Vec<&str> = vec![];
+        for (_, part) in STARSHIP_MODULES {
+            let all_disabled = STARSHIP_MODULES.iter().filter(|(_, p)| p == part).all(|(m, _)| disabled(m));
+            if all_disabled && SEGMENTS.contains(&part.name()) && !disable.contains(&part.name()) {
+                disable.push(part.name());
+            }
+        }
+        if !disable.is_empty() {
+            out.set("", "disable", disable.join(","));
+        }
+        let shown: Vec<&str> = STARSHIP_MODULES.iter().map(|(m, _)| *m).filter(|m| !disabled(m)).collect();
+        out.set_detectors(&shown);
+    } else {
+        let mentioned: Vec<&str> = left_modules.iter().chain(&right_modules).map(String::as_str).collect();
+        let mut shown = vec![];
+        let mut side = |names: &[String], out: &mut Imported| {
+            let mut parts: Vec<Part> = vec![];
+            for name in names {
+                let modules: Vec<(&str, Part)> = match name.as_str() {
+                    "all" => STARSHIP_MODULES.iter().filter(|(m, _)| !mentioned.contains(m)).copied().collect(),
+                    name => match STARSHIP_MODULES.iter().find(|(m, _)| *m == name) {
+                        Some(module) => vec![*module],
+                        None => {
+                            out.not_mapped(format!("module ${} in the format", name));
+                            vec![]
+                        },
+                    },
+                };
+                for (module, part) in modules.into_iter().filter(|(m, _)| !disabled(m)) {
+                    shown.push(module);
+                    if !parts.contains(&part) {
+                        parts.push(part);
+                    }
+                }
+            }
+            parts
+        };
+        let (left, right) = (side(&left_modules, &mut out), side(&right_modules, &mut out));
+        out.set_format(&left, &right);
+        out.set_detectors(&shown);
+    }
+
+    for (module, part) in STARSHIP_MODULES {
+        used.push(format!("{}.disabled", module));
+        let style_key = format!("{}.{}", module, if *module == "username" { "style_user" } else { "style" });
+        if let Some(color) = get(&style_key).and_then(Value::as_str).and_then(style_color) {
+            out.set("color", part.name(), color);
+            used.push(style_key);
+        }
+        let symbol_key = format!("{}.symbol", module);
+        if let (Some(symbol), true) = (get(&symbol_key).and_then(Value::as_str), matches!(*module, "git_branch" | "status")) {
+            if !symbol.is_empty() {
+                out.set(&format!("segment.{}", part.name()), "prefix", symbol);
+            }
+            used.push(symbol_key);
+        }
+    }
+    // the defaults of these are what nuprompt does anyway
+    if get("hostname.ssh_only") == Some(&Value::Bool(false)) {
+        out.set("", "show_host", "true");
+    }
+    if get("directory.truncate_to_repo") == Some(&Value::Bool(true)) {
+        out.set("", "path_style", "repo");
+    }
+    used.extend([String::from("hostname.ssh_only"), String::from("directory.truncate_to_repo")]);
+
+    for (key, _) in &entries {
+        if !used.contains(key) {
+            out.not_mapped(key.clone());
+        }
+    }
+    out
+}
+
+/// The names of the modules in a starship format, such as `git_branch` for `$git_branch` and `custom.foo` for
+/// `${custom.foo}`.
+fn modules(format: &str) -> Vec<String> {
+    let re = Regex::new(r"\$(?:\{([A-Za-z0-9_.]+)\}|([A-Za-z0-9_]+))").unwrap();
+    re.captures_iter(format)
+        .filter_map(|c| c.get(1).or(c.get(2)).map(|m| m.as_str().to_owned()))
+        .collect()
+}
+
+/// The color of a starship style such as `bold fg:#ff8800`, which is the first foreground color in it.
+fn style_color(style: &str) -> Option<String> {
+    style.split_whitespace().find_map(|token| match token.strip_prefix("fg:") {
+        Some(c) => color(c),
+        None if token.starts_with("bg:") => None,
+        None => color(token),
+    })
+}
+
+/// A color of another prompt as one nuprompt understands: a name, a number from the 256 color palette, or `#rrggbb`
+/// as `r,g,b`. The bright variants become the plain ones.
+fn color(raw: &str) -> Option<String> {
+    let raw = raw.trim().to_ascii_lowercase();
+    let name = raw.strip_prefix("bright-").unwrap_or(&raw);
+    match name {
+        "black" | "red" | "green" | "yellow" | "blue" | "magenta" | "cyan" | "white" => Some(name.to_owned()),
+        "purple" => Some(String::from("magenta")),
+        n if n.parse::<u8>().is_ok() => Some(n.to_owned()),
+        n => {
+            let hex = n.strip_prefix('#').filter(|h| h.len() == 6)?;
+            let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+            Some(format!("{},{},{}", channel(0)?, channel(2)?, channel(4)?))
+        },
+    }
+}
+
+/// Read the `section.key` entries of a TOML file, with multi-line strings and arrays. Lines that don't parse are
+/// skipped, since the import is only ever a best effort.
+fn toml_entries(contents: &str) -> Vec<(String, Value)> {
+    let mut section = String::new();
+    let mut entries = vec![];
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.split_once(']')).map(|(n, _)| n) {
+            section = name.trim_matches(['[', ']']).trim().to_owned();
+            continue;
+        }
+        let Some((key, raw)) = line.split_once('=') else {
+            continue;
+        };
+        let (key, raw) = (key.trim().trim_matches('"'), raw.trim());
+        let value = if let Some(rest) = raw.strip_prefix("\"\"\"") {
+            Value::Str(unescape(&multi_line(rest, "\"\"\"", &mut lines)))
+        } else if let Some(rest) = raw.strip_prefix("'''") {
+            Value::Str(multi_line(rest, "'''", &mut lines))
+        } else if let Some(rest) = raw.strip_prefix('"') {
+            Value::Str(unescape(basic_string(rest)))
+        } else if let Some(rest) = raw.strip_prefix('\'') {
+            Value::Str(rest.split_once('\'').map_or(rest, |(s, _)| s).to_owned())
+        } else if raw.starts_with('[') {
+            let mut array = raw.to_owned();
+            while array.matches('[').count() > array.matches(']').count() {
+                match lines.next() {
+                    Some(line) => array.push_str(line.trim()),
+                    None => break,
+                }
+            }
+            Value::Other(array)
+        } else {
+            match raw.split('#').next().unwrap_or_default().trim() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                other => Value::Other(other.to_owned()),
+            }
+        };
+        let key = match section.is_empty() {
+            true => key.to_owned(),
+            false => format!("{}.{}", section, key),
+        };
+        entries.push((key, value));
+    }
+    entries
+}
+
+/// The rest of a multi-line string, reading more lines until the closing delimiter. A newline right after the opening
+/// delimiter is not part of the string.
+fn multi_line<'a>(rest: &str, delimiter: &str, lines: &mut impl Iterator<Item = &'a str>) -> String {
+    if let Some((value, _)) = rest.split_once(delimiter) {
+        return value.to_owned();
+    }
+    let mut value = match rest.is_empty() {
+        true => String::new(),
+        false => format!("{}\n", rest),
+    };
+    for line in lines {
+        if let Some((last, _)) = line.split_once(delimiter) {
+            value.push_str(last);
+            break;
+        }
+        value.push_str(line);
+        value.push('\n');
+    }
+    value
+}
+
+/// The contents of a basic string up to its closing quote, still escaped.
+fn basic_string(rest: &str) -> &str {
+    let mut escaped = false;
+    for (i, c) in rest.char_indices() {
+        match c {
+            '"' if !escaped => return &rest[..i],
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    rest
+}
+
+/// Resolve the escapes of TOML basic strings, which also cover the `` icons of powerlevel10k. A backslash at
+/// the end of a line joins it with the next, and unknown escapes are kept as they are.
+fn unescape(raw: &str) -> String {
+    let mut out = String::new();
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(c @ ('"' | '\\')) => out.push(c),
+            Some(u @ ('u' | 'U')) => {
+                let digits: String = (0..if u == 'u' { 4 } else { 8 }).map_while(|_| chars.next_if(char::is_ascii_hexdigit)).collect();
+                match u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32) {
+                    Some(c) => out.push(c),
+                    None => out.extend(['\\', u].into_iter().chain(digits.chars())),
+                }
+            },
+            Some(c) if c.is_whitespace() => {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            },
+            Some(c) => out.extend(['\\', c]),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Translate a ~/.p10k.zsh. The left and right prompt elements pick the parts, the `_FOREGROUND` of an element gives
+/// its part a color, and the branch icon goes before the git part.
+fn p10k(contents: &str) -> Imported {
+    let entries = p10k_entries(contents);
+    let mut out = Imported::default();
+    let get = |name: &str| entries.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+    let elements = |name: &str| match get(name) {
+        Some(Value::List(elements)) => elements.clone(),
+        _ => vec![],
+    };
+    let (left, right) = (elements("LEFT_PROMPT_ELEMENTS"), elements("RIGHT_PROMPT_ELEMENTS"));
+    let mut shown = vec![];
+    let mut side = |elements: &[String], out: &mut Imported| {
+        let mut parts: Vec<Part> = vec![];
+        for element in elements {
+            match P10K_ELEMENTS.iter().find(|(e, _)| e == element) {
+                Some((element, part)) => {
+                    shown.push(*element);
+                    if !parts.contains(part) {
+                        parts.push(*part);
+                    }
+                },
+                None => out.not_mapped(format!("prompt element {}", element)),
+            }
+        }
+        parts
+    };
+    let mut used: Vec<String> = vec![];
+    if get("LEFT_PROMPT_ELEMENTS").is_some() || get("RIGHT_PROMPT_ELEMENTS").is_some() {
+        let (left, right) = (side(&left, &mut out), side(&right, &mut out));
+        out.set_format(&left, &right);
+        out.set_detectors(&shown);
+        used.extend([String::from("LEFT_PROMPT_ELEMENTS"), String::from("RIGHT_PROMPT_ELEMENTS")]);
+    }
+
+    let foregrounds = P10K_ELEMENTS.iter().map(|(e, part)| (e.to_ascii_uppercase(), *part))
+        .chain([(String::from("VCS_CLEAN"), Part::Git), (String::from("STATUS_ERROR"), Part::ExitCode)]);
+    for (element, part) in foregrounds {
+        let name = format!("{}_FOREGROUND", element);
+        if let Some(color) = get(&name).and_then(Value::as_str).and_then(color) {
+            out.set("color", part.name(), color);
+            used.push(name);
+        }
+    }
+    if let Some(icon) = get("VCS_BRANCH_ICON").and_then(Value::as_str) {
+        out.set("segment.git", "prefix", unescape(icon));
+        used.push(String::from("VCS_BRANCH_ICON"));
+    }
+    if get("MODE").and_then(Value::as_str) == Some("ascii") {
+        out.set("", "ascii", "true");
+        used.push(String::from("MODE"));
+    }
+    // a percentage of the terminal width has no counterpart, only a number of columns
+    if let Some(width) = get("DIR_MAX_LENGTH").and_then(Value::as_str).filter(|w| w.parse::<u16>().is_ok_and(|w| w > 0)) {
+        out.set("", "path_width", width);
+        used.push(String::from("DIR_MAX_LENGTH"));
+    }
+
+    for (name, _) in &entries {
+        if !used.contains(name) {
+            out.not_mapped(format!("POWERLEVEL9K_{}", name));
+        }
+    }
+    out
+}
+
+/// Read the `POWERLEVEL9K_` assignments of a zsh file, with the prefix taken off the names. Arrays may span lines and
+/// hold comments, as in the files that `p10k configure` writes.
+fn p10k_entries(contents: &str) -> Vec<(String, Value)> {
+    let assignment = Regex::new(r"^(?:(?:typeset|local|declare)\s+(?:-[A-Za-z]+\s+)*)?POWERLEVEL9K_([A-Za-z0-9_]+)=(.*)$").unwrap();
+    let uncomment = |line: &str| -> String {
+        let line = line.trim();
+        match line.starts_with('#') {
+            true => String::new(),
+            false => line.split(" #").next().unwrap_or_default().to_owned(),
+        }
+    };
+    let mut entries = vec![];
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        let Some(captures) = assignment.captures(line.trim()) else {
+            continue;
+        };
+        let (name, raw) = (captures[1].to_owned(), captures[2].trim().to_owned());
+        let value = if let Some(rest) = raw.strip_prefix('(') {
+            let mut words = uncomment(rest);
+            while !words.contains(')') {
+                match lines.next() {
+                    Some(line) => words.push_str(&format!(" {}", uncomment(line))),
+                    None => break,
+                }
+            }
+            let words = words.split(')').next().unwrap_or_default();
+            Value::List(words.split_whitespace().map(|w| w.trim_matches(['\'', '"']).to_owned()).collect())
+        } else if let Some(rest) = raw.strip_prefix('\'') {
+            Value::Str(rest.split_once('\'').map_or(rest, |(s, _)| s).to_owned())
+        } else if let Some(rest) = raw.strip_prefix('"') {
+            Value::Str(rest.split_once('"').map_or(rest, |(s, _)| s).to_owned())
+        } else {
+            Value::Str(uncomment(&raw).split_whitespace().next().unwrap_or_default().to_owned())
+        };
+        entries.push((name, value));
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(imported: &Imported) -> Vec<(&str, &str, &str)> {
+        imported.settings.iter().map(|(s, k, v)| (s.as_str(), k.as_str(), v.as_str())).collect()
+    }
+
+    #[test]
+    fn starship_example() {
+        let imported = starship(r#"
+add_newline = false
+format = """
+$username\
+$hostname\
+$directory\
+$git_branch\
+$git_status\
+$rust\
+$nodejs\
+$line_break\
+$character"""
+right_format = "$cmd_duration"
+
+[hostname]
+ssh_only = false
+
+[directory]
+truncation_length = 3
+truncate_to_repo = true
+
+[git_branch]
+symbol = " "
+style = "bold purple"
+
+[git_status]
+style = "red"
+
+[cmd_duration]
+style = "fg:#ff8800 bold"
+min_time = 500
+
+[character]
+success_symbol = "[➜](bold green)"
+"#);
+        assert_eq!(settings(&imported), [
+            ("", "format", "user cwd git detect exit_code | elapsed"),
+            ("", "detect", "rust"),
+            ("color", "git", "magenta"),
+            ("segment.git", "prefix", " "),
+            ("color", "elapsed", "255,136,0"),
+            ("", "show_host", "true"),
+            ("", "path_style", "repo"),
+        ]);
+        assert_eq!(imported.unmapped, [
+            "module $nodejs in the format",
+            "module $line_break in the format",
+            "add_newline",
+            "directory.truncation_length",
+            "cmd_duration.min_time",
+            "character.success_symbol",
+        ]);
+    }
+
+    #[test]
+    fn starship_disabled_without_format() {
+        let imported = starship("[git_branch]\ndisabled = true\n[git_commit]\ndisabled = true\n[git_state]\ndisabled = true\n[git_status]\ndisabled = true\n[golang]\ndisabled = true\n");
+        assert_eq!(settings(&imported), [("", "disable", "git"), ("", "detect", "rust")]);
+        assert!(imported.unmapped.is_empty(), "{:?}", imported.unmapped);
+    }
+
+    #[test]
+    fn p10k_example() {
+        let imported = p10k(r#"
+() {
+  typeset -g POWERLEVEL9K_LEFT_PROMPT_ELEMENTS=(
+    os_icon                 # os identifier
+    dir                     # current directory
+    vcs                     # git status
+    newline
+    prompt_char             # prompt symbol
+  )
+  typeset -g POWERLEVEL9K_RIGHT_PROMPT_ELEMENTS=(status command_execution_time context time)
+  typeset -g POWERLEVEL9K_MODE=ascii
+  typeset -g POWERLEVEL9K_DIR_FOREGROUND=31
+  typeset -g POWERLEVEL9K_VCS_CLEAN_FOREGROUND=76
+  typeset -g POWERLEVEL9K_VCS_BRANCH_ICON=' '
+  typeset -g POWERLEVEL9K_DIR_MAX_LENGTH=80
+  typeset -g POWERLEVEL9K_TIME_FORMAT='%D{%H:%M:%S}'
+}
+"#);
+        assert_eq!(settings(&imported), [
+            ("", "format", "cwd git exit_code | elapsed user"),
+            ("color", "cwd", "31"),
+            ("color", "git", "76"),
+            ("segment.git", "prefix", "\u{f126} "),
+            ("", "ascii", "true"),
+            ("", "path_width", "80"),
+        ]);
+        assert_eq!(imported.unmapped, [
+            "prompt element os_icon",
+            "prompt element newline",
+            "prompt element time",
+            "POWERLEVEL9K_TIME_FORMAT",
+        ]);
+    }
+
+    #[test]
+    fn style_colors() {
+        assert_eq!(style_color("bold bright-blue"), Some(String::from("blue")));
+        assert_eq!(style_color("bg:red fg:208"), Some(String::from("208")));
+        assert_eq!(style_color("bold italic"), None);
+        assert_eq!(color("#00ff7f"), Some(String::from("0,255,127")));
+        assert_eq!(color("#00ff7"), None);
+    }
+
+    #[test]
+    fn to_toml() {
+        let mut imported = Imported::default();
+        imported.set("", "format", "cwd git");
+        imported.set("color", "git", "magenta");
+        imported.set("", "ascii", "true");
+        imported.set("segment.git", "prefix", "on \"");
+        imported.set("color", "git", "red");
+        assert_eq!(imported.to_toml(), "format = \"cwd git\"\nascii = true\n\n[color]\ngit = \"magenta\"\n\n[segment.git]\nprefix = \"on \\\"\"\n");
+    }
+}
@@ -0,0 +1,70 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use crate::state;
+
+/// Look for problems that would make every prompt fail or silently lose features. These run once when the init code
+/// is printed rather than on every prompt.
+pub fn problems() -> Vec<String> {
+    let mut out = vec![];
+    let dir = state::state_dir();
+    let probe = dir.join(format!("NUPROMPT_probe_{}", std::process::id()));
+    if let Err(e) = fs::create_dir_all(&dir).and_then(|_| fs::write(&probe, b"")).and_then(|_| fs::remove_file(&probe)) {
+        out.push(format!("state dir {} is not writable ({}), elapsed times will be missing, set $NUPROMPT_STATE_DIR to a writable dir", dir.display(), e));
+    }
+    if !on_path("nuprompt") {
+        out.push(String::from("nuprompt is not on PATH so the prompt hooks cannot run it"));
+    }
+    if std::env::var_os(crate::NO_GIT_ENVVAR).is_none() {
+        if let Err(e) = git2::Config::open_default() {
+            out.push(format!("git config cannot be read ({}), git status will be missing", e.message()));
+        }
+    }
+    out.extend(crate::theme::problems());
+    out
+}
+
+fn on_path(name: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|p| std::env::split_paths(&p).collect::<Vec<PathBuf>>())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|d| fs::metadata(d.join(name)).ok())
+        .any(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// Make the message safe inside a single quoted string in every supported shell. Control characters, the escape that
+/// starts terminal sequences among them, are shown in caret notation like `cat -v` does, and the characters that end or
+/// escape a single quoted string in one of the shells become `?`: the quote, the curly quotes pwsh takes for it, the
+/// backslash, and the `!` of tcsh history. A backslash can't escape them, since xonsh and fish interpret it inside
+/// single quotes.
+pub fn sanitize(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    for c in message.chars() {
+        match c {
+            '\'' | '\\' | '!' | '\u{2018}'..='\u{201b}' => out.push('?'),
+            c if c.is_control() => {
+                let b = c as u32;
+                if b >= 0x80 {
+                    out.push_str("M-");
+                }
+                out.push('^');
+                out.push(char::from(((b & 0x7f) ^ 0x40) as u8));
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_examples() {
+        assert_eq!(sanitize("no nuprompt in /home/jürgen/bin (again)"), "no nuprompt in /home/jürgen/bin (again)");
+        assert_eq!(sanitize("\x1b]0;pwned\x07\n\x7f\u{9b}"), "^[]0;pwned^G^J^?M-^[");
+        assert_eq!(sanitize("it's C:\\bin! \u{2019}x\u{2019}"), "it?s C:?bin? ?x?");
+    }
+}
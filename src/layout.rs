@@ -0,0 +1,203 @@
+use log::debug;
+
+/// The parts of the left side in order, then optionally a `|` and the parts of the right side, separated by spaces.
+/// The parts that aren't listed are not shown. This wins over the side and order of each part.
+pub const FORMAT_ENVVAR: &str = "NUPROMPT_FORMAT";
+
+/// Part is a piece of the prompt that can be moved to either side and reordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    Audit,
+    History,
+    ExitCode,
+    Elapsed,
+    StartTime,
+    Command,
+    Health,
+    Throttled,
+    Secrets,
+    Updates,
+    ShellMode,
+    User,
+    Git,
+    Detect,
+    Fs,
+    Security,
+    Custom,
+    Cwd,
+}
+
+impl Part {
+
+    /// Every part, in the default order.
+    pub const ALL: &'static [Part] = &[
+        Part::Audit, Part::History, Part::ExitCode, Part::Elapsed, Part::StartTime, Part::Command, Part::Health,
+        Part::Throttled, Part::Secrets, Part::Updates, Part::ShellMode, Part::User, Part::Git, Part::Detect, Part::Fs,
+        Part::Security, Part::Custom, Part::Cwd,
+    ];
+
+    pub fn find(name: &str) -> Option<Part> {
+        Part::ALL.iter().find(|p| p.name() == name).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Part::Audit => "audit",
+            Part::History => "history",
+            Part::ExitCode => "exit_code",
+            Part::Elapsed => "elapsed",
+            Part::StartTime => "start_time",
+            Part::Command => "command",
+            Part::Health => "health",
+            Part::Throttled => "throttled",
+            Part::Secrets => "secrets",
+            Part::Updates => "updates",
+            Part::ShellMode => "shell_mode",
+            Part::User => "user",
+            Part::Git => "git",
+            Part::Detect => "detect",
+            Part::Fs => "fs",
+            Part::Security => "security",
+            Part::Custom => "custom",
+            Part::Cwd => "cwd",
+        }
+    }
+
+}
+
+/// Decoration is text written before and after a part, only when the part has anything to show.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoration {
+    pub part: Part,
+    pub prefix: String,
+    pub suffix: String,
+}
+
+/// The decorations from `$NUPROMPT_SEGMENT_<NAME>_PREFIX` and `$NUPROMPT_SEGMENT_<NAME>_SUFFIX`, e.g. `on ` before the
+/// branch or `(` and `)` around it. The values are used as they are, spaces included.
+pub fn decorations() -> Vec<Decoration> {
+    decorations_from(|name| std::env::var(name).ok())
+}
+
+fn decorations_from(lookup: impl Fn(&str) -> Option<String>) -> Vec<Decoration> {
+    Part::ALL.iter().filter_map(|part| {
+        let var = |key: &str| lookup(&format!("NUPROMPT_SEGMENT_{}_{}", part.name().to_ascii_uppercase(), key)).unwrap_or_default();
+        let (prefix, suffix) = (var("PREFIX"), var("SUFFIX"));
+        (!prefix.is_empty() || !suffix.is_empty()).then_some(Decoration { part: *part, prefix, suffix })
+    }).collect()
+}
+
+/// Layout holds the parts on each side of the prompt in the order they are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    pub left: Vec<Part>,
+    pub right: Vec<Part>,
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        Layout { left: Part::ALL.to_vec(), right: vec![] }
+    }
+}
+
+impl Layout {
+
+    /// Each part is placed by `$NUPROMPT_SEGMENT_<NAME>_SIDE`, `left` or `right`, and `$NUPROMPT_SEGMENT_<NAME>_ORDER`,
+    /// a number that defaults to ten times its position in the default order, so that a part can be put between two
+    /// others without giving every part a number.
+    pub fn from_env() -> Layout {
+        Layout::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Layout {
+        if let Some(format) = lookup(FORMAT_ENVVAR) {
+            return Layout::from_format(&format);
+        }
+        let mut placed: Vec<(i64, bool, Part)> = Part::ALL.iter().enumerate().map(|(i, part)| {
+            let var = |key: &str| {
+                let name = format!("NUPROMPT_SEGMENT_{}_{}", part.name().to_ascii_uppercase(), key);
+                lookup(&name).map(|v| (name, v.trim().to_owned()))
+            };
+            let right = match var("SIDE") {
+                Some((_, side)) if side == "right" => true,
+                Some((_, side)) if side == "left" => false,
+                Some((name, side)) => {
+                    debug!("ignoring unknown {} {:?}", name, side);
+                    false
+                },
+                None => false,
+            };
+            let default_order = (i as i64 + 1) * 10;
+            let order = var("ORDER").map_or(default_order, |(name, order)| order.parse().unwrap_or_else(|_| {
+                debug!("ignoring {} {:?} that is not a number", name, order);
+                default_order
+            }));
+            (order, right, *part)
+        }).collect();
+        // the sort is stable, so parts given the same number keep their default order
+        placed.sort_by_key(|(order, _, _)| *order);
+        let side = |right: bool| placed.iter().filter(|p| p.1 == right).map(|p| p.2).collect();
+        Layout { left: side(false), right: side(true) }
+    }
+
+    fn from_format(format: &str) -> Layout {
+        let (left, right) = format.split_once('|').unwrap_or((format, ""));
+        let parts = |side: &str| side.split_whitespace().filter_map(|name| {
+            let part = Part::find(name);
+            if part.is_none() {
+                debug!("ignoring unknown part {:?} in {}", name, FORMAT_ENVVAR);
+            }
+            part
+        }).collect();
+        Layout { left: parts(left), right: parts(right) }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(vars: &[(&str, &str)]) -> Layout {
+        Layout::from_lookup(|name| vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string()))
+    }
+
+    #[test]
+    fn default_layout() {
+        assert_eq!(layout(&[]), Layout::default());
+        assert_eq!(layout(&[("NUPROMPT_SEGMENT_GIT_SIDE", "middle"), ("NUPROMPT_SEGMENT_GIT_ORDER", "x")]), Layout::default());
+    }
+
+    #[test]
+    fn decorations() {
+        let vars = [("NUPROMPT_SEGMENT_GIT_PREFIX", "on "), ("NUPROMPT_SEGMENT_CWD_SUFFIX", ")"), ("NUPROMPT_SEGMENT_USER_PREFIX", "")];
+        let decorations = decorations_from(|name| vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string()));
+        assert_eq!(decorations, [
+            Decoration { part: Part::Git, prefix: String::from("on "), suffix: String::new() },
+            Decoration { part: Part::Cwd, prefix: String::new(), suffix: String::from(")") },
+        ]);
+    }
+
+    #[test]
+    fn format() {
+        let placed = layout(&[("NUPROMPT_FORMAT", " exit_code bogus git  cwd | elapsed "), ("NUPROMPT_SEGMENT_CWD_SIDE", "right")]);
+        assert_eq!(placed, Layout { left: vec![Part::ExitCode, Part::Git, Part::Cwd], right: vec![Part::Elapsed] });
+        assert_eq!(layout(&[("NUPROMPT_FORMAT", "cwd")]), Layout { left: vec![Part::Cwd], right: vec![] });
+    }
+
+    #[test]
+    fn sides_and_order() {
+        let placed = layout(&[
+            ("NUPROMPT_SEGMENT_GIT_SIDE", "right"),
+            ("NUPROMPT_SEGMENT_ELAPSED_SIDE", " right "),
+            ("NUPROMPT_SEGMENT_ELAPSED_ORDER", "1000"),
+            ("NUPROMPT_SEGMENT_CWD_ORDER", "0"),
+            ("NUPROMPT_SEGMENT_USER_ORDER", "70"),
+        ]);
+        assert_eq!(placed.right, [Part::Git, Part::Elapsed]);
+        assert_eq!(&placed.left[..4], [Part::Cwd, Part::Audit, Part::History, Part::ExitCode]);
+        // user takes the number of health and goes after it, since health comes first in the default order
+        assert_eq!(&placed.left[4..8], [Part::StartTime, Part::Command, Part::Health, Part::User]);
+        assert_eq!(placed.left.len() + placed.right.len(), Part::ALL.len());
+    }
+}
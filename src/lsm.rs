@@ -0,0 +1,90 @@
+use std::fs;
+use log::debug;
+
+/// Set to show the SELinux context or AppArmor profile that the shell runs under.
+pub const SECURITY_ENVVAR: &str = "NUPROMPT_SECURITY_CONTEXT";
+
+/// Newer kernels can stack security modules and give each one its own file, while the shared file belongs to whichever
+/// module was set up first.
+const APPARMOR_PATH: &str = "/proc/self/attr/apparmor/current";
+const CURRENT_PATH: &str = "/proc/self/attr/current";
+
+/// SecurityContext is the label a security module gives the process, and whether it restricts the process at all.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SecurityContext {
+    pub label: String,
+    pub confined: bool,
+}
+
+/// Read the context of our own process, which is the shell's, since the prompt is run without a transition.
+pub fn current() -> Option<SecurityContext> {
+    if let Some(raw) = read(APPARMOR_PATH) {
+        return parse_apparmor(&raw);
+    }
+    let raw = read(CURRENT_PATH)?;
+    parse_selinux(&raw).or_else(|| parse_apparmor(&raw))
+}
+
+fn read(path: &str) -> Option<String> {
+    match fs::read_to_string(path) {
+        Ok(raw) => Some(raw.trim_end_matches(['\0', '\n']).to_owned()).filter(|r| !r.is_empty()),
+        Err(e) => {
+            debug!("could not read {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// An SELinux context is `user:role:type` followed by an optional MLS range, e.g.
+/// `staff_u:staff_r:staff_t:s0-s0:c0.c1023`. The range is left out of the label, and the unconfined types don't
+/// restrict anything.
+fn parse_selinux(raw: &str) -> Option<SecurityContext> {
+    let mut parts = raw.splitn(4, ':');
+    let (user, role, kind) = (parts.next()?, parts.next()?, parts.next()?);
+    if [user, role, kind].iter().any(|p| p.is_empty() || p.contains(char::is_whitespace)) {
+        return None;
+    }
+    Some(SecurityContext { label: format!("se:{}:{}:{}", user, role, kind), confined: !kind.ends_with("unconfined_t") })
+}
+
+/// An AppArmor context is `unconfined`, or a profile followed by its mode in brackets, e.g. `firefox (enforce)`.
+/// Only the enforcing modes restrict anything, a profile in complain mode just logs.
+fn parse_apparmor(raw: &str) -> Option<SecurityContext> {
+    if raw == "unconfined" {
+        return Some(SecurityContext { label: String::from("aa:unconfined"), confined: false });
+    }
+    let (profile, mode) = raw.rsplit_once(" (")?;
+    let mode = mode.strip_suffix(')')?;
+    let label = match mode {
+        "enforce" => format!("aa:{}", profile),
+        mode => format!("aa:{}({})", profile, mode),
+    };
+    Some(SecurityContext { label, confined: matches!(mode, "enforce" | "kill") })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(label: &str, confined: bool) -> Option<SecurityContext> {
+        Some(SecurityContext { label: label.to_owned(), confined })
+    }
+
+    #[test]
+    fn parse_selinux_examples() {
+        assert_eq!(parse_selinux("staff_u:staff_r:staff_t:s0-s0:c0.c1023"), context("se:staff_u:staff_r:staff_t", true));
+        assert_eq!(parse_selinux("unconfined_u:unconfined_r:unconfined_t:s0-s0:c0.c1023"), context("se:unconfined_u:unconfined_r:unconfined_t", false));
+        assert_eq!(parse_selinux("system_u:system_r:sshd_t"), context("se:system_u:system_r:sshd_t", true));
+        assert_eq!(parse_selinux("unconfined"), None);
+        assert_eq!(parse_selinux("snap.a:b (enforce)"), None);
+    }
+
+    #[test]
+    fn parse_apparmor_examples() {
+        assert_eq!(parse_apparmor("unconfined"), context("aa:unconfined", false));
+        assert_eq!(parse_apparmor("restricted-shell (enforce)"), context("aa:restricted-shell", true));
+        assert_eq!(parse_apparmor("/usr/bin/foo (complain)"), context("aa:/usr/bin/foo(complain)", false));
+        assert_eq!(parse_apparmor("unprivileged_userns (unconfined)"), context("aa:unprivileged_userns(unconfined)", false));
+        assert_eq!(parse_apparmor("kernel"), None);
+    }
+}
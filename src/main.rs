@@ -1,222 +1,789 @@
 use std::env::args_os;
 use std::ffi::{OsStr, OsString};
-use std::fs;
-use std::io::{stdin, IsTerminal, Write};
-use std::ops::Deref;
-use std::os::unix::prelude::OsStrExt;
-use std::path::PathBuf;
+use std::io::{stderr, stdin, IsTerminal, Write};
+use std::os::unix::prelude::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use anyhow::{anyhow, Context};
 use coarsetime::Duration;
-use git2::{Repository, Status, StatusOptions};
+use git2::Repository;
 use log::debug;
-use termcolor::{BufferWriter, Color, ColorChoice, ColorSpec, WriteColor};
+use termcolor::{BufferWriter, Color, ColorChoice, NoColor};
+
+mod bookmark;
+mod cache;
+mod cienv;
+mod clock;
+mod completions;
+mod config;
+mod cwdrule;
+mod detect;
+mod dirhistory;
+mod exitcodes;
+mod fault;
+mod fastpath;
+mod fstype;
+mod git;
+mod gitcli;
+mod gitemail;
+mod glyph;
+mod health;
+mod hints;
+mod host;
+mod hyperlink;
+mod import;
+mod initcheck;
+mod layout;
+mod lsm;
+mod notify;
+mod pathtrim;
+mod process;
+mod publish;
+mod render;
+mod secrets;
+mod segment;
+mod shell;
+mod shellmode;
+mod signing;
+mod slo;
+mod state;
+mod strategy;
+mod sysupdate;
+mod theme;
+mod timing;
+#[cfg(feature = "trace")]
+mod trace;
+mod unpushed;
+mod update;
+mod username;
+mod version;
+mod warm;
+#[cfg(target_os = "linux")]
+mod watch;
+mod watchdog;
+mod width;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const RUST_LOG_FILTER_ENVVAR: &str = "NUPROMPT_RUST_LOG";
 const NO_GIT_ENVVAR: &str = "NUPROMPT_NO_GIT";
+const SHOW_COMMAND_ENVVAR: &str = "NUPROMPT_SHOW_COMMAND";
+const CWD_DIFF_ENVVAR: &str = "NUPROMPT_CWD_DIFF";
+const SHOW_HISTORY_ENVVAR: &str = "NUPROMPT_SHOW_HISTORY";
+const MINIMAL_ENVVAR: &str = "NUPROMPT_MINIMAL";
+/// Set to let bash and zsh expand the cwd themselves with `\w` and `%~`.
+const NATIVE_CWD_ENVVAR: &str = "NUPROMPT_NATIVE_CWD";
+/// Set to also export the branch and dirty flag from the scan in bash and zsh.
+const EXPORT_GIT_ENVVAR: &str = "NUPROMPT_EXPORT_GIT";
+/// Set to show the local time the previous command started next to its duration.
+const SHOW_START_ENVVAR: &str = "NUPROMPT_SHOW_START";
+/// Set to stamp every prompt with an ISO 8601 timestamp and the full hostname, whatever else is configured. The
+/// built in `audit` profile sets it.
+const AUDIT_ENVVAR: &str = "NUPROMPT_AUDIT";
+const ELAPSED_SCALE_ENVVAR: &str = "NUPROMPT_ELAPSED_SCALE";
+/// The color of exit codes 126 and 127.
+const SHELL_ERROR_COLOR_ENVVAR: &str = "NUPROMPT_SHELL_ERROR_COLOR";
 const PWD_ENVVAR: &str = "PWD";
+const NO_COLOR_ENVVAR: &str = "NO_COLOR";
+const TERM_ENVVAR: &str = "TERM";
 const HOME_ENVVAR: &str = "HOME";
 
+/// The flags we accept. Entries ending in = take a value.
+const KNOWN_FLAGS: &[&str] = &["--print-full-init", "--shell=", "--debug-timings", "--minimal", "--history=", "--color=", "--check", "--print-hooks", "--seq=", "--json", "--from-env", "--segment=", "--argv0=", "--shell-flags="];
+
+/// The subcommands that can also be run through a link named `nuprompt-<subcommand>`, like a busybox applet.
+const APPLETS: &[&str] = &["ps0", "ps1", "git-info"];
+
+/// The subcommand named by the link the binary was run through, e.g. `ps1` for `/usr/local/bin/nuprompt-ps1`.
+fn applet(argv0: &OsStr) -> Option<&'static str> {
+    let name = Path::new(argv0).file_name()?.as_bytes().strip_prefix(b"nuprompt-")?;
+    APPLETS.iter().find(|a| a.as_bytes() == name).copied()
+}
+
 fn main() -> Result<(), anyhow::Error> {
     // we output some debug logs which can be turned on if needed.
     env_logger::init_from_env(env_logger::Env::default().filter(RUST_LOG_FILTER_ENVVAR));
 
     // we handle args in a very basic way since this is not intended to be an interactive or iterative
-    // CLI UX.
-    let n_args = args_os().len();
-    let subcommand = args_os().nth(1);
-    let pid_arg = args_os().nth(2);
-    let extra_arg = args_os().nth(3);
-    match subcommand {
-        Some(p) if p.eq("bash") && n_args == 2 => {
-            println!("PS0='$(nuprompt ps0 $$)'\nPROMPT_COMMAND='eval $(nuprompt ps1 $$ $?)'");
+    // CLI UX. Anything starting with -- is a flag, everything else is positional.
+    let mut args = args_os();
+    let applet = args.next().as_deref().and_then(applet).map(OsString::from);
+    let (flags, positional): (Vec<OsString>, Vec<OsString>) = applet.into_iter().chain(args)
+        .partition(|a| a.as_bytes().starts_with(b"--"));
+    let positional: Vec<&OsStr> = positional.iter().map(OsString::as_os_str).collect();
+    // ps1 takes the state of the shell once, up front, since the prompt of a shell session uses the profile picked for
+    // it with 'nuprompt use' and the config has to be loaded before anything else
+    let mut timings = timing::Timings::new();
+    let prev = match positional.as_slice() {
+        [p, pid, exit_code] if p.eq(&"ps1") => {
+            // the start time and text of the previous command come from a file on the system
+            let prev = timings.span("state", || state::take(pid, failed_exit_code(exit_code).map(OsStr::as_bytes)));
+            Some(prev.unwrap_or_else(|e| {
+                debug!("error reading pid file: {}", e);
+                state::State::default()
+            }))
+        },
+        _ => None,
+    };
+    let session_profile = prev.as_ref().and_then(|st| st.profile.as_deref()).map(|p| String::from_utf8_lossy(p).into_owned());
+    config::load(session_profile);
+    // the config may turn this on, and it has to happen while this is still the only thread
+    git::trust_all_if_configured();
+    let flag = |name: &str| flags.iter().any(|f| f.eq(name));
+    let flag_value = |name: &str| flags.iter()
+        .find_map(|f| f.as_bytes().strip_prefix(name.as_bytes())?.strip_prefix(b"="));
+    if let Some(f) = flags.iter().find(|f| !KNOWN_FLAGS.iter().any(|k| f.as_os_str().eq(*k) || (k.ends_with('=') && f.as_bytes().starts_with(k.as_bytes())))) {
+        return Err(anyhow!("nuprompt {}: unknown flag {:?}", VERSION, f));
+    }
+    // the prompt cycle number lets ps0 ignore repeated starts of the same command line
+    let sequence = || flag_value("--seq").and_then(|s| std::str::from_utf8(s).ok()?.parse().ok());
+    let shell = match flag_value("--shell") {
+        Some(name) => shell::find(name).ok_or_else(|| anyhow!("unknown shell {:?}, expected one of {}", OsStr::from_bytes(name), shell::names()))?,
+        None => &shell::BASH,
+    };
+    match positional.as_slice() {
+        // 'nuprompt <shell>' is shorthand for the full init of that shell
+        [p] if shell::find(p.as_bytes()).is_some() => {
+            print_full_init(shell::find(p.as_bytes()).unwrap());
             Ok(())
         },
-        Some(p) if p.eq("ps0") && n_args == 3 => ps0(pid_arg.unwrap().deref()).context("nuprompt ps0"),
-        Some(p) if p.eq("ps1") && n_args == 4 => ps1(pid_arg.unwrap().deref(), extra_arg.unwrap().deref()).context("nuprompt ps1"),
-        _ => Err(anyhow!("nuprompt {} must be executed as either 'nuprompt ps0 <pid>' or 'nuprompt ps1 <pid> <exit code>'", VERSION))
+        [p, name] if p.eq(&"init") => {
+            let sh = shell::find(name.as_bytes())
+                .ok_or_else(|| anyhow!("unknown shell {:?}, expected one of {}", name, shell::names()))?;
+            if flag("--print-full-init") {
+                print_full_init(sh);
+            } else {
+                println!("{}", sh.init);
+            }
+            Ok(())
+        },
+        [p, pid] if p.eq(&"ps0") => ps0(pid, None, sequence()).context("nuprompt ps0"),
+        [p] if p.eq(&"warm") && flag("--print-hooks") => {
+            println!("{}", warm::HOOKS_SNIPPET);
+            Ok(())
+        },
+        [p] if p.eq(&"warm") => warm::run(&std::env::current_dir()?).context("nuprompt warm"),
+        [p, path] if p.eq(&"warm") => warm::run(Path::new(path)).context("nuprompt warm"),
+        [p, name] if p.eq(&"completions") => {
+            let script = completions::script(&name.to_string_lossy())
+                .ok_or_else(|| anyhow!("no completions for {:?}, expected one of {}", name, completions::SHELLS.join(", ")))?;
+            println!("{}", script);
+            Ok(())
+        },
+        [p] if p.eq(&"git-info") => git_info(&std::env::current_dir()?).context("nuprompt git-info"),
+        [p, path] if p.eq(&"git-info") => git_info(Path::new(path)).context("nuprompt git-info"),
+        [p] if p.eq(&"doctor") => doctor(),
+        [p, source, path] if p.eq(&"import") => import::run(&source.to_string_lossy(), Path::new(path)).context("nuprompt import"),
+        [p, paths @ ..] if p.eq(&"scan") && !paths.is_empty() => scan(paths, flag("--json")).context("nuprompt scan"),
+        [p, query @ ..] if p.eq(&"dirs") => dirhistory::print(&query.iter().map(|q| q.to_string_lossy().into_owned()).collect::<Vec<_>>()).context("nuprompt dirs"),
+        [p] if p.eq(&"use") => use_profile(None).context("nuprompt use"),
+        [p, name] if p.eq(&"use") => use_profile(Some(name)).context("nuprompt use"),
+        [p] if p.eq(&"version") => {
+            version::print(flag("--json"));
+            Ok(())
+        },
+        [p] if p.eq(&"updates") => sysupdate::refresh().context("nuprompt updates"),
+        [p] if p.eq(&"self-update") => update::run(flag("--check")).context("nuprompt self-update"),
+        [p] if p.eq(&"ps2") => ps2(shell, color_choice(flag_value("--color"))?).context("nuprompt ps2"),
+        [p, pid] if p.eq(&"read") => publish::read(pid).context("nuprompt read"),
+        [p, pid, command] if p.eq(&"ps0") => ps0(pid, Some(command), sequence()).context("nuprompt ps0"),
+        #[cfg(target_os = "linux")]
+        [p, path] if p.eq(&"watch") => watch::run(path).context("nuprompt watch"),
+        [p, pid, exit_code] if p.eq(&"ps1") => {
+            let opts = Ps1Options {
+                shell,
+                color: color_choice(flag_value("--color"))?,
+                debug_timings: flag("--debug-timings"),
+                minimal: flag("--minimal"),
+                history: flag_value("--history").filter(|h| !h.is_empty() && h.iter().all(u8::is_ascii_digit)).map(<[u8]>::to_vec),
+                git_from_env: flag("--from-env") || std::env::var_os(cienv::FROM_ENV_ENVVAR).is_some(),
+                custom: flags.iter().filter_map(|f| custom_segment(f.as_bytes())).collect(),
+                shell_mode: shellmode::ShellMode::from_hook(flag_value("--argv0"), flag_value("--shell-flags")),
+            };
+            ps1(pid, exit_code, prev.unwrap_or_default(), timings, &opts).context("nuprompt ps1")
+        },
+        _ => Err(anyhow!("nuprompt {} must be executed as either 'nuprompt init <{}> [--print-full-init]', 'nuprompt ps0 <pid> [command]', 'nuprompt ps2 [--shell=<shell>] [--color=always|never|auto]', 'nuprompt read <pid>', 'nuprompt warm [path] [--print-hooks]', 'nuprompt git-info [path]', 'nuprompt doctor', 'nuprompt import <starship|p10k> <file>', 'nuprompt scan <dir>... [--json]', 'nuprompt dirs [query...]', 'nuprompt use [profile]', 'nuprompt version [--json]', 'nuprompt updates', 'nuprompt self-update [--check]', 'nuprompt completions <bash|zsh|fish>' or 'nuprompt ps1 <pid> <exit code> [--shell=<shell>] [--debug-timings] [--minimal] [--history=<n>] [--from-env] [--segment=<name>=<value>] [--argv0=<$0>] [--shell-flags=<$->] [--color=always|never|auto]'", VERSION, shell::names()))
     }
 }
 
-fn ps0(raw_pid: &OsStr) -> Result<(), anyhow::Error> {
-    write_start_time(raw_pid)?;
+/// Print the hook code followed by a warning for each problem that would break it. The shell evaluates this once at
+/// startup, so the warnings appear once per shell rather than on every prompt, and so does the background query.
+fn print_full_init(sh: &shell::Shell) {
+    // the shells that eval the init keep the background for the session, and it must be set before the ps2 hook runs
+    if sh.export.is_some() && std::env::var_os(theme::BG_ENVVAR).is_none() {
+        if let Some(bg) = theme::query() {
+            let _ = shell::write_exports(sh, &[(theme::BG_ENVVAR, Some(bg.name().as_bytes()))], &mut std::io::stdout());
+            println!();
+        }
+    }
+    println!("{}", sh.full_init);
+    for problem in initcheck::problems() {
+        println!("{}", sh.warning.replace("MESSAGE", &initcheck::sanitize(&problem)));
+    }
+}
+
+/// Pick the config profile for the shell that runs us, which is our parent, or go back to the default without a name.
+/// The choice is kept in the state file of the shell and applies from the next prompt.
+fn use_profile(name: Option<&OsStr>) -> Result<(), anyhow::Error> {
+    let profile = match name {
+        Some(name) => {
+            let name = name.to_string_lossy().into_owned();
+            let known = config::profile_names();
+            if !known.contains(&name) {
+                return Err(anyhow!("unknown profile {:?}, the config has {}", name, match known.is_empty() {
+                    true => String::from("no profiles"),
+                    false => known.join(", "),
+                }));
+            }
+            Some(name.into_bytes())
+        },
+        None => None,
+    };
+    // SAFETY: getppid has no preconditions and cannot fail.
+    let shell_pid = OsString::from(unsafe { libc::getppid() }.to_string());
+    state::modify(&shell_pid, |st| state::State { profile, ..st })
+}
+
+fn ps0(raw_pid: &OsStr, command: Option<&OsStr>, sequence: Option<u64>) -> Result<(), anyhow::Error> {
+    let command = command.map(|c| c.as_bytes().trim_ascii().to_vec());
+    // a read-only or full temp dir, as in some containers, leaves the prompt without the elapsed time rather than
+    // printing an error before every command. 'nuprompt doctor' and the full init report it.
+    if let Err(e) = write_start_time(&clock::SystemClock, raw_pid, command, sequence) {
+        debug!("could not write the start time to {}, elapsed times will be missing: {:#}", state::state_dir().display(), e);
+    }
     Ok(())
 }
 
+/// Print the problems that the full init warns about, for when the warnings scrolled by or the environment changed
+/// since the shell started, such as a new $TMPDIR. Exits 1 if there are any.
+fn doctor() -> Result<(), anyhow::Error> {
+    println!("state dir: {}", state::state_dir().display());
+    if let Some(phase) = std::env::current_dir().ok().and_then(|d| watchdog::last_cut_off(&d)) {
+        println!("the last prompt here that ran out of time was in {}", phase);
+    }
+    let problems = initcheck::problems();
+    if problems.is_empty() {
+        println!("no problems found");
+        return Ok(());
+    }
+    for problem in problems {
+        println!("problem: {}", problem);
+    }
+    std::process::exit(1)
+}
 
-fn ps1(raw_pid: &OsStr, exit_code: &OsStr) -> Result<(), anyhow::Error> {
+/// Print the continuation prompt, which the init code of the shell sets once.
+fn ps2(shell: &'static shell::Shell, color: ColorChoice) -> Result<(), anyhow::Error> {
+    if shell.continuation_prefix.is_none() {
+        return Err(anyhow!("{} has no continuation prompt", shell.name));
+    }
+    let buf_writer = BufferWriter::stdout(color);
+    let mut buffer = buf_writer.buffer();
+    render::render_continuation(glyph::select(), theme::Background::from_env(), shell, &mut buffer)?;
+    buf_writer.print(&buffer)?;
+    Ok(())
+}
+
+/// Ps1Options holds the flags given to the ps1 subcommand.
+struct Ps1Options {
+    shell: &'static shell::Shell,
+    color: ColorChoice,
+    /// print the duration of each phase to stderr after the prompt
+    debug_timings: bool,
+    /// only show the exit code, elapsed time, and path
+    minimal: bool,
+    /// the history number of the next command as given by the shell
+    history: Option<Vec<u8>>,
+    /// take the git branch from the CI environment instead of the repo
+    git_from_env: bool,
+    /// the segments passed in by the shell hook, as names and values
+    custom: Vec<(Vec<u8>, Vec<u8>)>,
+    /// how the shell was started, from the name and flags the hook passes
+    shell_mode: Option<shellmode::ShellMode>,
+}
+
+/// Parse a `--segment=name=value` flag. Empty values are dropped so that the hook can pass a variable that isn't set.
+fn custom_segment(flag: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let spec = flag.strip_prefix(b"--segment=")?;
+    let split = spec.iter().position(|b| *b == b'=')?;
+    let (name, value) = (&spec[..split], &spec[split + 1..]);
+    (!name.is_empty() && !value.is_empty()).then(|| (name.to_vec(), value.to_vec()))
+}
+
+/// The exit code argument of ps1, or None when the last command succeeded.
+fn failed_exit_code(exit_code: &OsStr) -> Option<&OsStr> {
+    Some(exit_code).filter(|a| !a.is_empty() && !a.eq(&OsStr::new("0")))
+}
+
+/// Render the prompt. The state of the previous command was already taken by main, along with its time in the
+/// timings.
+fn ps1(raw_pid: &OsStr, exit_code: &OsStr, prev: state::State, mut timings: timing::Timings, opts: &Ps1Options) -> Result<(), anyhow::Error> {
+    let shell = opts.shell;
 
     // expect a status code as the first positional arg
-    let exit_code = Some(exit_code)
-        .filter(|a| !a.is_empty() && !a.eq(&OsStr::new("0")));
+    let exit_code = failed_exit_code(exit_code);
 
     // cwd comes from libc or from the env var
-    let possible_cwd = std::env::current_dir().ok()
+    let possible_cwd = fault::check("cwd").and_then(|_| std::env::current_dir()).ok()
         .or_else(|| std::env::var_os(PWD_ENVVAR).map(PathBuf::from));
 
-    // try and read the start time of the previous command from a file on the system
-    let elapsed: Option<Duration> = read_elapsed_time(raw_pid)
-        .map_or_else(|e| {
-            debug!("error reading elapsed time from pid file: {}", e);
-            None
-        }, Some);
+    let elapsed: Option<Duration> = prev.start_ticks.and_then(|t| clock::elapsed_since(&clock::SystemClock, t));
+
+    let shown_elapsed = elapsed.filter(|_| segment::enabled("elapsed", true));
+    let mut ctx = render::PromptContext::new(exit_code.map(OsStr::to_os_string), shown_elapsed, possible_cwd.clone().map(shorted_path_buf));
+    ctx.glyphs = glyph::select();
+    ctx.background = theme::Background::from_env();
+    ctx.layout = layout::Layout::from_env();
+    ctx.part_colors = theme::part_colors();
+    ctx.decorations = layout::decorations();
+    if !ctx.layout.right.is_empty() {
+        ctx.columns = width::terminal_columns();
+    }
+    ctx.elapsed_scale = elapsed_scale();
+    // the start ticks are monotonic, so the wall clock start is worked out from the duration
+    if let (Some(elapsed), true) = (shown_elapsed, segment::enabled("start_time", std::env::var_os(SHOW_START_ENVVAR).is_some())) {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        ctx.started_at = clock::local_hour_minute(now.saturating_sub(elapsed.as_secs()) as i64);
+    }
+    if let Ok(raw) = std::env::var(SHELL_ERROR_COLOR_ENVVAR) {
+        match Color::from_str(raw.trim()) {
+            Ok(color) => ctx.shell_error_color = color,
+            Err(e) => debug!("ignoring invalid {}: {}", SHELL_ERROR_COLOR_ENVVAR, e),
+        }
+    }
+    ctx.native_cwd = std::env::var_os(NATIVE_CWD_ENVVAR).is_some();
+    if let (Some(p), true) = (&possible_cwd, hyperlink::enabled()) {
+        ctx.cwd_link = Some(hyperlink::file_url(host::hostname().unwrap_or_default().as_bytes(), p));
+    }
+    if exit_code.is_some() {
+        ctx.exit_streak = prev.failures.unwrap_or(0);
+    }
+    if let (Some(code), Ok(list)) = (exit_code, std::env::var(exitcodes::QUIET_ENVVAR)) {
+        ctx.exit_quiet = exitcodes::is_quiet(&code.to_string_lossy(), &list);
+    }
+    // audit mode is not a segment, so neither minimal mode nor the segment lists can turn it off
+    if std::env::var_os(AUDIT_ENVVAR).is_some() {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        ctx.audit = clock::local_iso8601(now as i64).zip(host::hostname());
+    }
+    let minimal = opts.minimal || std::env::var_os(MINIMAL_ENVVAR).is_some();
+    let notification = match !minimal && segment::enabled("notify", std::env::var_os(notify::NOTIFY_ENVVAR).is_some()) {
+        true => notify::message(prev.command.as_deref(), elapsed, exit_code.map(OsStr::as_bytes)),
+        false => None,
+    };
+    let alert = match !minimal && segment::enabled("alert", std::env::var_os(notify::ALERT_ENVVAR).is_some()) {
+        true => notify::failure_alert(prev.command.as_deref(), elapsed, exit_code.map(OsStr::as_bytes)),
+        false => None,
+    };
+    // segments that have been too slow in this directory are skipped, and their names shown instead
+    let latency = possible_cwd.as_deref().filter(|_| !minimal).and_then(slo::Tracker::load);
+    if let Some(tracker) = &latency {
+        segment::throttle(tracker.throttled().to_vec());
+        ctx.throttled = tracker.throttled().to_vec();
+    }
+    // a prompt that runs out of time skips what is left, and one stuck in a step that can't be stopped is printed as
+    // it is before any segment ran
+    if let (false, Some(limit)) = (minimal, watchdog::limit()) {
+        let mut fallback = BufferWriter::stdout(opts.color).buffer();
+        render::render(&ctx, shell, &mut fallback)?;
+        watchdog::arm(limit, fallback.into_inner(), possible_cwd.as_deref());
+    }
+    // the latency budget learns of a segment that got the prompt killed from its time until then
+    let _latency_miss = possible_cwd.clone().filter(|_| latency.is_some()).map(|cwd| watchdog::on_kill(move |phase, took| {
+        if let Err(e) = slo::Tracker::load(&cwd).map_or(Ok(()), |tracker| tracker.record(&[(phase, took)])) {
+            debug!("failed to record segment latency: {}", e);
+        }
+    }));
+    if !minimal {
+        gather(&mut ctx, possible_cwd.as_deref(), prev, opts.git_from_env, &mut timings)?;
+        ctx.history = opts.history.clone().filter(|_| segment::enabled("history", std::env::var_os(SHOW_HISTORY_ENVVAR).is_some()));
+        if segment::enabled("custom", true) {
+            ctx.custom = opts.custom.clone();
+        }
+        ctx.shell_mode = opts.shell_mode.filter(|_| segment::enabled("shell_mode", std::env::var_os(shellmode::SHELL_MODE_ENVVAR).is_some()));
+    }
+    if let Some(path_opts) = pathtrim::Options::from_env() {
+        let shortened = pathtrim::shorten(ctx.cwd.as_os_str().as_bytes(), &path_opts, ctx.repo_root, ctx.glyphs.ellipsis);
+        ctx.cwd = PathBuf::from(OsStr::from_bytes(&shortened));
+    }
+
+    let color = opts.color;
+
+    // hints for well known exit codes go straight to stderr
+    if let (Some(exit_code), false, true) = (exit_code, minimal, segment::enabled("hints", std::env::var_os(hints::HINTS_ENVVAR).is_some())) {
+        let exit_code = exit_code.to_string_lossy();
+        if let Some(hint) = timings.span("hints", || hints::hint_for(&exit_code, ctx.glyphs)) {
+            hints::print_hint(&exit_code, &hint, color)?;
+        }
+    }
+
+    // long running commands can ask the terminal for a desktop notification
+    // a failure at the end of a long command can also ring the bell, without a second notification for the same command
+    match alert {
+        Some(notify::Alert::Bell) => notify::bell()?,
+        Some(notify::Alert::Notification(message)) if notification.is_none() => notify::send(&message)?,
+        _ => {},
+    }
+    if let Some(message) = notification {
+        notify::send(&message)?;
+    }
+
+    watchdog::disarm();
+    // minimal mode renders on the stack, falling back to the heap buffer for a prompt that doesn't fit
+    let printed = minimal && timings.span("render", || fastpath::print(&ctx, shell, color))?;
+    if !printed {
+        let buf_writer = BufferWriter::stdout(color);
+        let mut buffer = buf_writer.buffer();
+        timings.span("render", || render::render(&ctx, shell, &mut buffer))?;
+        buf_writer.print(&buffer)?;
+    }
+
+    // bash and zsh eval the output, so the scan results can be handed to the rest of the session
+    if std::env::var_os(EXPORT_GIT_ENVVAR).is_some() {
+        let dirty = ctx.git_bits.as_ref().map(|gb| match gb.index_modified + gb.worktree_modified + gb.untracked_files {
+            0 => b"0".as_slice(),
+            _ => b"1".as_slice(),
+        });
+        let vars = [
+            ("NUPROMPT_GIT_BRANCH", ctx.git_bits.as_ref().map(|gb| gb.head_ref.as_slice())),
+            ("NUPROMPT_GIT_DIRTY", dirty),
+        ];
+        shell::write_exports(shell, &vars, &mut std::io::stdout().lock())?;
+    }
+
+    // a failure to publish must not break the prompt
+    if std::env::var_os(publish::PUBLISH_ENVVAR).is_some() {
+        if let Err(e) = timings.span("publish", || publish::write(raw_pid, &ctx)) {
+            debug!("failed to publish prompt context: {}", e);
+        }
+    }
+    if let (Some(p), true) = (&possible_cwd, std::env::var_os(dirhistory::DIR_HISTORY_ENVVAR).is_some()) {
+        if let Err(e) = timings.span("dirs", || dirhistory::record(p)) {
+            debug!("failed to record directory history: {}", e);
+        }
+    }
+    if let (Some(p), Some(phase)) = (&possible_cwd, watchdog::culprit()) {
+        watchdog::record(p, phase);
+    }
+    if let Some(tracker) = latency {
+        if let Err(e) = tracker.record(timings.spans()) {
+            debug!("failed to record segment latency: {}", e);
+        }
+    }
+    if opts.debug_timings {
+        eprintln!("{}", timings.summary());
+    }
+    #[cfg(feature = "trace")]
+    trace::write(&timings);
+    Ok(())
+}
+
+/// fill in everything beyond the exit code, elapsed time, and path. This is what minimal mode skips.
+fn gather(ctx: &mut render::PromptContext, possible_cwd: Option<&Path>, prev: state::State, git_from_env: bool, timings: &mut timing::Timings) -> Result<(), anyhow::Error> {
+    ctx.command = prev.command
+        .filter(|c| !c.is_empty() && segment::enabled("command", std::env::var_os(SHOW_COMMAND_ENVVAR).is_some()));
+
+    // highlight the trailing directories that differ from where the previous command started
+    if let (Some(p), Some(prev_cwd), true) = (possible_cwd, prev.cwd, segment::enabled("cwd_diff", std::env::var_os(CWD_DIFF_ENVVAR).is_some())) {
+        ctx.raw_cwd_changed = raw_changed_components(Path::new(OsStr::from_bytes(&prev_cwd)), p);
+    }
+
+    // rules can color and mark the places that deserve care, like system directories
+    ctx.cwd_style = possible_cwd.and_then(cwdrule::style);
+
+    // network and encrypted file systems get a marker, and we skip the untracked file scan on network mounts
+    let fs_kind = possible_cwd.and_then(|p| timings.span("fstype", || fstype::detect(p)));
+    let include_untracked = !fs_kind.is_some_and(|k| k.is_network());
+    ctx.fs_kind = fs_kind.filter(|_| segment::enabled("fs", true));
+
+    // hardened systems label the shell with the security module policy it runs under
+    if segment::enabled("security", std::env::var_os(lsm::SECURITY_ENVVAR).is_some()) {
+        ctx.security = timings.span("security", lsm::current);
+    }
+
+    // a bookmarked directory is shown by name, with the real path moved to the terminal title
+    let bookmark = possible_cwd
+        .filter(|_| segment::enabled("bookmark", true))
+        .and_then(|p| timings.span("bookmark", || bookmark::resolve(p)));
+    if let Some((name, rest)) = bookmark {
+        ctx.title = possible_cwd.map(Path::to_path_buf);
+        ctx.cwd = PathBuf::from(format!("@{}", name));
+        if !rest.as_os_str().is_empty() {
+            ctx.cwd.push(rest);
+        }
+    }
 
     // try and parse git status if were in a repo
-    let start_time = coarsetime::Instant::now();
-    let (cwd, git_bits): (PathBuf, Option<GitBits>) = match possible_cwd {
-        Some(p) => {
-            match std::env::var_os(NO_GIT_ENVVAR) {
-                Some(_) => (shorted_path_buf(p), None),
-                None => {
-                    debug!("looking for git repo from working directory: {:?}", p);
-                    let ceil: &[PathBuf] = &[];
-                    match Repository::open_ext(&p, git2::RepositoryOpenFlags::empty(), ceil) {
-                        Ok(r) => (shorted_path_buf(p), Some(GitBits::from_repo(&r)?)),
-                        Err(e) => {
-                            debug!("could not open repository: {:?}", e);
-                            (shorted_path_buf(p), None)
-                        }
-                    }
+    let git_enabled = segment::enabled("git", std::env::var_os(NO_GIT_ENVVAR).is_none());
+    if git_enabled && git_from_env {
+        ctx.git_bits = cienv::git_bits();
+    } else if let (Some(p), true) = (possible_cwd, git_enabled) {
+        debug!("looking for git repo from working directory: {:?}", p);
+        match timings.span("git_open", || git::open(p)) {
+            Ok(r) => {
+                match git_bits(&r, include_untracked, timings) {
+                    Ok(gb) => ctx.git_bits = Some(gb),
+                    // the rest of the prompt is still worth showing without the status
+                    Err(e) => debug!("could not read git status: {:#}", e),
                 }
-            }
-        },
-        None => (PathBuf::new(), None),
-    };
-    debug!("scanned for git repo in {:?}", start_time.elapsed());
+                if hyperlink::enabled() {
+                    ctx.branch_link = timings.span("git_remote", || hyperlink::branch_url(&r));
+                }
+                ctx.repo_root = r.workdir().map(|w| p.components().count().saturating_sub(w.components().count()));
+                // a bookmark already names the directory
+                let repo_style = std::env::var(pathtrim::STYLE_ENVVAR).is_ok_and(|s| s.trim() == "repo");
+                if let Some(relative) = r.workdir().filter(|_| repo_style && ctx.title.is_none()).and_then(|w| pathtrim::repo_relative(w, p)) {
+                    ctx.cwd = relative;
+                    ctx.native_cwd = false;
+                }
+                ctx.git_counts = std::env::var_os(git::COUNTS_ENVVAR).is_some();
+                ctx.git_partially_staged = std::env::var_os(git::PARTIALLY_STAGED_ENVVAR).is_some();
+            },
+            // libgit2 refuses repos owned by another user, like git's safe.directory check
+            Err(e) if e.code() == git2::ErrorCode::Owner => {
+                debug!("repository is not trusted: {}", e);
+                ctx.git_untrusted = true;
+            },
+            Err(e) => debug!("could not open repository: {:?}", e),
+        }
+    }
+
+    ctx.branch_color = ctx.git_bits.as_ref().and_then(|gb| git::branch_color(&gb.head_ref));
+
+    // project descriptions from the language detectors
+    ctx.projects = possible_cwd
+        .filter(|_| segment::enabled("detect", true))
+        .map(|p| timings.span("detect", || detect::detect(p)))
+        .unwrap_or_default();
+
+    // problems in the shell environment
+    if segment::enabled("health", true) {
+        ctx.health = timings.span("health", health::markers);
+    }
+
+    // credentials exported to every command the shell runs
+    if segment::enabled("secrets", std::env::var_os(secrets::SECRETS_ENVVAR).is_some()) {
+        ctx.secrets = timings.span("secrets", secrets::exported_count);
+    }
+
+    // pending package updates and reboots, from a cache that is refreshed in the background
+    if segment::enabled("updates", std::env::var_os(sysupdate::UPDATES_ENVVAR).is_some()) {
+        ctx.updates = timings.span("updates", sysupdate::cached);
+    }
 
     // the username or uid:guid
-    let username = users::get_current_username()
-        .unwrap_or_else(|| OsString::from(format!("{}:{}", users::get_current_uid(), users::get_current_gid())));
+    let username = timings.span("user", || users::get_current_username()
+        .unwrap_or_else(|| OsString::from(format!("{}:{}", users::get_current_uid(), users::get_current_gid()))));
     debug!("found user: {:?}", username);
+    ctx.username = Some(username).filter(|_| segment::enabled("user", true)).map(|u| username::display(&u));
 
-    // prepare the buffered writer
-    let buf_writer = BufferWriter::stdout(if stdin().is_terminal() { ColorChoice::Auto } else { ColorChoice::Never});
-    let mut buffer = buf_writer.buffer();
-    buffer.write_all(b"PS1='[")?;
-    if let Some(exit_code) = exit_code {
-        buffer.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
-        buffer.write_all(exit_code.as_bytes())?;
-        buffer.write_all(b" ")?;
-    }
-    if let Some(elapsed) = elapsed {
-        buffer.set_color(ColorSpec::new().set_fg(Some(Color::Cyan)))?;
-        write!(buffer, "{:.2}s ", elapsed.as_f64())?;
-    }
-    buffer.set_color(ColorSpec::new().set_fg(Some(Color::Cyan)).set_bold(true).set_intense(true))?;
-    buffer.write_all(username.as_bytes())?;
-    buffer.write_all(b" ")?;
-    if let Some(git_bits) = git_bits {
-        buffer.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_intense(true))?;
-        write_with_escaped_quote(git_bits.head_ref.as_bytes(), &mut buffer)?;
-        buffer.set_color(&ColorSpec::default())?;
-        git_bits.write_elements(&mut buffer)?;
-        buffer.write_all(b" ")?;
-    }
-    buffer.set_color(&ColorSpec::default())?;
-    write_with_escaped_quote(cwd.as_os_str().as_bytes(), &mut buffer)?;
-    buffer.write_all(b" \xE2\x9F\xAB '")?;
-    buf_writer.print(&buffer)?;
+    // the hostname decides the color of the username, and is shown abbreviated over ssh or when asked for
+    let hostname = timings.span("host", host::hostname);
+    ctx.user_color = hostname.as_ref()
+        .map(|h| host::host_color(h.as_bytes()))
+        .unwrap_or(Color::Cyan);
+    if segment::enabled("host", host::in_ssh_session() || std::env::var_os(host::SHOW_HOST_ENVVAR).is_some()) {
+        ctx.host = hostname.map(|h| host::abbreviate(h.as_bytes()));
+    }
     Ok(())
 }
 
-/// write some raw bytes but make sure we escape any single quotes.
-fn write_with_escaped_quote(input: &[u8], mut w: impl Write) -> Result<(), std::io::Error> {
-    for (i, x) in input.split(|u| *u == b'\'').enumerate() {
-        if i > 0 {
-            w.write_all(b"'\\''")?;
+/// gather everything we show about the repo.
+fn git_bits(r: &Repository, include_untracked: bool, timings: &mut timing::Timings) -> Result<git::GitBits, anyhow::Error> {
+    let mut gb = timings.span("git_status", || git_status(r, include_untracked))?;
+    let configured = std::env::var_os(git::DEFAULT_BRANCH_ENVVAR);
+    if segment::enabled("default_branch", configured.is_some()) {
+        let configured = configured.unwrap_or_default();
+        gb.default_branch_ahead = timings.span("git_default_branch", || git::default_branch_ahead(r, &configured.to_string_lossy()));
+    }
+    gb.operation = timings.span("git_operation", || git::operation(r));
+    if gb.operation.is_some() && segment::enabled("operation_age", true) {
+        gb.operation_age = git::operation_age(r);
+    }
+    gb.sparse_checkout = git::sparse_checkout(r);
+    gb.partial_clone = git::partial_clone(r);
+    gb.shallow = git::shallow(r);
+    if segment::enabled("stale_lock", true) {
+        gb.stale_lock = git::stale_lock(r);
+    }
+    if segment::enabled("unpushed", std::env::var_os(unpushed::UNPUSHED_ENVVAR).is_some()) {
+        gb.unpushed_branches = timings.span("git_unpushed", || unpushed::count(r));
+    }
+    if segment::enabled("head_pushed", std::env::var_os(unpushed::HEAD_ENVVAR).is_some()) {
+        gb.head_unpushed = timings.span("git_head_pushed", || unpushed::head_unpushed(r));
+    }
+    if segment::enabled("main_worktree", std::env::var_os(git::MAIN_WORKTREE_ENVVAR).is_some()) {
+        gb.main_worktree_dirty = timings.span("git_main_worktree", || git::main_worktree_dirty(r));
+    }
+    if segment::enabled("git_email", std::env::var_os(gitemail::GIT_EMAIL_ENVVAR).is_some()) {
+        gb.email = timings.span("git_email", || gitemail::differing(r));
+    }
+    if segment::enabled("signing", std::env::var_os(signing::SIGNING_ENVVAR).is_some()) {
+        gb.signing = timings.span("git_signing", || signing::signing_status(r));
+    }
+    Ok(gb)
+}
+
+/// Print the branch and the `:sdu` dirty letters of the repo at the path, without color or prompt framing, for
+/// scripts and editor status lines. The status comes from the same watcher, strategy, and backend as the prompt.
+fn git_info(path: &Path) -> Result<(), anyhow::Error> {
+    let r = match git::open(path) {
+        Ok(r) => r,
+        // not being in a repo is the common answer, not an error worth printing into a status line
+        Err(e) if e.code() == git2::ErrorCode::NotFound => std::process::exit(1),
+        Err(e) => return Err(e.into()),
+    };
+    let include_untracked = !fstype::detect(path).is_some_and(|k| k.is_network());
+    let gb = git_status(&r, include_untracked)?;
+    let mut out = gb.head_ref.clone();
+    if gb.index_modified > 0 || gb.worktree_modified > 0 || gb.untracked_files > 0 || gb.worktree_unknown {
+        out.push(b':');
+        let partially_staged = if std::env::var_os(git::PARTIALLY_STAGED_ENVVAR).is_some() { gb.partially_staged } else { 0 };
+        for (letter, n) in [(b's', gb.index_modified), (b'd', gb.worktree_modified), (b'u', gb.untracked_files), (b'p', partially_staged)] {
+            if n > 0 {
+                out.push(letter);
+            }
+        }
+        if gb.worktree_unknown {
+            out.push(b'?');
         }
-        w.write_all(x)?;
     }
+    out.push(b'\n');
+    std::io::stdout().lock().write_all(&out)?;
     Ok(())
 }
 
-fn prev_start_file_path(raw_pid: &OsStr) -> PathBuf {
-    std::env::temp_dir().join(format!("NUPROMPT_{}_prev_start", raw_pid.to_string_lossy()))
+/// Print the git segment of each directory as the prompt would show it, one line each in the order given, or a JSON
+/// object each with --json. The directories are scanned in parallel, and those outside a repo get an empty segment.
+fn scan(paths: &[&OsStr], json: bool) -> Result<(), anyhow::Error> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get()).min(paths.len()).max(1);
+    let mut results: Vec<(usize, Result<Option<git::GitBits>, anyhow::Error>)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads).map(|t| scope.spawn(move || {
+            paths.iter().enumerate().skip(t).step_by(threads).map(|(i, p)| (i, scan_one(Path::new(p)))).collect::<Vec<_>>()
+        })).collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap_or_default()).collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    let glyphs = glyph::select();
+    let counts = std::env::var_os(git::COUNTS_ENVVAR).is_some();
+    let partially_staged = std::env::var_os(git::PARTIALLY_STAGED_ENVVAR).is_some();
+    let mut stdout = std::io::stdout().lock();
+    for (i, result) in results {
+        let path = paths[i];
+        let gb = result.unwrap_or_else(|e| {
+            eprintln!("nuprompt scan: {}: {:#}", path.to_string_lossy(), e);
+            None
+        });
+        if json {
+            let mut out = String::from("{");
+            publish::field(&mut out, "path", &publish::json_string(&path.to_string_lossy()));
+            publish::field(&mut out, "git", &gb.as_ref().map_or("null".to_owned(), publish::git_json));
+            out.push('}');
+            writeln!(stdout, "{}", out)?;
+            continue;
+        }
+        let mut segment = NoColor::new(vec![]);
+        if let Some(gb) = gb {
+            let mut w = shell::PromptWriter { shell: &shell::PLAIN, inner: &mut segment, background: theme::Background::Dark, zero_width: false, fg: None };
+            w.write_literal(&gb.head_ref)?;
+            gb.write_elements(&mut w, counts, partially_staged, glyphs)?;
+        }
+        stdout.write_all(path.as_bytes())?;
+        stdout.write_all(b"\t")?;
+        stdout.write_all(segment.get_ref())?;
+        stdout.write_all(b"\n")?;
+    }
+    Ok(())
 }
 
-fn read_elapsed_time(raw_pid: &OsStr) -> Result<Duration, anyhow::Error> {
-    let tf = prev_start_file_path(raw_pid);
-    let contents = fs::read(&tf)?;
-    fs::remove_file(&tf)?;
-    let ticks = u64::from_be_bytes(contents[..8].try_into()?);
-    let now_ticks = coarsetime::Instant::now().as_ticks();
-    debug!("read start time from pid file: {} now={}", ticks, now_ticks);
-    Ok(Duration::from_ticks(now_ticks - ticks))
+fn scan_one(path: &Path) -> Result<Option<git::GitBits>, anyhow::Error> {
+    let r = match git::open(path) {
+        Ok(r) => r,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let include_untracked = !fstype::detect(path).is_some_and(|k| k.is_network());
+    Ok(Some(git_bits(&r, include_untracked, &mut timing::Timings::new())?))
 }
 
-fn write_start_time(raw_pid: &OsStr) -> Result<(), anyhow::Error>{
-    let tf = prev_start_file_path(raw_pid);
-    let now_ticks = coarsetime::Instant::now().as_ticks();
-    fs::write(&tf, now_ticks.to_be_bytes())?;
-    debug!("wrote start time to pid file: now={}", now_ticks);
-    Ok(())
+/// scan the repo for its status, or use the answer precomputed by a background watcher if that is enabled, or by a
+/// recent `nuprompt warm`. Otherwise the size of the repo decides how much of the status we compute, and the scan is
+/// done by libgit2 or the git CLI.
+fn git_status(r: &Repository, include_untracked: bool) -> Result<git::GitBits, anyhow::Error> {
+    fault::check("git_status")?;
+    #[cfg(target_os = "linux")]
+    if let (Some(_), Some(workdir)) = (std::env::var_os(watch::WATCH_ENVVAR), r.workdir()) {
+        if let Some(gb) = watch::cached_or_spawn(workdir) {
+            debug!("using status from watcher");
+            return Ok(gb);
+        }
+    }
+    // a `nuprompt warm` from a git hook may have just done the scan for us
+    if let Some(gb) = r.workdir().and_then(|w| warm::take(r, w, include_untracked)) {
+        debug!("using status from nuprompt warm");
+        return Ok(gb);
+    }
+    let strategy = strategy::choose(r);
+    let start = std::time::Instant::now();
+    // a scan the watchdog kills is over any budget, and the next prompt must not try it again
+    let _downgrade = r.workdir().map(Path::to_path_buf).map(|workdir| watchdog::on_kill(move |_, took| {
+        strategy::observe_workdir(&workdir, strategy, took);
+    }));
+    if fault::injected("git_stall") && strategy == strategy::Strategy::Full {
+        std::thread::sleep(std::time::Duration::from_secs(2));
+    }
+    let gb = gitcli::status(r, include_untracked, strategy)?;
+    strategy::observe(r, strategy, start.elapsed());
+    Ok(gb)
 }
 
-/// GitBits holds the result of scanning the git repo for current status.
-struct GitBits {
-    head_ref: String,
-    index_modified: bool,
-    worktree_modified: bool,
-    untracked_files: bool,
-}
-
-impl GitBits {
-
-    fn from_repo(r: &Repository) -> Result<GitBits, anyhow::Error> {
-        let short_ref = r.head()
-            .map(|h| h.shorthand().unwrap().to_owned())
-            .unwrap_or_else(|e| {
-                debug!("error reading head ref: {}", e);
-               String::from("NO HEAD")
-            });
-        let mut gb = GitBits{
-            head_ref: short_ref,
-            index_modified: false,
-            worktree_modified: false,
-            untracked_files: false,
-        };
-        let statuses = r.statuses(Some(StatusOptions::new()
-            .include_ignored(false)
-            .include_untracked(true)
-            .exclude_submodules(true)
-            .include_unreadable(false)))?;
-        let wt_modified: Status = Status::WT_MODIFIED | Status::WT_DELETED | Status::WT_TYPECHANGE | Status::WT_RENAMED;
-        let index_modified: Status = Status::INDEX_NEW | Status::INDEX_MODIFIED | Status::INDEX_TYPECHANGE | Status::INDEX_RENAMED | Status::INDEX_DELETED;
-        for x in statuses.iter() {
-            debug!("git status {:?}: {:?}", x.path(), x.status());
-            let st = x.status();
-            if st.intersects(wt_modified) {
-                gb.worktree_modified = true;
-            }
-            if st.intersects(index_modified) {
-                gb.index_modified = true;
-            }
-            if st.contains(Status::WT_NEW) {
-                gb.untracked_files = true;
-            }
-        }
-        Ok(gb)
+/// Decide whether to write colors. Stdout is always captured by the shell hook, so in auto mode we look at stdin and
+/// stderr to find out whether the prompt ends up on a terminal, and respect $NO_COLOR and a dumb $TERM.
+fn color_choice(flag: Option<&[u8]>) -> Result<ColorChoice, anyhow::Error> {
+    match flag {
+        Some(b"always") => return Ok(ColorChoice::Always),
+        Some(b"never") => return Ok(ColorChoice::Never),
+        Some(b"auto") | None => {},
+        Some(other) => return Err(anyhow!("unknown color mode {:?}, expected one of always, never, auto", OsStr::from_bytes(other))),
     }
+    let no_color = std::env::var_os(NO_COLOR_ENVVAR).is_some_and(|v| !v.is_empty());
+    let dumb = std::env::var_os(TERM_ENVVAR).is_none_or(|t| t.is_empty() || t.eq("dumb"));
+    let terminal = stdin().is_terminal() || stderr().is_terminal();
+    debug!("color detection: no_color={} dumb={} terminal={}", no_color, dumb, terminal);
+    Ok(if !no_color && !dumb && terminal { ColorChoice::Always } else { ColorChoice::Never })
+}
 
-    fn write_elements(&self, mut w: impl Write) -> Result<(), std::io::Error> {
-        if self.index_modified || self.worktree_modified || self.untracked_files {
-            w.write_all(b":")?;
-            if self.index_modified {
-                w.write_all(b"s")?;
-            }
-            if self.worktree_modified {
-                w.write_all(b"d")?;
-            }
-            if self.untracked_files {
-                w.write_all(b"u")?;
-            }
+/// Read the two elapsed time color thresholds in seconds, e.g. `1,10`.
+fn elapsed_scale() -> (f64, f64) {
+    let Ok(raw) = std::env::var(ELAPSED_SCALE_ENVVAR) else {
+        return render::DEFAULT_ELAPSED_SCALE;
+    };
+    match raw.split_once(',').map(|(a, b)| (a.trim().parse(), b.trim().parse())) {
+        Some((Ok(a), Ok(b))) if a <= b => (a, b),
+        _ => {
+            debug!("ignoring invalid {}: {:?}", ELAPSED_SCALE_ENVVAR, raw);
+            render::DEFAULT_ELAPSED_SCALE
         }
-        Ok(())
     }
+}
 
+fn write_start_time(clock: &dyn clock::Clock, raw_pid: &OsStr, command: Option<Vec<u8>>, sequence: Option<u64>) -> Result<(), anyhow::Error>{
+    let now_ticks = clock.now_ticks();
+    // the command starts in the directory the previous prompt was shown in, so remember it for the next prompt
+    let cwd = std::env::current_dir().ok().map(|p| p.into_os_string().into_vec());
+    state::modify(raw_pid, |prev| {
+        debug!("writing start time to pid file: now={} sequence={:?}", now_ticks, sequence);
+        prev.started(now_ticks, sequence, command, cwd)
+    })
 }
 
+/// Count the trailing components of the current path that are not shared with the previous one. Both are the real
+/// paths, without the ~, bookmark, or trimming of the displayed one.
+fn raw_changed_components(prev: &Path, current: &Path) -> usize {
+    let common = prev.components().zip(current.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    current.components().count() - common
+}
 
 /// Replace a prefix of $HOME with ~ in the given path.
 fn shorted_path_buf(input: PathBuf) -> PathBuf {
@@ -0,0 +1,110 @@
+use std::io::{stderr, IsTerminal, Write};
+use coarsetime::Duration;
+use crate::shell::escape_non_printable;
+
+/// Commands that run for at least this many seconds trigger a desktop notification.
+pub const NOTIFY_ENVVAR: &str = "NUPROMPT_NOTIFY_SECS";
+/// The threshold when notifications are turned on through $NUPROMPT_ENABLE without one.
+const DEFAULT_THRESHOLD: f64 = 60.0;
+
+/// The comma separated command names that never notify. Interactive programs run for as long as you use them, so
+/// their duration says nothing about waiting.
+const NOTIFY_EXCLUDE_ENVVAR: &str = "NUPROMPT_NOTIFY_EXCLUDE";
+const DEFAULT_EXCLUDE: &str = "vi,vim,nvim,emacs,nano,less,more,man,ssh,mosh,top,htop,tmux,screen";
+
+/// Set to 777 to use the OSC 777 notification sequence instead of OSC 9.
+const NOTIFY_OSC_ENVVAR: &str = "NUPROMPT_NOTIFY_OSC";
+
+/// Commands that fail after running for at least this many seconds trigger an alert.
+pub const ALERT_ENVVAR: &str = "NUPROMPT_ALERT_FAILED_SECS";
+/// How to alert: `bell` (the default) rings the terminal bell, `osc` sends a notification like $NUPROMPT_NOTIFY_SECS.
+const ALERT_STYLE_ENVVAR: &str = "NUPROMPT_ALERT_STYLE";
+
+/// Alert is how a failed long command is brought to attention.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Alert {
+    Bell,
+    Notification(Vec<u8>),
+}
+
+/// Build the notification text for the previous command, if it ran for longer than the configured threshold and is
+/// not excluded.
+pub fn message(command: Option<&[u8]>, elapsed: Option<Duration>, exit_code: Option<&[u8]>) -> Option<Vec<u8>> {
+    let threshold: f64 = match std::env::var(NOTIFY_ENVVAR) {
+        Ok(raw) => raw.trim().parse().ok()?,
+        Err(_) => DEFAULT_THRESHOLD,
+    };
+    describe(command, elapsed?, exit_code, threshold)
+}
+
+/// Pick the alert for the previous command, if it failed after running for longer than the alert threshold and is not
+/// excluded.
+pub fn failure_alert(command: Option<&[u8]>, elapsed: Option<Duration>, exit_code: Option<&[u8]>) -> Option<Alert> {
+    let threshold: f64 = std::env::var(ALERT_ENVVAR).ok()?.trim().parse().ok()?;
+    let message = describe(command, elapsed?, Some(exit_code?), threshold)?;
+    match std::env::var(ALERT_STYLE_ENVVAR).as_deref() {
+        Ok("osc") => Some(Alert::Notification(message)),
+        _ => Some(Alert::Bell),
+    }
+}
+
+fn describe(command: Option<&[u8]>, elapsed: Duration, exit_code: Option<&[u8]>, threshold: f64) -> Option<Vec<u8>> {
+    if elapsed.as_f64() < threshold {
+        return None;
+    }
+    let name = command?.split(u8::is_ascii_whitespace).find(|w| !w.is_empty())?;
+    let exclude = std::env::var(NOTIFY_EXCLUDE_ENVVAR).unwrap_or_else(|_| DEFAULT_EXCLUDE.to_owned());
+    if exclude.split(',').any(|e| e.trim().as_bytes() == name) {
+        return None;
+    }
+    let mut out = vec![];
+    escape_non_printable(name, &mut out);
+    match exit_code {
+        Some(code) => {
+            out.extend_from_slice(b" failed with ");
+            escape_non_printable(code, &mut out);
+            out.extend_from_slice(b" after ");
+        },
+        None => out.extend_from_slice(b" finished after "),
+    }
+    out.extend_from_slice(format_duration(elapsed).as_bytes());
+    Some(out)
+}
+
+/// Format the duration in the largest whole units, e.g. 4m12s or 1h3m.
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{}s", m, s),
+        (h, m, _) => format!("{}h{}m", h, m),
+    }
+}
+
+/// Write the notification escape sequence to stderr, which is the terminal since the hook only captures stdout.
+/// There is no reliable way to tell from here whether the terminal is focused, so we leave it to the terminal to
+/// decide whether to show it.
+pub fn send(message: &[u8]) -> Result<(), std::io::Error> {
+    if !stderr().is_terminal() {
+        return Ok(());
+    }
+    let mut out = stderr().lock();
+    match std::env::var(NOTIFY_OSC_ENVVAR).as_deref() {
+        Ok("777") => out.write_all(b"\x1b]777;notify;nuprompt;")?,
+        _ => out.write_all(b"\x1b]9;")?,
+    }
+    out.write_all(message)?;
+    out.write_all(b"\x07")?;
+    out.flush()
+}
+
+/// Ring the terminal bell on stderr, which most terminals turn into an urgency hint or a visual bell when the window is
+/// not focused.
+pub fn bell() -> Result<(), std::io::Error> {
+    if !stderr().is_terminal() {
+        return Ok(());
+    }
+    let mut out = stderr().lock();
+    out.write_all(b"\x07")?;
+    out.flush()
+}
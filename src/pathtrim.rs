@@ -0,0 +1,149 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use crate::width::visible_width;
+
+/// The most columns the path may take before middle components are elided. Unset means no limit.
+pub const WIDTH_ENVVAR: &str = "NUPROMPT_PATH_WIDTH";
+/// The comma separated components that are never elided: `repo` for the root of the git repo, and a number for that
+/// many trailing components.
+const KEEP_ENVVAR: &str = "NUPROMPT_PATH_KEEP";
+const DEFAULT_KEEP: &str = "repo,2";
+/// `repo` shows the cwd inside a git repo as the name of the repo and the path within it, e.g. `nuprompt:/src`.
+pub const STYLE_ENVVAR: &str = "NUPROMPT_PATH_STYLE";
+/// Where the ellipsis goes, `middle` keeps the first component and `left` elides it too.
+const ELLIPSIS_ENVVAR: &str = "NUPROMPT_PATH_ELLIPSIS";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    Middle,
+    Left,
+}
+
+/// Options says how to shorten a path that is too wide.
+#[derive(Debug)]
+pub struct Options {
+    pub width: usize,
+    pub keep_repo: bool,
+    pub keep_last: usize,
+    pub placement: Placement,
+}
+
+impl Options {
+
+    /// Read the options from the environment, or None when the path is not limited.
+    pub fn from_env() -> Option<Options> {
+        let width = std::env::var(WIDTH_ENVVAR).ok()?.trim().parse().ok()?;
+        let keep = std::env::var(KEEP_ENVVAR).unwrap_or_else(|_| DEFAULT_KEEP.to_owned());
+        let keep: Vec<&str> = keep.split(',').map(str::trim).collect();
+        Some(Options {
+            width,
+            keep_repo: keep.contains(&"repo"),
+            keep_last: keep.iter().find_map(|k| k.parse().ok()).unwrap_or(0),
+            placement: match std::env::var(ELLIPSIS_ENVVAR).as_deref().map(str::trim) {
+                Ok("left") => Placement::Left,
+                _ => Placement::Middle,
+            },
+        })
+    }
+
+}
+
+/// Elide components of the path, oldest first, until it fits in the width. Each run of elided components is replaced
+/// by a single ellipsis. The repo root is given as the number of components it sits before the last one. The kept
+/// components can still leave the path wider than asked for.
+pub fn shorten(path: &[u8], opts: &Options, repo_root: Option<usize>, ellipsis: &str) -> Vec<u8> {
+    if visible_width(path) <= opts.width {
+        return path.to_vec();
+    }
+    let parts: Vec<&[u8]> = path.split(|b| *b == b'/').collect();
+    let n = parts.len();
+    let repo_index = repo_root.filter(|_| opts.keep_repo).and_then(|r| n.checked_sub(r + 1));
+    let kept = |i: usize| (i == 0 && opts.placement == Placement::Middle)
+        || i + opts.keep_last >= n
+        || Some(i) == repo_index;
+    let mut elided = vec![false; n];
+    let mut out = path.to_vec();
+    for i in 0..n {
+        if kept(i) {
+            continue;
+        }
+        elided[i] = true;
+        out = join(&parts, &elided, ellipsis);
+        if visible_width(&out) <= opts.width {
+            break;
+        }
+    }
+    out
+}
+
+/// The cwd as the name of the repo root followed by the path within the repo, or None when the cwd isn't under the
+/// root, as happens when one of them was reached through a symlink.
+pub fn repo_relative(workdir: &Path, cwd: &Path) -> Option<PathBuf> {
+    let name = workdir.file_name()?;
+    let rest = cwd.strip_prefix(workdir).ok()?;
+    let mut out = OsString::from(name);
+    out.push(":/");
+    out.push(rest.as_os_str());
+    Some(PathBuf::from(out))
+}
+
+fn join(parts: &[&[u8]], elided: &[bool], ellipsis: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (i, part) in parts.iter().enumerate() {
+        if elided[i] && i > 0 && elided[i - 1] {
+            continue;
+        }
+        if i > 0 {
+            out.push(b'/');
+        }
+        out.extend_from_slice(if elided[i] { ellipsis.as_bytes() } else { part });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(width: usize, keep_repo: bool, keep_last: usize, placement: Placement) -> Options {
+        Options { width, keep_repo, keep_last, placement }
+    }
+
+    fn shortened(path: &str, opts: &Options, repo_root: Option<usize>) -> String {
+        String::from_utf8(shorten(path.as_bytes(), opts, repo_root, "\u{2026}")).unwrap()
+    }
+
+    #[test]
+    fn shorten_examples() {
+        let path = "~/src/github.com/astromechza/nuprompt/src/snapshots";
+        assert_eq!(shortened(path, &opts(80, true, 2, Placement::Middle), Some(2)), path);
+        assert_eq!(shortened(path, &opts(20, false, 2, Placement::Middle), None), "~/\u{2026}/src/snapshots");
+        assert_eq!(shortened(path, &opts(20, true, 2, Placement::Middle), Some(2)), "~/\u{2026}/nuprompt/src/snapshots");
+        assert_eq!(shortened(path, &opts(20, true, 2, Placement::Left), Some(2)), "\u{2026}/nuprompt/src/snapshots");
+        assert_eq!(shortened(path, &opts(40, false, 1, Placement::Middle), None), "~/\u{2026}/astromechza/nuprompt/src/snapshots");
+        assert_eq!(shortened("/etc/nginx/sites-enabled/default", &opts(20, false, 1, Placement::Middle), None), "/\u{2026}/default");
+    }
+
+    /// The repo root is kept in place with ellipses on both sides of it.
+    #[test]
+    fn shorten_keeps_repo_between_ellipses() {
+        let path = "~/work/clients/acme/api/services/billing/internal/ledger";
+        assert_eq!(shortened(path, &opts(30, true, 2, Placement::Middle), Some(4)), "~/\u{2026}/api/\u{2026}/internal/ledger");
+    }
+
+    #[test]
+    fn repo_relative_examples() {
+        let root = Path::new("/home/ben/src/monorepo/");
+        assert_eq!(repo_relative(root, Path::new("/home/ben/src/monorepo/services/api")), Some(PathBuf::from("monorepo:/services/api")));
+        assert_eq!(repo_relative(root, Path::new("/home/ben/src/monorepo")), Some(PathBuf::from("monorepo:/")));
+        assert_eq!(repo_relative(root, Path::new("/tmp/elsewhere")), None);
+        assert_eq!(repo_relative(Path::new("/"), Path::new("/etc")), None);
+    }
+
+    /// Kept components win over the width.
+    #[test]
+    fn shorten_never_drops_kept_components() {
+        let path = "/a-very-long-directory-name/another-very-long-one";
+        assert_eq!(shortened(path, &opts(10, false, 2, Placement::Middle), None), path);
+    }
+}
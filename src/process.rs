@@ -0,0 +1,38 @@
+use std::io::Read;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::anyhow;
+use log::debug;
+
+/// How often we check whether the child has exited. Most of the commands we run finish in a few milliseconds.
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Run the command to completion and capture its stdout, or kill it if it takes longer than the timeout. Stderr is
+/// discarded since we only ever look at the exit status and stdout of these commands.
+pub fn output_with_timeout(cmd: &mut Command, timeout: Duration) -> Result<Output, anyhow::Error> {
+    let start = Instant::now();
+    let mut child = cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // read stdout on another thread so that a chatty child can't block on a full pipe while we wait for it
+    let mut stdout = child.stdout.take().ok_or_else(|| anyhow!("child has no stdout"))?;
+    let reader = thread::spawn(move || {
+        let mut buf = Vec::new();
+        stdout.read_to_end(&mut buf).map(|_| buf)
+    });
+    loop {
+        if let Some(status) = child.try_wait()? {
+            let stdout = reader.join().map_err(|_| anyhow!("stdout reader panicked"))??;
+            debug!("{:?} exited with {} in {:?}", cmd.get_program(), status, start.elapsed());
+            return Ok(Output { status, stdout, stderr: vec![] });
+        }
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!("{:?} timed out after {:?}", cmd.get_program(), timeout));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,103 @@
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use crate::git::GitBits;
+use crate::render::PromptContext;
+
+/// Set to write the prompt context of every prompt as JSON for status bars and other tools.
+pub const PUBLISH_ENVVAR: &str = "NUPROMPT_PUBLISH";
+
+fn publish_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR").filter(|d| !d.is_empty()) {
+        Some(d) => PathBuf::from(d).join("nuprompt"),
+        None => std::env::temp_dir().join(format!("nuprompt-{}", users::get_current_uid())),
+    }
+}
+
+pub fn publish_path(raw_pid: &OsStr) -> PathBuf {
+    publish_dir().join(format!("{}.json", raw_pid.to_string_lossy()))
+}
+
+/// Write the context for the shell pid. The file is replaced with a rename so readers never see a partial write.
+pub fn write(raw_pid: &OsStr, ctx: &PromptContext) -> Result<(), anyhow::Error> {
+    let path = publish_path(raw_pid);
+    fs::create_dir_all(publish_dir())?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, to_json(raw_pid, ctx))?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+/// Print the last published context for the shell pid.
+pub fn read(raw_pid: &OsStr) -> Result<(), anyhow::Error> {
+    print!("{}", fs::read_to_string(publish_path(raw_pid))?);
+    Ok(())
+}
+
+/// Render the context as a single line JSON object. Byte strings that aren't UTF-8 are converted lossily.
+fn to_json(raw_pid: &OsStr, ctx: &PromptContext) -> String {
+    let mut out = String::from("{");
+    field(&mut out, "pid", &json_string(&raw_pid.to_string_lossy()));
+    field(&mut out, "exit_code", &ctx.exit_code.as_ref().map_or("null".to_owned(), |c| json_string(&c.to_string_lossy())));
+    field(&mut out, "elapsed_secs", &ctx.elapsed.map_or("null".to_owned(), |e| format!("{:.3}", e.as_f64())));
+    field(&mut out, "cwd", &json_string(&ctx.cwd.to_string_lossy()));
+    field(&mut out, "user", &optional_string(ctx.username.as_deref().map(OsStr::as_encoded_bytes)));
+    field(&mut out, "host", &optional_string(ctx.host.as_deref().map(OsStr::as_encoded_bytes)));
+    field(&mut out, "git", &ctx.git_bits.as_ref().map_or("null".to_owned(), git_json));
+    field(&mut out, "projects", &json_array(ctx.projects.iter().map(String::as_str)));
+    field(&mut out, "health", &json_array(ctx.health.iter().copied()));
+    field(&mut out, "fs", &optional_string(ctx.fs_kind.map(|k| k.marker().as_bytes())));
+    out.push_str("}\n");
+    out
+}
+
+/// The git status as a JSON object, as it is published and printed by `nuprompt scan --json`.
+pub fn git_json(gb: &GitBits) -> String {
+    let mut g = String::from("{");
+    field(&mut g, "branch", &json_string(&String::from_utf8_lossy(&gb.head_ref)));
+    field(&mut g, "staged", &gb.index_modified.to_string());
+    field(&mut g, "modified", &gb.worktree_modified.to_string());
+    field(&mut g, "untracked", &gb.untracked_files.to_string());
+    field(&mut g, "partially_staged", &gb.partially_staged.to_string());
+    field(&mut g, "partial", &gb.partial.to_string());
+    field(&mut g, "worktree_unknown", &gb.worktree_unknown.to_string());
+    field(&mut g, "email", &optional_string(gb.email.as_deref().map(str::as_bytes)));
+    field(&mut g, "operation", &optional_string(gb.operation.as_deref().map(str::as_bytes)));
+    field(&mut g, "operation_age_secs", &gb.operation_age.map_or("null".to_owned(), |a| a.as_secs().to_string()));
+    g.push('}');
+    g
+}
+
+pub fn field(out: &mut String, name: &str, value: &str) {
+    if !out.ends_with('{') {
+        out.push(',');
+    }
+    let _ = write!(out, "{}:{}", json_string(name), value);
+}
+
+fn optional_string(value: Option<&[u8]>) -> String {
+    value.map_or("null".to_owned(), |v| json_string(&String::from_utf8_lossy(v)))
+}
+
+pub fn json_array<'a>(values: impl Iterator<Item = &'a str>) -> String {
+    format!("[{}]", values.map(json_string).collect::<Vec<_>>().join(","))
+}
+
+pub fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 || c == '\u{7f}' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,809 @@
+use std::ffi::OsString;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use coarsetime::Duration;
+use termcolor::{Buffer, Color, ColorSpec, NoColor, WriteColor};
+use crate::cwdrule::CwdStyle;
+use crate::fstype::FsKind;
+use crate::git::GitBits;
+use crate::lsm::SecurityContext;
+use crate::glyph::{self, Glyphs};
+use crate::host::palette_color;
+use crate::layout::{Decoration, Layout, Part};
+use crate::shell::{PromptWriter, RightPrompt, Shell, PLAIN};
+use crate::shellmode::ShellMode;
+use crate::sysupdate::Updates;
+use crate::theme::Background;
+use crate::width::visible_width;
+
+/// The maximum number of columns of the previous command to show in the prompt.
+const COMMAND_SNIPPET_LEN: usize = 24;
+
+/// Commands faster than the first threshold in seconds are green, those faster than the second are yellow, and the
+/// rest are red.
+pub const DEFAULT_ELAPSED_SCALE: (f64, f64) = (1.0, 10.0);
+
+/// PromptContext holds everything gathered for a single prompt, ready to be rendered.
+pub struct PromptContext {
+    /// the timestamp and full hostname that audit mode stamps on every prompt
+    pub audit: Option<(String, OsString)>,
+    pub exit_code: Option<OsString>,
+    /// how many times in a row the same command failed with this exit code, shown from the second time on
+    pub exit_streak: u64,
+    /// the exit code is a routine one, like 130 from Ctrl-C, which is shown dimmed rather than red
+    pub exit_quiet: bool,
+    /// the history number of the next command
+    pub history: Option<Vec<u8>>,
+    pub elapsed: Option<Duration>,
+    pub elapsed_scale: (f64, f64),
+    /// the local time the previous command started, like 14:03
+    pub started_at: Option<String>,
+    pub command: Option<Vec<u8>>,
+    pub health: Vec<&'static str>,
+    /// the segments skipped for being over their latency budget
+    pub throttled: Vec<&'static str>,
+    /// the number of exported credential variables
+    pub secrets: usize,
+    pub updates: Option<Updates>,
+    pub username: Option<OsString>,
+    /// the abbreviated hostname, only set when it should be shown
+    pub host: Option<OsString>,
+    pub user_color: Color,
+    /// the color of exit codes that mean the shell could not run the command at all
+    pub shell_error_color: Color,
+    pub git_bits: Option<GitBits>,
+    /// the color of the branch from the first branch color rule that matched
+    pub branch_color: Option<Color>,
+    /// the web page of the branch on the forge, which the branch links to
+    pub branch_link: Option<String>,
+    /// show the number of changed files rather than letters
+    pub git_counts: bool,
+    pub git_partially_staged: bool,
+    /// the cwd is in a repo owned by another user that we refused to open
+    pub git_untrusted: bool,
+    pub projects: Vec<String>,
+    pub fs_kind: Option<FsKind>,
+    pub security: Option<SecurityContext>,
+    /// the names and values of the segments passed in by the shell hook
+    pub custom: Vec<(Vec<u8>, Vec<u8>)>,
+    /// whether the shell is a login shell and restricted, only set when it should be shown
+    pub shell_mode: Option<ShellMode>,
+    pub cwd: PathBuf,
+    /// how many components before the last one of the cwd the root of the git repo is
+    pub repo_root: Option<usize>,
+    /// the number of trailing components of the real cwd that changed since the previous command, counted before the
+    /// path is shortened for display. That many trailing components of the shown cwd are highlighted, or all of it when
+    /// it has fewer.
+    pub raw_cwd_changed: usize,
+    /// let the shell expand the cwd, so bash applies $PROMPT_DIRTRIM
+    pub native_cwd: bool,
+    /// the color and marker from the first cwd rule that matched
+    pub cwd_style: Option<CwdStyle>,
+    /// the `file://` url of the cwd, which the path links to
+    pub cwd_link: Option<String>,
+    /// the terminal title to set, used when the cwd is shown as a bookmark
+    pub title: Option<PathBuf>,
+    pub glyphs: &'static Glyphs,
+    pub background: Background,
+    pub layout: Layout,
+    /// the colors that replace the usual ones of some parts
+    pub part_colors: Vec<(Part, Color)>,
+    /// the text around the parts that are decorated
+    pub decorations: Vec<Decoration>,
+    /// the width of the terminal, needed to draw the right side for shells that have no right prompt
+    pub columns: Option<usize>,
+}
+
+impl PromptContext {
+
+    pub fn new(exit_code: Option<OsString>, elapsed: Option<Duration>, cwd: Option<PathBuf>) -> PromptContext {
+        PromptContext {
+            audit: None,
+            exit_code,
+            exit_streak: 0,
+            exit_quiet: false,
+            history: None,
+            elapsed,
+            elapsed_scale: DEFAULT_ELAPSED_SCALE,
+            started_at: None,
+            command: None,
+            health: vec![],
+            throttled: vec![],
+            secrets: 0,
+            updates: None,
+            username: None,
+            host: None,
+            user_color: Color::Cyan,
+            shell_error_color: Color::Magenta,
+            git_bits: None,
+            branch_color: None,
+            branch_link: None,
+            git_counts: false,
+            git_partially_staged: false,
+            git_untrusted: false,
+            projects: vec![],
+            fs_kind: None,
+            security: None,
+            custom: vec![],
+            shell_mode: None,
+            cwd: cwd.unwrap_or_default(),
+            repo_root: None,
+            raw_cwd_changed: 0,
+            native_cwd: false,
+            cwd_style: None,
+            cwd_link: None,
+            title: None,
+            glyphs: &glyph::UNICODE,
+            background: Background::Dark,
+            layout: Layout::default(),
+            part_colors: vec![],
+            decorations: vec![],
+            columns: None,
+        }
+    }
+
+}
+
+/// Render the prompt for the shell into the buffer. The buffer decides whether colors are written.
+pub fn render(ctx: &PromptContext, shell: &'static Shell, out: &mut dyn WriteColor) -> Result<(), std::io::Error> {
+    let mut buffer = PromptWriter { shell, inner: out, background: ctx.background, zero_width: false, fg: None };
+    buffer.write_all(shell.prompt_prefix)?;
+    if let Some(title) = &ctx.title {
+        buffer.write_title(title.as_os_str().as_bytes())?;
+    }
+    let right = &ctx.layout.right[..];
+    // the right side goes at the end of the left one when the shell can't show it on its own
+    let mut appended: &[Part] = &[];
+    match shell.right_prompt {
+        _ if right.is_empty() => {},
+        RightPrompt::Emulated => match ctx.columns.and_then(|columns| right_column(ctx, columns)) {
+            Some(column) => write_emulated_right(ctx, column, &mut buffer)?,
+            None => appended = right,
+        },
+        RightPrompt::Appended => appended = right,
+        RightPrompt::Variable(_) => {},
+    }
+    // audit mode can't be turned off, so a layout that leaves the stamp out still gets it first
+    let stamp: &[Part] = match ctx.audit.is_some() && !ctx.layout.left.contains(&Part::Audit) && !right.contains(&Part::Audit) {
+        true => &[Part::Audit],
+        false => &[],
+    };
+    buffer.write_all(b"[")?;
+    for part in stamp.iter().chain(&ctx.layout.left).chain(appended) {
+        write_part(*part, ctx, &mut buffer)?;
+    }
+    write!(buffer, "{} ", ctx.glyphs.prompt_end)?;
+    buffer.write_all(shell.prompt_suffix)?;
+    if let (RightPrompt::Variable(prefix), false) = (shell.right_prompt, right.is_empty()) {
+        buffer.write_all(prefix)?;
+        for part in right {
+            write_part(*part, ctx, &mut buffer)?;
+        }
+        buffer.reset()?;
+        buffer.write_all(shell.prompt_suffix)?;
+    }
+    Ok(())
+}
+
+/// The column the right side starts at so that it ends at the right edge, or None when it doesn't fit.
+fn right_column(ctx: &PromptContext, columns: usize) -> Option<usize> {
+    let mut measured = NoColor::new(vec![]);
+    let mut buffer = PromptWriter { shell: &PLAIN, inner: &mut measured, background: ctx.background, zero_width: false, fg: None };
+    for part in &ctx.layout.right {
+        write_part(*part, ctx, &mut buffer).ok()?;
+    }
+    let width = visible_width(measured.get_ref());
+    (width < columns).then(|| columns - width + 1)
+}
+
+/// Shells without a right prompt get one by saving the cursor, moving it to the column and writing the right side
+/// there, and restoring the cursor so that the left side is written as usual. The whole thing is non-printing as far as
+/// the shell is concerned, so the line editor still knows where the left side ends.
+fn write_emulated_right(ctx: &PromptContext, column: usize, buffer: &mut PromptWriter) -> Result<(), std::io::Error> {
+    buffer.write_all(buffer.shell.escape_open)?;
+    buffer.zero_width = true;
+    write!(buffer, "\x1b7\x1b[{}G", column)?;
+    for part in &ctx.layout.right {
+        write_part(*part, ctx, buffer)?;
+    }
+    buffer.reset()?;
+    buffer.write_all(b"\x1b8")?;
+    buffer.zero_width = false;
+    buffer.write_all(buffer.shell.escape_close)
+}
+
+/// Write one part of the prompt followed by a space, or nothing when it has nothing to show. A decorated part is
+/// written to a buffer of its own first, so that the prefix and suffix only appear when there is something between
+/// them, and the suffix goes before the space.
+fn write_part(part: Part, ctx: &PromptContext, buffer: &mut PromptWriter) -> Result<(), std::io::Error> {
+    buffer.fg = ctx.part_colors.iter().find(|(p, _)| *p == part).map(|(_, color)| *color);
+    let Some(decoration) = ctx.decorations.iter().find(|d| d.part == part) else {
+        return write_part_content(part, ctx, buffer);
+    };
+    let mut captured = if buffer.supports_color() { Buffer::ansi() } else { Buffer::no_color() };
+    let mut inner = PromptWriter { inner: &mut captured, ..*buffer };
+    write_part_content(part, ctx, &mut inner)?;
+    let content = captured.as_slice();
+    if content.is_empty() {
+        return Ok(());
+    }
+    if !decoration.prefix.is_empty() {
+        buffer.set_color(&ColorSpec::default())?;
+        buffer.write_literal(decoration.prefix.as_bytes())?;
+    }
+    buffer.write_all(content.strip_suffix(b" ").unwrap_or(content))?;
+    if !decoration.suffix.is_empty() {
+        buffer.set_color(&ColorSpec::default())?;
+        buffer.write_literal(decoration.suffix.as_bytes())?;
+    }
+    buffer.write_all(b" ")
+}
+
+fn write_part_content(part: Part, ctx: &PromptContext, buffer: &mut PromptWriter) -> Result<(), std::io::Error> {
+    match part {
+        Part::Audit => {
+            if let Some((timestamp, hostname)) = &ctx.audit {
+                buffer.set_color(ColorSpec::new().set_dimmed(true))?;
+                write!(buffer, "{} ", timestamp)?;
+                buffer.write_literal(hostname.as_bytes())?;
+                buffer.write_all(b" ")?;
+            }
+        },
+        Part::History => {
+            if let Some(history) = &ctx.history {
+                buffer.set_color(ColorSpec::new().set_dimmed(true))?;
+                buffer.write_all(b"!")?;
+                buffer.write_literal(history)?;
+                buffer.write_all(b" ")?;
+            }
+        },
+        Part::ExitCode => {
+            if let Some(exit_code) = &ctx.exit_code {
+                // the shell reports a missing or non-executable command with these, so they are not the command's own failure
+                let shell_error = match exit_code.as_bytes() {
+                    b"127" => Some(ctx.glyphs.not_found),
+                    b"126" => Some(ctx.glyphs.not_executable),
+                    _ => None,
+                };
+                match shell_error {
+                    Some(glyph) => {
+                        buffer.set_color(ColorSpec::new().set_fg(Some(ctx.shell_error_color)).set_bold(true))?;
+                        buffer.write_all(glyph.as_bytes())?;
+                    },
+                    None if ctx.exit_quiet => buffer.set_color(ColorSpec::new().set_dimmed(true))?,
+                    None => buffer.set_color(ColorSpec::new().set_fg(Some(Color::Red)).set_bold(ctx.exit_streak > 1))?,
+                }
+                buffer.write_literal(exit_code.as_bytes())?;
+                if ctx.exit_streak > 1 {
+                    write!(buffer, "{}{}", ctx.glyphs.times, ctx.exit_streak)?;
+                }
+                buffer.write_all(b" ")?;
+            }
+        },
+        Part::Elapsed => {
+            if let Some(elapsed) = ctx.elapsed {
+                let color = match elapsed.as_f64() {
+                    e if e < ctx.elapsed_scale.0 => Color::Green,
+                    e if e < ctx.elapsed_scale.1 => Color::Yellow,
+                    _ => Color::Red,
+                };
+                buffer.set_color(ColorSpec::new().set_fg(Some(color)))?;
+                write!(buffer, "{:.2}s ", elapsed.as_f64())?;
+            }
+        },
+        Part::StartTime => {
+            if let Some(started_at) = &ctx.started_at {
+                buffer.set_color(ColorSpec::new().set_dimmed(true))?;
+                write!(buffer, "at {} ", started_at)?;
+            }
+        },
+        Part::Command => {
+            if let Some(command) = &ctx.command {
+                buffer.set_color(ColorSpec::new().set_fg(Some(Color::Cyan)).set_dimmed(true))?;
+                buffer.write_literal(command_snippet(command, ctx.glyphs).as_bytes())?;
+                buffer.write_all(b" ")?;
+            }
+        },
+        Part::Health => {
+            for marker in &ctx.health {
+                buffer.set_color(ColorSpec::new().set_fg(Some(Color::Red)).set_dimmed(true))?;
+                buffer.write_all(marker.as_bytes())?;
+                buffer.write_all(b" ")?;
+            }
+        },
+        Part::Throttled => {
+            for name in &ctx.throttled {
+                buffer.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_dimmed(true))?;
+                write!(buffer, "~{} ", name)?;
+            }
+        },
+        Part::Secrets => {
+            if ctx.secrets > 0 {
+                buffer.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_dimmed(true))?;
+                write!(buffer, "{}{} ", ctx.glyphs.key, ctx.secrets)?;
+            }
+        },
+        Part::Updates => {
+            if let Some(updates) = &ctx.updates {
+                if updates.reboot_required {
+                    buffer.set_color(ColorSpec::new().set_fg(Some(Color::Red)).set_dimmed(true))?;
+                    buffer.write_all(b"!reboot ")?;
+                }
+                if let Some(pending) = updates.pending.filter(|n| *n > 0) {
+                    buffer.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_dimmed(true))?;
+                    write!(buffer, "pkg:{} ", pending)?;
+                }
+            }
+        },
+        Part::ShellMode => {
+            if let Some(mode) = ctx.shell_mode {
+                buffer.set_color(ColorSpec::new().set_fg(Some(if mode.restricted { Color::Red } else { Color::Cyan })).set_dimmed(true))?;
+                write!(buffer, "{} ", mode.label())?;
+            }
+        },
+        Part::User => {
+            if let Some(username) = &ctx.username {
+                buffer.set_color(ColorSpec::new().set_fg(Some(ctx.user_color)).set_bold(true).set_intense(true))?;
+                buffer.write_literal(username.as_bytes())?;
+                if let Some(host) = &ctx.host {
+                    buffer.write_all(b"@")?;
+                    buffer.write_literal(host.as_bytes())?;
+                }
+                buffer.write_all(b" ")?;
+            }
+        },
+        Part::Git => {
+            if let Some(git_bits) = &ctx.git_bits {
+                buffer.set_color(ColorSpec::new().set_fg(Some(ctx.branch_color.unwrap_or(Color::Yellow))).set_intense(true))?;
+                if let Some(link) = &ctx.branch_link {
+                    buffer.write_hyperlink(link.as_bytes())?;
+                }
+                buffer.write_literal(&git_bits.head_ref)?;
+                if ctx.branch_link.is_some() {
+                    buffer.write_hyperlink(b"")?;
+                }
+                buffer.set_color(&ColorSpec::default())?;
+                git_bits.write_elements(buffer, ctx.git_counts, ctx.git_partially_staged, ctx.glyphs)?;
+                buffer.write_all(b" ")?;
+            }
+            if ctx.git_untrusted {
+                buffer.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
+                buffer.write_all(b"git:untrusted ")?;
+            }
+        },
+        Part::Detect => {
+            for project in &ctx.projects {
+                buffer.set_color(ColorSpec::new().set_fg(Some(Color::Blue)).set_intense(true))?;
+                buffer.write_literal(project.as_bytes())?;
+                buffer.write_all(b" ")?;
+            }
+        },
+        Part::Fs => {
+            if let Some(fs_kind) = ctx.fs_kind {
+                buffer.set_color(ColorSpec::new().set_fg(Some(Color::Magenta)))?;
+                buffer.write_all(fs_kind.marker().as_bytes())?;
+                buffer.write_all(b" ")?;
+            }
+        },
+        Part::Security => {
+            if let Some(security) = &ctx.security {
+                let color = if security.confined { Color::Green } else { Color::Yellow };
+                buffer.set_color(ColorSpec::new().set_fg(Some(color)).set_dimmed(true))?;
+                buffer.write_literal(security.label.as_bytes())?;
+                buffer.write_all(b" ")?;
+            }
+        },
+        Part::Custom => {
+            // the name picks the color, so that each segment keeps its own
+            for (name, value) in &ctx.custom {
+                buffer.set_color(ColorSpec::new().set_fg(Some(palette_color(name))))?;
+                buffer.write_literal(value)?;
+                buffer.write_all(b" ")?;
+            }
+        },
+        Part::Cwd => {
+            let cwd_color = ctx.cwd_style.as_ref().and_then(|s| s.color);
+            if let Some(marker) = ctx.cwd_style.as_ref().and_then(|s| s.marker.as_deref()) {
+                buffer.set_color(ColorSpec::new().set_fg(cwd_color).set_bold(true))?;
+                buffer.write_literal(marker.as_bytes())?;
+                buffer.write_all(b" ")?;
+            }
+            buffer.set_color(ColorSpec::new().set_fg(cwd_color))?;
+            if let Some(link) = &ctx.cwd_link {
+                buffer.write_hyperlink(link.as_bytes())?;
+            }
+            // what the shell expands can't be measured, so the emulated right side writes the cwd itself
+            match buffer.shell.native_cwd.filter(|_| ctx.native_cwd && !buffer.zero_width) {
+                Some(native) => buffer.write_all(native)?,
+                None => {
+                    let cwd = ctx.cwd.as_os_str().as_bytes();
+                    let split = changed_split(cwd, ctx.raw_cwd_changed);
+                    buffer.write_literal(&cwd[..split])?;
+                    if split < cwd.len() {
+                        buffer.set_color(ColorSpec::new().set_fg(Some(Color::Green)).set_bold(true).set_intense(true))?;
+                        buffer.write_literal(&cwd[split..])?;
+                        buffer.set_color(&ColorSpec::default())?;
+                    }
+                },
+            }
+            if ctx.cwd_link.is_some() {
+                buffer.write_hyperlink(b"")?;
+            }
+            buffer.write_all(b" ")?;
+        },
+    }
+    Ok(())
+}
+
+/// Render the continuation prompt, which has no state of its own and so only needs the glyphs and background. The shell must have a
+/// continuation prefix.
+pub fn render_continuation(glyphs: &Glyphs, background: Background, shell: &'static Shell, out: &mut dyn WriteColor) -> Result<(), std::io::Error> {
+    let mut buffer = PromptWriter { shell, inner: out, background, zero_width: false, fg: None };
+    buffer.write_all(shell.continuation_prefix.unwrap_or_default())?;
+    buffer.set_color(ColorSpec::new().set_dimmed(true))?;
+    write!(buffer, "{} {} ", glyphs.ellipsis, glyphs.prompt_end)?;
+    buffer.reset()?;
+    buffer.write_all(shell.prompt_suffix)?;
+    Ok(())
+}
+
+/// find where the last `changed` components of the displayed path start.
+fn changed_split(cwd: &[u8], changed: usize) -> usize {
+    let mut split = cwd.len();
+    for _ in 0..changed {
+        match cwd[..split].iter().rposition(|b| *b == b'/') {
+            Some(i) => split = i,
+            None => return 0,
+        }
+    }
+    if split < cwd.len() { split + 1 } else { split }
+}
+
+/// abbreviate the previous command to its first line and a limited number of columns, so that wide characters don't
+/// make the snippet take more space than intended.
+fn command_snippet(command: &[u8], glyphs: &Glyphs) -> String {
+    let command = String::from_utf8_lossy(command);
+    let first_line = command.lines().next().unwrap_or_default();
+    let (mut out, mut columns, mut truncated) = (String::new(), 0, false);
+    for c in first_line.chars() {
+        columns += visible_width(c.encode_utf8(&mut [0; 4]).as_bytes());
+        if columns > COMMAND_SNIPPET_LEN {
+            truncated = true;
+            break;
+        }
+        out.push(c);
+    }
+    if truncated || command.lines().nth(1).is_some() {
+        out.push_str(glyphs.ellipsis);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use termcolor::Buffer;
+    use crate::shell::SHELLS;
+    use crate::signing::Signing;
+
+    /// Set this to write the current output over the snapshot files instead of comparing against them.
+    const UPDATE_ENVVAR: &str = "NUPROMPT_UPDATE_SNAPSHOTS";
+
+    /// Render the context for every shell, with and without color, and compare the escaped bytes against the snapshot.
+    fn assert_snapshot(name: &str, ctx: &PromptContext) {
+        assert_rendered(name, SHELLS, |shell, buffer| render(ctx, shell, buffer).unwrap());
+    }
+
+    fn assert_rendered(name: &str, shells: &[&'static Shell], render: impl Fn(&'static Shell, &mut Buffer)) {
+        let mut actual = String::new();
+        for shell in shells {
+            for (mode, mut buffer) in [("ansi", Buffer::ansi()), ("plain", Buffer::no_color())] {
+                render(shell, &mut buffer);
+                actual.push_str(&format!("--- {} {} ---\n{}\n", shell.name, mode, buffer.as_slice().escape_ascii()));
+            }
+        }
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/snapshots").join(format!("{}.snap", name));
+        if std::env::var_os(UPDATE_ENVVAR).is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, &actual).unwrap();
+            return;
+        }
+        let expected = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("missing snapshot {:?} ({}), run with {}=1 to create it", path, e, UPDATE_ENVVAR));
+        assert!(expected == actual, "snapshot {} does not match, run with {}=1 to update it\n--- expected\n{}\n--- actual\n{}", name, UPDATE_ENVVAR, expected, actual);
+    }
+
+    fn full_context() -> PromptContext {
+        let mut ctx = PromptContext::new(Some(OsString::from("1")), Some(Duration::from_millis(1500)), Some(PathBuf::from("~/src/nuprompt")));
+        ctx.username = Some(OsString::from("ben"));
+        ctx.user_color = Color::Green;
+        ctx.git_bits = Some(GitBits { head_ref: b"main".to_vec(), ..GitBits::default() });
+        ctx
+    }
+
+    #[test]
+    fn snapshot_minimal() {
+        assert_snapshot("minimal", &PromptContext::new(None, None, Some(PathBuf::from("~"))));
+    }
+
+    #[test]
+    fn snapshot_clean_repo() {
+        assert_snapshot("clean_repo", &full_context());
+    }
+
+    #[test]
+    fn snapshot_dirty_repo() {
+        let mut ctx = full_context();
+        ctx.git_bits = Some(GitBits {
+            head_ref: b"feature/x".to_vec(),
+            index_modified: 2,
+            worktree_modified: 7,
+            untracked_files: 150,
+            partially_staged: 1,
+            partial: false,
+            worktree_unknown: false,
+            default_branch_ahead: Some((b"origin/main".to_vec(), 3)),
+            signing: Some(Signing::Locked),
+            email: None,
+            operation: Some(String::from("BISECTING (~4 steps left)")),
+            operation_age: Some(std::time::Duration::from_secs(3 * 86400 + 7200)),
+            sparse_checkout: false,
+            partial_clone: false,
+            shallow: false,
+            main_worktree_dirty: true,
+            unpushed_branches: 3,
+            head_unpushed: true,
+            stale_lock: Some("index.lock"),
+        });
+        assert_snapshot("dirty_repo", &ctx);
+    }
+
+    #[test]
+    fn snapshot_dirty_repo_counts() {
+        let mut ctx = full_context();
+        ctx.git_counts = true;
+        ctx.git_bits = Some(GitBits {
+            head_ref: b"main".to_vec(),
+            index_modified: 2,
+            worktree_modified: 7,
+            untracked_files: 150,
+            partial: true,
+            ..GitBits::default()
+        });
+        assert_snapshot("dirty_repo_counts", &ctx);
+    }
+
+    #[test]
+    fn snapshot_index_only() {
+        let mut ctx = full_context();
+        let index_only = || GitBits { head_ref: b"main".to_vec(), index_modified: 2, partial: true, worktree_unknown: true, ..GitBits::default() };
+        ctx.git_bits = Some(index_only());
+        assert_snapshot("index_only", &ctx);
+        ctx.git_counts = true;
+        ctx.git_bits = Some(index_only());
+        assert_snapshot("index_only_counts", &ctx);
+    }
+
+    #[test]
+    fn snapshot_hyperlinks() {
+        let mut ctx = full_context();
+        ctx.cwd_link = Some(String::from("file://box/home/ben/src/my%20nuprompt"));
+        ctx.branch_link = Some(String::from("https://github.com/astromechza/nuprompt/tree/main"));
+        assert_snapshot("hyperlinks", &ctx);
+    }
+
+    #[test]
+    fn snapshot_partially_staged() {
+        let mut ctx = full_context();
+        ctx.git_partially_staged = true;
+        let gb = || GitBits { head_ref: b"main".to_vec(), index_modified: 2, worktree_modified: 3, partially_staged: 1, ..GitBits::default() };
+        ctx.git_bits = Some(gb());
+        assert_snapshot("partially_staged", &ctx);
+        ctx.git_counts = true;
+        ctx.git_bits = Some(gb());
+        assert_snapshot("partially_staged_counts", &ctx);
+    }
+
+    #[test]
+    fn snapshot_untrusted_repo() {
+        let mut ctx = full_context();
+        ctx.git_bits = None;
+        ctx.git_untrusted = true;
+        assert_snapshot("untrusted_repo", &ctx);
+    }
+
+    #[test]
+    fn snapshot_sparse_repo() {
+        let mut ctx = full_context();
+        ctx.git_bits = Some(GitBits { head_ref: b"main".to_vec(), sparse_checkout: true, partial_clone: true, ..GitBits::default() });
+        assert_snapshot("sparse_repo", &ctx);
+    }
+
+    #[test]
+    fn snapshot_git_email() {
+        let mut ctx = full_context();
+        ctx.git_bits = Some(GitBits { head_ref: b"main".to_vec(), email: Some(String::from("ben@corp.example")), ..GitBits::default() });
+        assert_snapshot("git_email", &ctx);
+    }
+
+    #[test]
+    fn snapshot_shallow_repo() {
+        let mut ctx = full_context();
+        ctx.git_bits = Some(GitBits { head_ref: b"main".to_vec(), shallow: true, ..GitBits::default() });
+        assert_snapshot("shallow_repo", &ctx);
+    }
+
+    #[test]
+    fn snapshot_no_head() {
+        let mut ctx = full_context();
+        ctx.exit_code = Some(OsString::from("127"));
+        ctx.git_bits = Some(GitBits { head_ref: b"NO HEAD".to_vec(), untracked_files: 1, partial: true, ..GitBits::default() });
+        assert_snapshot("no_head", &ctx);
+    }
+
+    #[test]
+    fn snapshot_quiet_exit_code() {
+        let mut ctx = full_context();
+        ctx.exit_code = Some(OsString::from("130"));
+        ctx.exit_quiet = true;
+        assert_snapshot("quiet_exit_code", &ctx);
+    }
+
+    #[test]
+    fn snapshot_exit_streak() {
+        let mut ctx = full_context();
+        ctx.exit_streak = 3;
+        assert_snapshot("exit_streak", &ctx);
+    }
+
+    #[test]
+    fn snapshot_not_executable() {
+        let mut ctx = full_context();
+        ctx.exit_code = Some(OsString::from("126"));
+        assert_snapshot("not_executable", &ctx);
+    }
+
+    #[test]
+    fn snapshot_escaping() {
+        let mut ctx = full_context();
+        ctx.command = Some(b"echo '$HOME' `id` \\ 100% done\x1b[2J".to_vec());
+        ctx.git_bits = Some(GitBits { head_ref: b"it's-$(rm)-`x`-\\-%F".to_vec(), ..GitBits::default() });
+        ctx.cwd = PathBuf::from(std::ffi::OsStr::from_bytes(b"/tmp/a\xffb\x07c"));
+        assert_snapshot("escaping", &ctx);
+    }
+
+    #[test]
+    fn snapshot_cwd_changed() {
+        let mut ctx = full_context();
+        ctx.cwd = PathBuf::from("/srv/data/logs");
+        ctx.raw_cwd_changed = 2;
+        assert_snapshot("cwd_changed", &ctx);
+    }
+
+    #[test]
+    fn snapshot_cwd_style() {
+        let mut ctx = full_context();
+        ctx.cwd = PathBuf::from("/etc/ssh");
+        ctx.raw_cwd_changed = 1;
+        ctx.cwd_style = Some(CwdStyle { color: Some(Color::Red), marker: Some(String::from("\u{26A0}")) });
+        assert_snapshot("cwd_style", &ctx);
+    }
+
+    #[test]
+    fn snapshot_audit() {
+        let mut ctx = full_context();
+        ctx.audit = Some((String::from("2024-03-09T14:03:27+01:00"), OsString::from("build-07.prod.example.com")));
+        ctx.history = Some(b"512".to_vec());
+        assert_snapshot("audit", &ctx);
+    }
+
+    #[test]
+    fn audit_survives_format() {
+        let mut ctx = full_context();
+        ctx.audit = Some((String::from("2024-03-09T14:03:27+01:00"), OsString::from("build-07.prod.example.com")));
+        // as from a format of "cwd | git"
+        ctx.layout = Layout { left: vec![Part::Cwd], right: vec![Part::Git] };
+        let mut out = Buffer::no_color();
+        render(&ctx, &crate::shell::BASH, &mut out).unwrap();
+        let prompt = String::from_utf8(out.into_inner()).unwrap();
+        assert!(prompt.starts_with("PS1='[2024-03-09T14:03:27+01:00 build-07.prod.example.com "), "{:?}", prompt);
+    }
+
+    #[test]
+    fn snapshot_continuation() {
+        let shells: Vec<&'static Shell> = SHELLS.iter().copied().filter(|s| s.continuation_prefix.is_some()).collect();
+        assert_rendered("continuation", &shells, |shell, buffer| render_continuation(&glyph::UNICODE, Background::Dark, shell, buffer).unwrap());
+        assert_rendered("continuation_ascii", &shells[..1], |shell, buffer| render_continuation(&glyph::ASCII, Background::Dark, shell, buffer).unwrap());
+    }
+
+    #[test]
+    fn snapshot_light_background() {
+        let mut ctx = full_context();
+        ctx.background = Background::Light;
+        ctx.git_bits = Some(GitBits { head_ref: b"main".to_vec(), index_modified: 1, untracked_files: 2, ..GitBits::default() });
+        ctx.updates = Some(Updates { reboot_required: false, pending: Some(12) });
+        assert_snapshot("light_background", &ctx);
+    }
+
+    /// Git and the elapsed time on the right, which zsh and tcsh show natively, bash and pwsh draw at the right edge and
+    /// the others append to the left side.
+    #[test]
+    fn snapshot_right_side() {
+        let mut ctx = full_context();
+        ctx.layout = Layout { left: vec![Part::ExitCode, Part::User, Part::Cwd], right: vec![Part::Git, Part::Elapsed] };
+        ctx.columns = Some(80);
+        assert_snapshot("right_side", &ctx);
+        // too narrow a terminal appends the right side just as an unknown width does
+        ctx.columns = Some(11);
+        let mut narrow = Buffer::no_color();
+        render(&ctx, &crate::shell::BASH, &mut narrow).unwrap();
+        assert_eq!(String::from_utf8(narrow.into_inner()).unwrap(), "PS1='[1 ben ~/src/nuprompt main 1.49s ⟫ '");
+    }
+
+    #[test]
+    fn snapshot_branch_color() {
+        let mut ctx = full_context();
+        ctx.branch_color = Some(Color::Red);
+        assert_snapshot("branch_color", &ctx);
+    }
+
+    /// The decorations only go around the parts with something to show, and the suffix goes before the space.
+    #[test]
+    fn snapshot_decorations() {
+        let mut ctx = full_context();
+        ctx.decorations = vec![
+            Decoration { part: Part::Git, prefix: String::from("on ("), suffix: String::from(")") },
+            Decoration { part: Part::Cwd, prefix: String::from("in '$HOME' "), suffix: String::new() },
+            Decoration { part: Part::Detect, prefix: String::from("<"), suffix: String::from(">") },
+        ];
+        assert_snapshot("decorations", &ctx);
+    }
+
+    #[test]
+    fn snapshot_part_colors() {
+        let mut ctx = full_context();
+        ctx.git_bits = Some(GitBits { head_ref: b"main".to_vec(), index_modified: 1, ..GitBits::default() });
+        ctx.part_colors = vec![(Part::Git, Color::Blue), (Part::Cwd, Color::Ansi256(208))];
+        ctx.layout = Layout { left: vec![Part::Git, Part::Cwd], right: vec![] };
+        assert_snapshot("part_colors", &ctx);
+    }
+
+    #[test]
+    fn snapshot_native_cwd() {
+        let mut ctx = full_context();
+        ctx.native_cwd = true;
+        ctx.raw_cwd_changed = 1;
+        assert_snapshot("native_cwd", &ctx);
+    }
+
+    #[test]
+    fn snapshot_ascii() {
+        let mut ctx = full_context();
+        ctx.command = Some(b"cargo build --release --workspace --all-features".to_vec());
+        ctx.glyphs = &glyph::ASCII;
+        assert_snapshot("ascii", &ctx);
+    }
+
+    #[test]
+    fn snapshot_segments() {
+        let mut ctx = full_context();
+        ctx.command = Some("cargo build 漢字漢字漢字 --release --workspace --all-features\nsecond line".as_bytes().to_vec());
+        ctx.health = vec!["!agent", "!gpgtty"];
+        ctx.throttled = vec!["updates"];
+        ctx.updates = Some(Updates { reboot_required: true, pending: Some(12) });
+        ctx.secrets = 2;
+        ctx.projects = vec![String::from("go1.22+work")];
+        ctx.fs_kind = Some(FsKind::Nfs);
+        ctx.security = Some(SecurityContext { label: String::from("se:staff_u:staff_r:staff_t"), confined: true });
+        ctx.custom = vec![(b"task".to_vec(), b"write $docs".to_vec())];
+        ctx.shell_mode = Some(ShellMode { login: true, restricted: false });
+        ctx.host = Some(OsString::from("aws:10.0.3.12"));
+        ctx.cwd = PathBuf::from("@api/src");
+        ctx.title = Some(PathBuf::from("/home/ben/work/api/src"));
+        ctx.raw_cwd_changed = 1;
+        ctx.history = Some(b"512".to_vec());
+        ctx.started_at = Some(String::from("14:03"));
+        assert_snapshot("segments", &ctx);
+    }
+}
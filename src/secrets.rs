@@ -0,0 +1,19 @@
+/// Set to count the credentials exported in the shell environment.
+pub const SECRETS_ENVVAR: &str = "NUPROMPT_SECRETS";
+/// The comma separated variable names that hold credentials, replacing the default list.
+const SECRET_VARS_ENVVAR: &str = "NUPROMPT_SECRET_VARS";
+
+/// Tokens that credential helpers and short lived sessions can usually provide instead.
+const DEFAULT_SECRET_VARS: &str = "AWS_SECRET_ACCESS_KEY,AWS_SESSION_TOKEN,GITHUB_TOKEN,GH_TOKEN,GITLAB_TOKEN,\
+    NPM_TOKEN,CARGO_REGISTRY_TOKEN,DOCKER_PASSWORD,HF_TOKEN,OPENAI_API_KEY,ANTHROPIC_API_KEY,VAULT_TOKEN";
+
+/// Count the credential variables that are exported with a non-empty value. The prompt is a child of the shell, so
+/// its environment is exactly what the shell exports to every command it runs.
+pub fn exported_count() -> usize {
+    let names = std::env::var(SECRET_VARS_ENVVAR).unwrap_or_else(|_| DEFAULT_SECRET_VARS.to_owned());
+    names.split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .filter(|n| std::env::var_os(n).is_some_and(|v| !v.is_empty()))
+        .count()
+}
@@ -0,0 +1,45 @@
+use std::sync::OnceLock;
+use log::debug;
+use crate::watchdog;
+
+/// Comma separated segment names to turn off or on for this prompt, overriding the variables that normally decide.
+/// Because they are read on every prompt, exporting them toggles segments for the rest of the session.
+pub const DISABLE_ENVVAR: &str = "NUPROMPT_DISABLE";
+pub const ENABLE_ENVVAR: &str = "NUPROMPT_ENABLE";
+
+/// The segments that can be toggled.
+pub const SEGMENTS: &[&str] = &[
+    "elapsed", "history", "command", "health", "user", "host", "git", "default_branch", "signing", "detect", "fs",
+    "bookmark", "cwd_diff", "hints", "notify", "updates", "main_worktree", "secrets", "unpushed", "head_pushed",
+    "start_time", "alert", "stale_lock", "security", "operation_age", "custom", "shell_mode", "git_email",
+];
+
+/// The segments skipped for this prompt because they have been over their latency budget in this directory.
+static THROTTLED: OnceLock<Vec<&'static str>> = OnceLock::new();
+
+/// Skip the segments for the rest of this prompt. Only the first call has any effect.
+pub fn throttle(names: Vec<&'static str>) {
+    let _ = THROTTLED.set(names);
+}
+
+fn listed(envvar: &str, name: &str) -> bool {
+    std::env::var(envvar).is_ok_and(|raw| raw.split(',').map(str::trim).any(|n| {
+        if !n.is_empty() && !SEGMENTS.contains(&n) {
+            debug!("unknown segment {:?} in {}", n, envvar);
+        }
+        n == name
+    }))
+}
+
+/// Decide whether the segment is shown. $NUPROMPT_DISABLE and the latency budget win over $NUPROMPT_ENABLE, which
+/// wins over the default given by the segment's own configuration. Nothing is shown once the prompt is out of time.
+pub fn enabled(name: &str, default: bool) -> bool {
+    debug_assert!(SEGMENTS.contains(&name), "segment {} is not in SEGMENTS", name);
+    if listed(DISABLE_ENVVAR, name) || THROTTLED.get().is_some_and(|t| t.contains(&name)) || watchdog::expired() {
+        false
+    } else if listed(ENABLE_ENVVAR, name) {
+        true
+    } else {
+        default
+    }
+}
@@ -0,0 +1,667 @@
+use std::io::Write;
+use termcolor::{Color, ColorSpec, WriteColor};
+use crate::theme::{self, Background};
+
+/// Shell describes how nuprompt hooks into a particular shell and how the rendered prompt must be wrapped and escaped
+/// for it. Adding a new shell should only require adding a new entry to [SHELLS].
+pub struct Shell {
+    pub name: &'static str,
+    /// the line to add to the shell rc file, this usually evaluates the full init
+    pub init: &'static str,
+    /// the hook code that wires ps0 and ps1 into the shell
+    pub full_init: &'static str,
+    /// a statement that prints a warning to stderr, with MESSAGE replaced by text that is safe in single quotes
+    pub warning: &'static str,
+    /// written before and after the whole prompt
+    pub prompt_prefix: &'static [u8],
+    pub prompt_suffix: &'static [u8],
+    /// written before the continuation prompt in place of the prompt prefix, for the shells that have one
+    pub continuation_prefix: Option<&'static [u8]>,
+    /// written around any non-printing escape sequences so that the shell can calculate the prompt width
+    pub escape_open: &'static [u8],
+    pub escape_close: &'static [u8],
+    /// write dynamic text so that it appears literally in the prompt
+    pub write_literal: fn(&[u8], &mut dyn Write) -> Result<(), std::io::Error>,
+    /// the prompt escape that makes the shell expand the cwd itself, if it has one
+    pub native_cwd: Option<&'static [u8]>,
+    /// the keyword that exports a variable, for the shells that eval our output rather than print it
+    pub export: Option<&'static [u8]>,
+    pub right_prompt: RightPrompt,
+}
+
+/// How a shell shows the right side of the prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RightPrompt {
+    /// a prompt variable of its own, assigned by a statement that starts with this and ends with the prompt suffix
+    Variable(&'static [u8]),
+    /// drawn at the right edge by moving the cursor there and back again before the left side is written
+    Emulated,
+    /// the shell has no right prompt we can set, so the right side follows the left one
+    Appended,
+}
+
+pub const BASH: Shell = Shell {
+    name: "bash",
+    init: r#"eval "$(nuprompt init bash --print-full-init)""#,
+    full_init: r#"PS0='$(nuprompt ps0 $$ --seq=\# "$(fc -ln -0)")'
+PROMPT_COMMAND='eval "$(nuprompt ps1 $$ $? --history=$HISTCMD --argv0="$0" --shell-flags=$-)"'
+eval "$(nuprompt ps2)""#,
+    warning: r#"echo 'nuprompt: MESSAGE' >&2"#,
+    prompt_prefix: b"PS1='",
+    prompt_suffix: b"'",
+    continuation_prefix: Some(b"PS2='"),
+    escape_open: b"\\[",
+    escape_close: b"\\]",
+    write_literal: write_bash_literal,
+    native_cwd: Some(b"\\w"),
+    export: Some(b"export"),
+    right_prompt: RightPrompt::Emulated,
+};
+
+pub const ZSH: Shell = Shell {
+    name: "zsh",
+    init: r#"eval "$(nuprompt init zsh --print-full-init)""#,
+    full_init: r#"__nuprompt_preexec() { nuprompt ps0 $$ "$1" }
+__nuprompt_precmd() { eval "$(nuprompt ps1 $$ $? --shell=zsh --history=$((HISTCMD + 1)) --argv0="$0" --shell-flags=$-)" }
+autoload -Uz add-zsh-hook
+add-zsh-hook preexec __nuprompt_preexec
+add-zsh-hook precmd __nuprompt_precmd
+eval "$(nuprompt ps2 --shell=zsh)""#,
+    warning: r#"echo 'nuprompt: MESSAGE' >&2"#,
+    prompt_prefix: b"PROMPT='",
+    prompt_suffix: b"'",
+    continuation_prefix: Some(b"PROMPT2='"),
+    escape_open: b"%{",
+    escape_close: b"%}",
+    write_literal: write_zsh_literal,
+    native_cwd: Some(b"%~"),
+    export: Some(b"export"),
+    right_prompt: RightPrompt::Variable(b"\nRPROMPT='"),
+};
+
+pub const FISH: Shell = Shell {
+    name: "fish",
+    init: "nuprompt init fish --print-full-init | source",
+    full_init: r#"function __nuprompt_preexec --on-event fish_preexec
+    nuprompt ps0 $fish_pid "$argv"
+end
+function fish_prompt
+    nuprompt ps1 $fish_pid $status --shell=fish --shell-flags=(status is-login; and echo l)
+end"#,
+    warning: r#"echo 'nuprompt: MESSAGE' >&2"#,
+    prompt_prefix: b"",
+    prompt_suffix: b"",
+    continuation_prefix: None,
+    escape_open: b"",
+    escape_close: b"",
+    write_literal: write_raw,
+    native_cwd: None,
+    export: None,
+    right_prompt: RightPrompt::Appended,
+};
+
+pub const NU: Shell = Shell {
+    name: "nu",
+    // nushell cannot source generated code at runtime so the full init must be saved and sourced from the config
+    init: "nuprompt init nu --print-full-init | save -f ($nu.default-config-dir | path join nuprompt.nu)",
+    full_init: r#"$env.PROMPT_COMMAND = {|| nuprompt ps1 $nu.pid $env.LAST_EXIT_CODE --shell=nu }
+$env.PROMPT_INDICATOR = ""
+$env.PROMPT_MULTILINE_INDICATOR = {|| nuprompt ps2 --shell=nu }
+$env.config = ($env.config | upsert hooks.pre_execution (($env.config.hooks.pre_execution? | default []) | append {|| nuprompt ps0 $nu.pid (commandline) }))"#,
+    warning: r#"print -e 'nuprompt: MESSAGE'"#,
+    prompt_prefix: b"",
+    prompt_suffix: b"",
+    continuation_prefix: Some(b""),
+    escape_open: b"",
+    escape_close: b"",
+    write_literal: write_raw,
+    native_cwd: None,
+    export: None,
+    right_prompt: RightPrompt::Appended,
+};
+
+pub const PWSH: Shell = Shell {
+    name: "pwsh",
+    init: "Invoke-Expression (& nuprompt init pwsh --print-full-init | Out-String)",
+    full_init: r#"function global:prompt {
+    $ec = if ($global:?) { 0 } elseif ($global:LASTEXITCODE) { $global:LASTEXITCODE } else { 1 }
+    (& nuprompt ps1 $PID $ec --shell=pwsh) -join "`n"
+}
+Set-PSReadLineKeyHandler -Key Enter -ScriptBlock {
+    $line = $null
+    $cursor = $null
+    [Microsoft.PowerShell.PSConsoleReadLine]::GetBufferState([ref]$line, [ref]$cursor)
+    & nuprompt ps0 $PID $line
+    [Microsoft.PowerShell.PSConsoleReadLine]::AcceptLine()
+}
+Set-PSReadLineOption -ContinuationPrompt ((& nuprompt ps2 --shell=pwsh) -join "`n")"#,
+    warning: r#"Write-Warning 'nuprompt: MESSAGE'"#,
+    prompt_prefix: b"",
+    prompt_suffix: b"",
+    continuation_prefix: Some(b""),
+    escape_open: b"",
+    escape_close: b"",
+    write_literal: write_raw,
+    native_cwd: None,
+    export: None,
+    right_prompt: RightPrompt::Emulated,
+};
+
+pub const XONSH: Shell = Shell {
+    name: "xonsh",
+    init: "execx($(nuprompt init xonsh --print-full-init))",
+    full_init: r#"import os as __nuprompt_os
+import subprocess as __nuprompt_subprocess
+__nuprompt_rtn = 0
+@events.on_precommand
+def __nuprompt_precommand(cmd, **kwargs):
+    __nuprompt_subprocess.run(["nuprompt", "ps0", str(__nuprompt_os.getpid()), cmd])
+@events.on_postcommand
+def __nuprompt_postcommand(cmd, rtn, **kwargs):
+    global __nuprompt_rtn
+    __nuprompt_rtn = rtn
+def __nuprompt_prompt():
+    args = ["nuprompt", "ps1", str(__nuprompt_os.getpid()), str(__nuprompt_rtn), "--shell=xonsh"]
+    return __nuprompt_subprocess.run(args, stdout=__nuprompt_subprocess.PIPE, text=True).stdout
+$PROMPT = __nuprompt_prompt
+$MULTILINE_PROMPT = __nuprompt_subprocess.run(["nuprompt", "ps2", "--shell=xonsh"], stdout=__nuprompt_subprocess.PIPE, text=True).stdout"#,
+    warning: r#"print('nuprompt: MESSAGE', file=__import__('sys').stderr)"#,
+    prompt_prefix: b"",
+    prompt_suffix: b"",
+    continuation_prefix: Some(b""),
+    escape_open: b"",
+    escape_close: b"",
+    write_literal: write_xonsh_literal,
+    native_cwd: None,
+    export: None,
+    right_prompt: RightPrompt::Appended,
+};
+
+pub const TCSH: Shell = Shell {
+    name: "tcsh",
+    // csh joins the lines of a command substitution with spaces, so every statement of the full init ends with a ;
+    init: r#"eval "`nuprompt init tcsh --print-full-init`""#,
+    full_init: r#"alias postcmd 'nuprompt ps0 $$ "\!#"';
+alias precmd 'eval "`nuprompt ps1 $$ $status --shell=tcsh`"';
+eval "`nuprompt ps2 --shell=tcsh`";"#,
+    warning: r#"echo 'nuprompt: MESSAGE' > /dev/stderr;"#,
+    prompt_prefix: b"set prompt='",
+    prompt_suffix: b"'",
+    continuation_prefix: Some(b"set prompt2='"),
+    escape_open: b"%{",
+    escape_close: b"%}",
+    write_literal: write_tcsh_literal,
+    native_cwd: Some(b"%~"),
+    export: None,
+    right_prompt: RightPrompt::Variable(b"; set rprompt='"),
+};
+
+/// POSIX sh, dash, busybox ash, and osh, for minimal containers and rescue shells. These have no hook before a command
+/// runs, so PS1 runs us through command substitution and the prompt goes without elapsed times. They also have no
+/// markers for non-printing text, which only matters to the few that do line editing.
+pub const SH: Shell = Shell {
+    name: "sh",
+    init: r#"eval "$(nuprompt init sh --print-full-init)""#,
+    full_init: r#"PS1='$(nuprompt ps1 $$ $? --shell=sh)'"#,
+    warning: r#"echo 'nuprompt: MESSAGE' >&2"#,
+    prompt_prefix: b"",
+    prompt_suffix: b"",
+    continuation_prefix: None,
+    escape_open: b"",
+    escape_close: b"",
+    write_literal: write_raw,
+    native_cwd: None,
+    export: None,
+    right_prompt: RightPrompt::Appended,
+};
+
+/// Writes the prompt exactly as the terminal shows it, which is used to measure it.
+pub const PLAIN: Shell = Shell {
+    name: "plain",
+    init: "",
+    full_init: "",
+    warning: "",
+    prompt_prefix: b"",
+    prompt_suffix: b"",
+    continuation_prefix: None,
+    escape_open: b"",
+    escape_close: b"",
+    write_literal: write_raw,
+    native_cwd: None,
+    export: None,
+    right_prompt: RightPrompt::Appended,
+};
+
+pub const SHELLS: &[&Shell] = &[&BASH, &ZSH, &FISH, &NU, &PWSH, &XONSH, &TCSH, &SH];
+
+pub fn find(name: &[u8]) -> Option<&'static Shell> {
+    SHELLS.iter().find(|s| s.name.as_bytes() == name).copied()
+}
+
+pub fn names() -> String {
+    SHELLS.iter().map(|s| s.name).collect::<Vec<_>>().join("|")
+}
+
+/// Write statements that export each variable with a value and unset the others, so that the values from the last
+/// prompt never linger. Values are single quoted and may hold any bytes. Shells that print our output instead of
+/// evaluating it can't be given variables, so nothing is written for them.
+pub fn write_exports(shell: &Shell, vars: &[(&str, Option<&[u8]>)], w: &mut dyn Write) -> Result<(), std::io::Error> {
+    let Some(export) = shell.export else {
+        return Ok(());
+    };
+    for (name, value) in vars {
+        match value {
+            Some(value) => {
+                w.write_all(b"\n")?;
+                w.write_all(export)?;
+                write!(w, " {}='", name)?;
+                write_with_escaped_quote(value, w)?;
+                w.write_all(b"'")?;
+            },
+            None => write!(w, "\nunset {}", name)?,
+        }
+    }
+    Ok(())
+}
+
+/// write some raw bytes but make sure we escape any single quotes.
+fn write_with_escaped_quote(input: &[u8], w: &mut dyn Write) -> Result<(), std::io::Error> {
+    for (i, x) in input.split(|u| *u == b'\'').enumerate() {
+        if i > 0 {
+            w.write_all(b"'\\''")?;
+        }
+        w.write_all(x)?;
+    }
+    Ok(())
+}
+
+/// Bash decodes backslash escapes and then performs parameter expansion and command substitution on PS1, so
+/// backslashes, dollars, and backticks must all be escaped against both passes in addition to the single quotes.
+fn write_bash_literal(input: &[u8], w: &mut dyn Write) -> Result<(), std::io::Error> {
+    let mut escaped = Vec::with_capacity(input.len());
+    for b in input {
+        match b {
+            b'\\' => escaped.extend_from_slice(b"\\\\\\\\"),
+            b'$' | b'`' => escaped.extend_from_slice(&[b'\\', b'\\', *b]),
+            _ => escaped.push(*b),
+        }
+    }
+    write_with_escaped_quote(&escaped, w)
+}
+
+/// Xonsh takes raw ANSI colors but substitutes `{field}` templates in whatever the prompt function returns.
+fn write_xonsh_literal(input: &[u8], w: &mut dyn Write) -> Result<(), std::io::Error> {
+    for b in input {
+        match b {
+            b'{' => w.write_all(b"{{")?,
+            b'}' => w.write_all(b"}}")?,
+            _ => w.write_all(std::slice::from_ref(b))?,
+        }
+    }
+    Ok(())
+}
+
+/// Zsh only performs percent expansion on the prompt unless PROMPT_SUBST is set.
+fn write_zsh_literal(input: &[u8], w: &mut dyn Write) -> Result<(), std::io::Error> {
+    for (i, x) in input.split(|u| *u == b'%').enumerate() {
+        if i > 0 {
+            w.write_all(b"%%")?;
+        }
+        write_with_escaped_quote(x, w)?;
+    }
+    Ok(())
+}
+
+/// Tcsh expands `%` sequences and `!` history numbers in the prompt, and a single quoted word can't hold a quote.
+fn write_tcsh_literal(input: &[u8], w: &mut dyn Write) -> Result<(), std::io::Error> {
+    let mut escaped = Vec::with_capacity(input.len());
+    for b in input {
+        match b {
+            b'%' => escaped.extend_from_slice(b"%%"),
+            b'!' => escaped.extend_from_slice(b"\\!"),
+            _ => escaped.push(*b),
+        }
+    }
+    write_with_escaped_quote(&escaped, w)
+}
+
+/// Make arbitrary bytes safe to show in a terminal without losing information. Printable utf-8 passes through, a
+/// backslash is doubled, and control characters and invalid utf-8 become \xNN escapes of their bytes.
+pub fn escape_non_printable(input: &[u8], out: &mut Vec<u8>) {
+    for chunk in input.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            let mut buf = [0u8; 4];
+            let encoded = c.encode_utf8(&mut buf).as_bytes();
+            if c == '\\' {
+                out.extend_from_slice(b"\\\\");
+            } else if c.is_control() {
+                encoded.iter().for_each(|b| push_hex_escape(*b, out));
+            } else {
+                out.extend_from_slice(encoded);
+            }
+        }
+        chunk.invalid().iter().for_each(|b| push_hex_escape(*b, out));
+    }
+}
+
+fn push_hex_escape(b: u8, out: &mut Vec<u8>) {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    out.extend_from_slice(&[b'\\', b'x', HEX[(b >> 4) as usize], HEX[(b & 0xf) as usize]]);
+}
+
+fn write_raw(input: &[u8], w: &mut dyn Write) -> Result<(), std::io::Error> {
+    w.write_all(input)
+}
+
+/// PromptWriter wraps a termcolor writer so that color changes are surrounded by the shell's non-printing markers.
+pub struct PromptWriter<'a> {
+    pub shell: &'static Shell,
+    pub inner: &'a mut dyn WriteColor,
+    /// colors are adapted to stay readable on it
+    pub background: Background,
+    /// set while writing inside the shell's markers for non-printing text, where colors need no markers of their own
+    pub zero_width: bool,
+    /// replaces the foreground of every color that is set, for a part of the prompt with a color of its own
+    pub fg: Option<Color>,
+}
+
+impl PromptWriter<'_> {
+
+    /// write dynamic text with the escaping rules of the shell after escaping anything non-printable.
+    pub fn write_literal(&mut self, input: &[u8]) -> Result<(), std::io::Error> {
+        let mut printable = Vec::with_capacity(input.len());
+        escape_non_printable(input, &mut printable);
+        (self.shell.write_literal)(&printable, &mut self.inner)
+    }
+
+    /// set the terminal title with an OSC 0 sequence. This is skipped when we're not writing color since that usually
+    /// means the output is not going to a terminal.
+    pub fn write_title(&mut self, title: &[u8]) -> Result<(), std::io::Error> {
+        if !self.supports_color() {
+            return Ok(());
+        }
+        let mut printable = Vec::with_capacity(title.len());
+        escape_non_printable(title, &mut printable);
+        self.inner.write_all(self.shell.escape_open)?;
+        self.inner.write_all(b"\x1b]0;")?;
+        (self.shell.write_literal)(&printable, &mut self.inner)?;
+        self.inner.write_all(b"\x07")?;
+        self.inner.write_all(self.shell.escape_close)
+    }
+
+    /// start an OSC 8 hyperlink to the url, or end the current one with an empty url. Like the title this is skipped
+    /// without color. The url must be percent encoded already.
+    pub fn write_hyperlink(&mut self, url: &[u8]) -> Result<(), std::io::Error> {
+        if !self.supports_color() {
+            return Ok(());
+        }
+        if !self.zero_width {
+            self.inner.write_all(self.shell.escape_open)?;
+        }
+        self.inner.write_all(b"\x1b]8;;")?;
+        (self.shell.write_literal)(url, &mut self.inner)?;
+        self.inner.write_all(b"\x07")?;
+        if !self.zero_width {
+            self.inner.write_all(self.shell.escape_close)?;
+        }
+        Ok(())
+    }
+
+}
+
+impl Write for PromptWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl WriteColor for PromptWriter<'_> {
+    fn supports_color(&self) -> bool {
+        self.inner.supports_color()
+    }
+
+    fn set_color(&mut self, spec: &ColorSpec) -> std::io::Result<()> {
+        if !self.supports_color() {
+            return Ok(());
+        }
+        let mut spec = theme::adapt(spec, self.background);
+        if let Some(fg) = self.fg {
+            spec.set_fg(Some(fg));
+        }
+        if self.zero_width {
+            return self.inner.set_color(&spec);
+        }
+        self.inner.write_all(self.shell.escape_open)?;
+        self.inner.set_color(&spec)?;
+        self.inner.write_all(self.shell.escape_close)
+    }
+
+    fn reset(&mut self) -> std::io::Result<()> {
+        if !self.supports_color() {
+            return Ok(());
+        }
+        if self.zero_width {
+            return self.inner.reset();
+        }
+        self.inner.write_all(self.shell.escape_open)?;
+        self.inner.reset()?;
+        self.inner.write_all(self.shell.escape_close)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// reverse escape_non_printable so that we can check it loses nothing.
+    fn unescape(input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut i = 0;
+        while i < input.len() {
+            match &input[i..] {
+                [b'\\', b'\\', ..] => {
+                    out.push(b'\\');
+                    i += 2;
+                },
+                [b'\\', b'x', h, l, ..] => {
+                    out.push(u8::from_str_radix(std::str::from_utf8(&[*h, *l]).unwrap(), 16).unwrap());
+                    i += 4;
+                },
+                [b, ..] => {
+                    out.push(*b);
+                    i += 1;
+                },
+                [] => unreachable!(),
+            }
+        }
+        out
+    }
+
+    /// a tiny xorshift generator so the fuzz cases are reproducible without extra dependencies.
+    fn random_bytes(seed: &mut u64, max_len: usize) -> Vec<u8> {
+        let mut next = || {
+            *seed ^= *seed << 13;
+            *seed ^= *seed >> 7;
+            *seed ^= *seed << 17;
+            *seed
+        };
+        let len = (next() as usize) % max_len;
+        (0..len).map(|_| {
+            let r = next();
+            // bias towards the interesting bytes: controls, quotes, backslashes, and high bytes
+            match r % 4 {
+                0 => b"\x00\x07\x1b\x7f\\'$`%\n"[(r >> 8) as usize % 10],
+                1 => 0x80 | (r >> 8) as u8,
+                _ => (r >> 8) as u8,
+            }
+        }).collect()
+    }
+
+    /// check that the literal stays inside the surrounding single quotes, except for escaped quotes between them.
+    fn assert_single_quoted(literal: &[u8], name: &str) {
+        let mut quoted = true;
+        let mut i = 0;
+        while i < literal.len() {
+            match (quoted, literal[i]) {
+                (_, b'\'') => quoted = !quoted,
+                (true, _) => {},
+                (false, b'\\') if literal.get(i + 1) == Some(&b'\'') => i += 1,
+                (false, b) => panic!("{} literal has unquoted byte {:?} in {:?}", name, b as char, literal),
+            }
+            i += 1;
+        }
+        assert!(quoted, "{} literal does not end inside the quotes: {:?}", name, literal);
+    }
+
+    #[test]
+    fn escape_non_printable_examples() {
+        let mut out = Vec::new();
+        escape_non_printable(b"main\x1b[31m \xff\\x \xe2\x9f\xab \xc2\x9b", &mut out);
+        assert_eq!(out, b"main\\x1b[31m \\xff\\\\x \xe2\x9f\xab \\xc2\\x9b");
+    }
+
+    #[test]
+    fn escape_non_printable_fuzz() {
+        let mut seed = 0x2545F4914F6CDD1D;
+        for _ in 0..20_000 {
+            let input = random_bytes(&mut seed, 64);
+            let mut out = Vec::new();
+            escape_non_printable(&input, &mut out);
+            let text = std::str::from_utf8(&out).expect("output must be valid utf-8");
+            assert!(!text.chars().any(char::is_control), "control character in {:?}", text);
+            assert_eq!(unescape(&out), input, "escaping of {:?} must be lossless", input);
+        }
+    }
+
+    #[test]
+    fn shell_literals_fuzz() {
+        let mut seed = 0x9E3779B97F4A7C15;
+        for _ in 0..5_000 {
+            let input = random_bytes(&mut seed, 64);
+            let mut printable = Vec::new();
+            escape_non_printable(&input, &mut printable);
+            for shell in SHELLS {
+                let mut out = Vec::new();
+                (shell.write_literal)(&printable, &mut out).unwrap();
+                assert!(!out.iter().any(|b| b.is_ascii_control()), "{} literal contains a control byte", shell.name);
+                if !shell.prompt_suffix.is_empty() {
+                    assert_single_quoted(&out, shell.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tcsh_literal_examples() {
+        let mut out = Vec::new();
+        write_tcsh_literal(b"100% it's done!", &mut out).unwrap();
+        assert_eq!(out, b"100%% it'\\''s done\\!");
+    }
+
+    #[test]
+    fn write_exports_examples() {
+        let mut out = Vec::new();
+        write_exports(&BASH, &[("NUPROMPT_GIT_BRANCH", Some(b"it's")), ("NUPROMPT_GIT_DIRTY", None)], &mut out).unwrap();
+        assert_eq!(out, b"\nexport NUPROMPT_GIT_BRANCH='it'\\''s'\nunset NUPROMPT_GIT_DIRTY");
+        out.clear();
+        write_exports(&FISH, &[("NUPROMPT_GIT_BRANCH", Some(b"main"))], &mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    /// any bytes in a value, including newlines and quotes, stay inside the single quotes.
+    #[test]
+    fn write_exports_fuzz() {
+        let mut seed = 0xD1B54A32D192ED03;
+        for _ in 0..5_000 {
+            let value = random_bytes(&mut seed, 64);
+            let mut out = Vec::new();
+            write_exports(&ZSH, &[("X", Some(&value))], &mut out).unwrap();
+            let literal = out.strip_prefix(b"\nexport X='").and_then(|l| l.strip_suffix(b"'")).expect("export statement");
+            assert_single_quoted(literal, "export");
+        }
+    }
+
+    /// Render a prompt with the given bytes in every field that shows dynamic text.
+    fn hostile_context(bytes: &[u8]) -> crate::render::PromptContext {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        use std::path::PathBuf;
+        let mut ctx = crate::render::PromptContext::new(Some(OsStr::from_bytes(bytes).to_os_string()), None, Some(PathBuf::from(OsStr::from_bytes(bytes))));
+        ctx.username = Some(OsStr::from_bytes(bytes).to_os_string());
+        ctx.host = Some(OsStr::from_bytes(bytes).to_os_string());
+        ctx.command = Some(bytes.to_vec());
+        ctx.projects = vec![String::from_utf8_lossy(bytes).into_owned()];
+        ctx.custom = vec![(b"task".to_vec(), bytes.to_vec())];
+        ctx.git_bits = Some(crate::git::GitBits { head_ref: bytes.to_vec(), ..crate::git::GitBits::default() });
+        ctx
+    }
+
+    /// Evaluate each prompt in the shell and print its expansion, NUL terminated. The shell must be installed.
+    fn expand_in_shell(program: &str, args: &[&str], expand: &[u8], prompts: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        use std::process::{Command, Stdio};
+        let mut script = Vec::new();
+        for prompt in prompts {
+            script.extend_from_slice(prompt);
+            script.push(b'\n');
+            script.extend_from_slice(expand);
+            script.push(b'\n');
+        }
+        let mut child = Command::new(program).args(args).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()
+            .unwrap_or_else(|e| panic!("could not run {}: {}", program, e));
+        // the shell writes while we write, so the script goes in from another thread to keep both pipes moving
+        let mut stdin = child.stdin.take().unwrap();
+        let output = std::thread::scope(|scope| {
+            scope.spawn(move || stdin.write_all(&script).unwrap());
+            child.wait_with_output().unwrap()
+        });
+        assert!(output.stderr.is_empty(), "{} complained: {}", program, String::from_utf8_lossy(&output.stderr));
+        let mut expansions: Vec<Vec<u8>> = output.stdout.split(|b| *b == 0).map(<[u8]>::to_vec).collect();
+        assert_eq!(expansions.pop().as_deref(), Some(&b""[..]), "{} output must end with a NUL", program);
+        expansions
+    }
+
+    /// The prompt the shell shows must be exactly what we meant it to show, whatever the bytes in the branch, user,
+    /// and path. A shell that expands anything we wrote, like a `$(...)` or a `%~`, shows something else.
+    fn assert_round_trip(shell: &'static Shell, program: &str, args: &[&str], expand: &[u8]) {
+        use termcolor::Buffer;
+        let mut seed = 0xA0761D6478BD642F;
+        let mut inputs: Vec<Vec<u8>> = [
+            &b"$(echo injected)"[..], b"`echo injected`", b"${PS1}", b"\\w \\u \\$", b"%~ %n %F{red}", b"'\\''", b"!! !#",
+            b"\\[\\] %{%}", "漢字 ⟫".as_bytes(),
+        ].iter().map(|b| b.to_vec()).collect();
+        inputs.extend((0..300).map(|_| random_bytes(&mut seed, 48)));
+        for color in [false, true] {
+            let render = |shell: &'static Shell, input: &[u8]| {
+                let mut buffer = if color { Buffer::ansi() } else { Buffer::no_color() };
+                crate::render::render(&hostile_context(input), shell, &mut buffer).unwrap();
+                buffer.into_inner()
+            };
+            let prompts: Vec<Vec<u8>> = inputs.iter().map(|i| render(shell, i)).collect();
+            let expansions = expand_in_shell(program, args, expand, &prompts);
+            assert_eq!(expansions.len(), inputs.len());
+            for (input, expansion) in inputs.iter().zip(expansions) {
+                // the non-printing markers expand to these bytes, and a raw rendering has none
+                let shown: Vec<u8> = expansion.into_iter().filter(|b| *b != 1 && *b != 2).collect();
+                assert!(shown == render(&FISH, input), "{} mangled {:?} into {:?}", shell.name, input.escape_ascii().to_string(), shown.escape_ascii().to_string());
+            }
+        }
+    }
+
+    // The round trips need the real shells, so they are left to `cargo test -- --ignored` which ci runs with both
+    // installed.
+    #[test]
+    #[ignore = "needs bash installed"]
+    fn bash_round_trip() {
+        assert_round_trip(&BASH, "bash", &["--norc", "--noprofile", "-s"], b"printf '%s\\0' \"${PS1@P}\"");
+    }
+
+    #[test]
+    #[ignore = "needs zsh installed"]
+    fn zsh_round_trip() {
+        assert_round_trip(&ZSH, "zsh", &["-f", "-s"], b"print -rn -- \"${(%)PROMPT}\"; printf '\\0'");
+    }
+}
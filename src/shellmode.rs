@@ -0,0 +1,58 @@
+/// Set to show whether the shell is a login shell, and whether it is restricted.
+pub const SHELL_MODE_ENVVAR: &str = "NUPROMPT_SHOW_SHELL_MODE";
+
+/// ShellMode is what the hook tells us about how the shell was started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShellMode {
+    pub login: bool,
+    pub restricted: bool,
+}
+
+impl ShellMode {
+
+    /// Work out the mode from `$0` and `$-` as the hook passed them. A login shell is started with a `-` in front of
+    /// its name, or has `l` in its flags in zsh and in what the fish hook passes, and a restricted one has `r`. Without
+    /// either there is nothing to go on.
+    pub fn from_hook(argv0: Option<&[u8]>, flags: Option<&[u8]>) -> Option<ShellMode> {
+        if argv0.is_none() && flags.is_none() {
+            return None;
+        }
+        let flags = flags.unwrap_or_default();
+        let login = argv0.is_some_and(|a| a.starts_with(b"-")) || flags.contains(&b'l');
+        // bash started as rbash is restricted too
+        let name = argv0.map(|a| a.rsplit(|b| *b == b'/').next().unwrap_or(a));
+        let restricted = flags.contains(&b'r') || name.is_some_and(|n| n == b"rbash" || n == b"-rbash");
+        Some(ShellMode { login, restricted })
+    }
+
+    pub fn label(self) -> &'static str {
+        match (self.login, self.restricted) {
+            (true, false) => "login",
+            (false, false) => "nonlogin",
+            (true, true) => "login:r",
+            (false, true) => "nonlogin:r",
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(argv0: Option<&str>, flags: Option<&str>) -> Option<&'static str> {
+        ShellMode::from_hook(argv0.map(str::as_bytes), flags.map(str::as_bytes)).map(ShellMode::label)
+    }
+
+    #[test]
+    fn from_hook_examples() {
+        assert_eq!(label(Some("-bash"), Some("himBHs")), Some("login"));
+        assert_eq!(label(Some("bash"), Some("himBHs")), Some("nonlogin"));
+        assert_eq!(label(Some("zsh"), Some("569JNRXZghiklms")), Some("login"));
+        assert_eq!(label(Some("-rbash"), Some("hiBHs")), Some("login:r"));
+        assert_eq!(label(Some("bash"), Some("hrBHs")), Some("nonlogin:r"));
+        assert_eq!(label(Some("/bin/rbash"), Some("hB")), Some("nonlogin:r"));
+        assert_eq!(label(None, Some("")), Some("nonlogin"));
+        assert_eq!(label(None, None), None);
+    }
+}
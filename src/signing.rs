@@ -0,0 +1,140 @@
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+use git2::Repository;
+use log::debug;
+use crate::process::output_with_timeout;
+
+pub const SIGNING_ENVVAR: &str = "NUPROMPT_SIGNING";
+const SIGNING_TIMEOUT_ENVVAR: &str = "NUPROMPT_SIGNING_TIMEOUT_MS";
+
+/// The default time we allow each gpg or ssh-agent query to take.
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Signing describes whether a commit in this repo would be signed successfully right now.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signing {
+    /// the signing key is available and unlocked (or needs no passphrase)
+    Ready,
+    /// the signing key exists but is locked or not loaded in the agent, so signing will prompt
+    Locked,
+    /// the signing key or agent could not be found, so signing will fail
+    Missing,
+}
+
+impl Signing {
+
+    pub fn marker(&self) -> &'static str {
+        match self {
+            Signing::Ready => "sig",
+            Signing::Locked => "sig?",
+            Signing::Missing => "sig!",
+        }
+    }
+
+}
+
+/// Check the signing status if commit.gpgsign is enabled for the repo, otherwise return None.
+pub fn signing_status(r: &Repository) -> Option<Signing> {
+    let cfg = r.config().ok()?;
+    if !cfg.get_bool("commit.gpgsign").unwrap_or(false) {
+        return None;
+    }
+    let timeout = std::env::var(SIGNING_TIMEOUT_ENVVAR).ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_TIMEOUT);
+    let key = cfg.get_string("user.signingkey").ok();
+    let format = cfg.get_string("gpg.format").unwrap_or_else(|_| String::from("openpgp"));
+    let status = match format.as_str() {
+        "openpgp" => {
+            // without a configured key, gpg picks the key matching the committer email
+            let key = key.or_else(|| cfg.get_string("user.email").ok())?;
+            gpg_status(&key, timeout)
+        },
+        "ssh" => ssh_status(key.as_deref()?, timeout),
+        _ => {
+            debug!("not checking signing for gpg.format {}", format);
+            return None;
+        }
+    };
+    debug!("signing status with {} key: {:?}", format, status);
+    Some(status)
+}
+
+fn gpg_status(key: &str, timeout: Duration) -> Signing {
+    let out = match output_with_timeout(Command::new("gpg")
+        .args(["--batch", "--with-colons", "--with-keygrip", "--list-secret-keys", key]), timeout) {
+        Ok(o) if o.status.success() => o.stdout,
+        Ok(_) => return Signing::Missing,
+        Err(e) => {
+            debug!("failed to list gpg keys: {}", e);
+            return Signing::Missing;
+        }
+    };
+    let grips: Vec<String> = String::from_utf8_lossy(&out).lines()
+        .filter(|l| l.starts_with("grp:"))
+        .filter_map(|l| l.split(':').nth(9).map(str::to_owned))
+        .collect();
+    if grips.is_empty() {
+        return Signing::Missing;
+    }
+    let mut cmd = Command::new("gpg-connect-agent");
+    cmd.arg("--no-autostart");
+    for g in &grips {
+        cmd.arg(format!("KEYINFO {}", g));
+    }
+    cmd.arg("/bye");
+    let out = match output_with_timeout(&mut cmd, timeout) {
+        Ok(o) if o.status.success() => o.stdout,
+        Ok(_) => return Signing::Locked,
+        Err(e) => {
+            debug!("failed to query gpg-agent: {}", e);
+            return Signing::Locked;
+        }
+    };
+    // each line is "S KEYINFO <grip> <type> <serial> <idstr> <cached> <protection> ..."
+    let unlocked = String::from_utf8_lossy(&out).lines()
+        .filter(|l| l.starts_with("S KEYINFO "))
+        .any(|l| {
+            let fields: Vec<&str> = l.split_ascii_whitespace().collect();
+            fields.get(6) == Some(&"1") || fields.get(7) == Some(&"C")
+        });
+    if unlocked { Signing::Ready } else { Signing::Locked }
+}
+
+fn ssh_status(key: &str, timeout: Duration) -> Signing {
+    let public = if let Some(literal) = key.strip_prefix("key::") {
+        literal.to_owned()
+    } else if key.starts_with("ssh-") || key.starts_with("ecdsa-") || key.starts_with("sk-") {
+        key.to_owned()
+    } else {
+        let path = match key.strip_prefix("~/").zip(std::env::var_os("HOME")) {
+            Some((rest, home)) => PathBuf::from(home).join(rest),
+            None => PathBuf::from(key),
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(contents) if !contents.starts_with("-----BEGIN") => contents,
+            // a private key file is used directly by ssh-keygen without the agent
+            Ok(_) => return Signing::Ready,
+            Err(e) => {
+                debug!("failed to read ssh signing key {:?}: {}", path, e);
+                return Signing::Missing;
+            }
+        }
+    };
+    let blob = match public.split_ascii_whitespace().nth(1) {
+        Some(b) => b.to_owned(),
+        None => return Signing::Missing,
+    };
+    match output_with_timeout(Command::new("ssh-add").arg("-L"), timeout) {
+        Ok(o) if String::from_utf8_lossy(&o.stdout).lines().any(|l| l.split_ascii_whitespace().nth(1) == Some(&blob)) => Signing::Ready,
+        // exit code 1 means the agent is running but doesn't have the key
+        Ok(o) if o.status.code() == Some(1) || o.status.success() => Signing::Locked,
+        Ok(_) => Signing::Missing,
+        Err(e) => {
+            debug!("failed to query ssh-agent: {}", e);
+            Signing::Missing
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+use log::debug;
+use crate::cache;
+
+/// The latency budget in milliseconds for each segment. A segment whose recent p95 in a directory is over the budget
+/// is skipped there, and `$NUPROMPT_SEGMENT_<NAME>_BUDGET_MS` sets the budget of a single segment.
+pub const BUDGET_ENVVAR: &str = "NUPROMPT_SEGMENT_BUDGET_MS";
+
+/// The samples are kept per directory in the cache.
+const CACHE_NAMESPACE: &str = "latency";
+/// How many of the most recent samples the p95 is taken over.
+const MAX_SAMPLES: usize = 20;
+/// A segment is never skipped on fewer samples than this, so that one cold start doesn't count against it.
+const MIN_SAMPLES: usize = 5;
+/// A skipped segment is run again on every this many prompts to see whether it has become fast.
+const REPROBE_EVERY: u32 = 20;
+
+/// Record holds the recent durations of one segment in microseconds and how many prompts it has been skipped for.
+#[derive(Debug, PartialEq, Eq)]
+struct Record {
+    name: &'static str,
+    skipped: u32,
+    samples: Vec<u64>,
+    /// set for a skipped segment that is run this time to measure it again
+    probing: bool,
+}
+
+/// Tracker decides which segments to skip in a directory and records how long the others took.
+pub struct Tracker {
+    key: Vec<u8>,
+    records: Vec<Record>,
+    throttled: Vec<&'static str>,
+}
+
+impl Tracker {
+
+    /// Load the samples for the directory, or None when no segment has a budget.
+    pub fn load(cwd: &Path) -> Option<Tracker> {
+        if !any_budget(budget) {
+            return None;
+        }
+        let key = cwd.as_os_str().as_bytes().to_vec();
+        let mut records = cache::get(CACHE_NAMESPACE, &key, None).map_or_else(Vec::new, |raw| decode(&raw));
+        let throttled = throttle(&mut records, budget);
+        if !throttled.is_empty() {
+            debug!("skipping segments over their latency budget: {:?}", throttled);
+        }
+        Some(Tracker { key, records, throttled })
+    }
+
+    /// The segments to skip for this prompt.
+    pub fn throttled(&self) -> &[&'static str] {
+        &self.throttled
+    }
+
+    /// Add the durations of the segments that ran to their samples and save them.
+    pub fn record(mut self, spans: &[(&'static str, Duration)]) -> Result<(), anyhow::Error> {
+        add_samples(&mut self.records, spans, budget);
+        cache::put(CACHE_NAMESPACE, &self.key, encode(&self.records).as_bytes())
+    }
+
+}
+
+/// The budget of the segment in microseconds.
+fn budget(name: &str) -> Option<u64> {
+    let specific = format!("NUPROMPT_SEGMENT_{}_BUDGET_MS", name.to_ascii_uppercase());
+    let raw = std::env::var(specific).or_else(|_| std::env::var(BUDGET_ENVVAR)).ok()?;
+    match raw.trim().parse::<u64>() {
+        Ok(ms) => Some(ms * 1000),
+        Err(e) => {
+            debug!("ignoring invalid latency budget {:?} for {}: {}", raw, name, e);
+            None
+        },
+    }
+}
+
+/// Whether a budget is set for any of the measured segments, by the global budget or one of their own.
+fn any_budget(budget: impl Fn(&str) -> Option<u64>) -> bool {
+    MEASURED.iter().any(|name| budget(name).is_some())
+}
+
+/// The segments whose work is skipped when they are turned off, which are the only ones worth measuring. Each is timed
+/// by a span of the same name.
+const MEASURED: &[&str] = &["git", "detect", "bookmark", "security", "health", "secrets", "updates"];
+
+/// The segment a timing span belongs to. The git segment is made of many spans.
+fn segment_of(span: &str) -> Option<&'static str> {
+    let name = if span.starts_with("git_") { "git" } else { span };
+    MEASURED.iter().find(|s| **s == name).copied()
+}
+
+/// The p95 by nearest rank, so with fewer than twenty samples it is the slowest.
+fn p95(samples: &[u64]) -> u64 {
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+    sorted[(sorted.len() * 95).div_ceil(100).saturating_sub(1)]
+}
+
+/// Pick the segments over budget, except those that are due to be measured again.
+fn throttle(records: &mut [Record], budget: impl Fn(&str) -> Option<u64>) -> Vec<&'static str> {
+    let mut throttled = vec![];
+    for record in records {
+        let over = budget(record.name).is_some_and(|b| record.samples.len() >= MIN_SAMPLES && p95(&record.samples) > b);
+        if !over {
+            record.skipped = 0;
+        } else if record.skipped + 1 >= REPROBE_EVERY {
+            record.skipped = 0;
+            record.probing = true;
+        } else {
+            record.skipped += 1;
+            throttled.push(record.name);
+        }
+    }
+    throttled
+}
+
+/// A probe that comes in under budget wipes the old samples, so the segment is back from the next prompt on rather
+/// than once the slow samples have aged out.
+fn add_samples(records: &mut Vec<Record>, spans: &[(&'static str, Duration)], budget: impl Fn(&str) -> Option<u64>) {
+    let mut totals: Vec<(&'static str, u64)> = vec![];
+    for (span, d) in spans {
+        if let Some(name) = segment_of(span) {
+            match totals.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, total)) => *total += d.as_micros() as u64,
+                None => totals.push((name, d.as_micros() as u64)),
+            }
+        }
+    }
+    for (name, total) in totals {
+        let index = match records.iter().position(|r| r.name == name) {
+            Some(i) => i,
+            None => {
+                records.push(Record { name, skipped: 0, samples: vec![], probing: false });
+                records.len() - 1
+            },
+        };
+        let record = &mut records[index];
+        if record.probing && budget(name).is_some_and(|b| total <= b) {
+            record.samples.clear();
+        }
+        record.samples.push(total);
+        let excess = record.samples.len().saturating_sub(MAX_SAMPLES);
+        record.samples.drain(..excess);
+    }
+}
+
+/// One line per segment, with its name, the prompts it has been skipped for, and the samples.
+fn encode(records: &[Record]) -> String {
+    let mut out = String::new();
+    for r in records {
+        let samples: Vec<String> = r.samples.iter().map(u64::to_string).collect();
+        out.push_str(&format!("{} {} {}\n", r.name, r.skipped, samples.join(",")));
+    }
+    out
+}
+
+fn decode(raw: &[u8]) -> Vec<Record> {
+    String::from_utf8_lossy(raw).lines().filter_map(|line| {
+        let mut fields = line.split(' ');
+        let name = segment_of(fields.next()?)?;
+        let skipped = fields.next()?.parse().ok()?;
+        let samples = fields.next()?.split(',').filter_map(|s| s.parse().ok()).collect();
+        Some(Record { name, skipped, samples, probing: false })
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &'static str, skipped: u32, samples: &[u64]) -> Record {
+        Record { name, skipped, samples: samples.to_vec(), probing: false }
+    }
+
+    #[test]
+    fn p95_examples() {
+        assert_eq!(p95(&[5]), 5);
+        assert_eq!(p95(&[9, 1, 5, 3, 7]), 9);
+        let twenty: Vec<u64> = (1..=20).collect();
+        assert_eq!(p95(&twenty), 19);
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        let records = vec![record("git", 3, &[120000, 98000]), record("detect", 0, &[])];
+        assert_eq!(decode(encode(&records).as_bytes()), records);
+        assert_eq!(decode(b"bogus 1 2\ngit x 1\n"), vec![]);
+    }
+
+    /// A slow segment is skipped until it is due to be measured again, and a fast probe brings it back.
+    #[test]
+    fn throttle_and_reprobe() {
+        let budget = |_: &str| Some(50_000);
+        let mut records = vec![record("git", 0, &[80_000; 5]), record("detect", 0, &[80_000; 4])];
+        assert_eq!(throttle(&mut records, budget), ["git"]);
+        for _ in 2..REPROBE_EVERY {
+            assert_eq!(throttle(&mut records, budget), ["git"]);
+        }
+        assert_eq!(throttle(&mut records, budget), Vec::<&str>::new());
+        assert!(records[0].probing);
+        add_samples(&mut records, &[("git_open", Duration::from_millis(10)), ("git_status", Duration::from_millis(5))], budget);
+        assert_eq!(records[0].samples, [15_000]);
+        assert_eq!(throttle(&mut records, budget), Vec::<&str>::new());
+    }
+
+    /// The budget of one segment is enough to start measuring, without the global one.
+    #[test]
+    fn budget_of_one_segment() {
+        assert!(!any_budget(|_| None));
+        assert!(any_budget(|name| (name == "secrets").then_some(20_000)));
+        assert!(any_budget(|_| Some(50_000)));
+        // only the measured segments count, since only they are ever skipped
+        assert!(!any_budget(|name| (name == "render").then_some(20_000)));
+    }
+
+    #[test]
+    fn keeps_recent_samples() {
+        let mut records = vec![];
+        for ms in 0..30 {
+            add_samples(&mut records, &[("detect", Duration::from_millis(ms)), ("render", Duration::from_millis(1))], |_| None);
+        }
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].samples.len(), MAX_SAMPLES);
+        assert_eq!(records[0].samples[0], 10_000);
+    }
+}
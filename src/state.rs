@@ -0,0 +1,485 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use anyhow::anyhow;
+use log::debug;
+
+/// The directory that holds the state files, which defaults to the temp dir. Point this somewhere local when the temp
+/// dir is on NFS or shared between hosts.
+const STATE_DIR_ENVVAR: &str = "NUPROMPT_STATE_DIR";
+
+/// How long we wait for another process to release the lock before going ahead without it. A stuck lock on a network
+/// file system must not hang the prompt.
+const LOCK_WAIT: Duration = Duration::from_millis(100);
+
+/// The magic bytes at the start of every state file. The version byte follows directly after.
+const MAGIC: &[u8; 3] = b"NUP";
+const VERSION: u8 = 1;
+
+/// The length of a legacy (pre-versioning) state file which held just the big endian start ticks.
+const LEGACY_LEN: usize = 8;
+
+const TAG_START_TICKS: u8 = 1;
+const TAG_COMMAND: u8 = 2;
+const TAG_CWD: u8 = 3;
+const TAG_COMMAND_INDEX: u8 = 4;
+const TAG_SEQUENCE: u8 = 5;
+const TAG_PROFILE: u8 = 6;
+const TAG_FAILURE: u8 = 7;
+const TAG_FAILURES: u8 = 8;
+
+/// State holds whatever we persist for a single shell pid between the ps0 and ps1 invocations.
+///
+/// On disk this is the magic bytes, a version byte, then a sequence of records. Each record is a tag byte, a big endian
+/// u16 length, the value, and a big endian crc32 of the tag, length, and value. Records with unknown tags are skipped so
+/// that older binaries can read files written by newer ones. A truncated or corrupt record ends the decoding, but any
+/// records before it are kept.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct State {
+    pub start_ticks: Option<u64>,
+    pub command: Option<Vec<u8>>,
+    /// the working directory the previous command was started in
+    pub cwd: Option<Vec<u8>>,
+    /// the number of commands started in this shell session, this outlives a single command
+    pub command_index: Option<u64>,
+    /// the prompt cycle the command was started in, as numbered by the shell
+    pub sequence: Option<u64>,
+    /// the config profile picked with `nuprompt use`, this outlives a single command
+    pub profile: Option<Vec<u8>>,
+    /// the exit code and text of the last command that failed, joined by a nul, this outlives a single command
+    pub failure: Option<Vec<u8>>,
+    /// how many times in a row that command failed that way, this outlives a single command
+    pub failures: Option<u64>,
+}
+
+impl State {
+
+    pub fn decode(contents: &[u8]) -> Result<State, anyhow::Error> {
+        let mut st = State::default();
+        if contents.len() == LEGACY_LEN && !contents.starts_with(MAGIC) {
+            debug!("decoding legacy state file");
+            st.start_ticks = Some(u64::from_be_bytes(contents.try_into()?));
+            return Ok(st);
+        }
+        let body = match contents.strip_prefix(MAGIC.as_slice()) {
+            Some([VERSION, body @ ..]) => body,
+            Some([v, ..]) => return Err(anyhow!("unsupported state file version {}", v)),
+            _ => return Err(anyhow!("state file has no header")),
+        };
+        for (tag, value) in Records(body) {
+            match tag {
+                TAG_START_TICKS => st.start_ticks = value.try_into().ok().map(u64::from_be_bytes),
+                TAG_COMMAND => st.command = Some(value.to_vec()),
+                TAG_CWD => st.cwd = Some(value.to_vec()),
+                TAG_COMMAND_INDEX => st.command_index = value.try_into().ok().map(u64::from_be_bytes),
+                TAG_SEQUENCE => st.sequence = value.try_into().ok().map(u64::from_be_bytes),
+                TAG_PROFILE => st.profile = Some(value.to_vec()),
+                TAG_FAILURE => st.failure = Some(value.to_vec()),
+                TAG_FAILURES => st.failures = value.try_into().ok().map(u64::from_be_bytes),
+                _ => debug!("skipping unknown state record tag {}", tag),
+            }
+        }
+        Ok(st)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(32);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        if let Some(ticks) = self.start_ticks {
+            push_record(&mut out, TAG_START_TICKS, &ticks.to_be_bytes());
+        }
+        if let Some(command) = &self.command {
+            push_record(&mut out, TAG_COMMAND, &command[..command.len().min(u16::MAX as usize)]);
+        }
+        // a path longer than a record can hold is left out rather than truncated into a different path
+        if let Some(cwd) = self.cwd.as_ref().filter(|c| c.len() <= u16::MAX as usize) {
+            push_record(&mut out, TAG_CWD, cwd);
+        }
+        if let Some(index) = self.command_index {
+            push_record(&mut out, TAG_COMMAND_INDEX, &index.to_be_bytes());
+        }
+        if let Some(sequence) = self.sequence {
+            push_record(&mut out, TAG_SEQUENCE, &sequence.to_be_bytes());
+        }
+        if let Some(profile) = self.profile.as_ref().filter(|p| p.len() <= u16::MAX as usize) {
+            push_record(&mut out, TAG_PROFILE, profile);
+        }
+        if let Some(failure) = self.failure.as_ref().filter(|f| f.len() <= u16::MAX as usize) {
+            push_record(&mut out, TAG_FAILURE, failure);
+        }
+        if let Some(failures) = self.failures {
+            push_record(&mut out, TAG_FAILURES, &failures.to_be_bytes());
+        }
+        out
+    }
+
+    /// The state after a command starts. Some shell setups run the start hook for every simple command of a line, such
+    /// as each part of a pipeline or each pass of a loop, so a start in the same prompt cycle as one that ps1 has not
+    /// consumed yet is ignored and the duration covers the whole line. Without a sequence number every start counts.
+    pub fn started(self, start_ticks: u64, sequence: Option<u64>, command: Option<Vec<u8>>, cwd: Option<Vec<u8>>) -> State {
+        if self.start_ticks.is_some() && sequence.is_some() && self.sequence == sequence {
+            debug!("ignoring repeated start in prompt cycle {:?}", sequence);
+            return self;
+        }
+        let command_index = Some(self.command_index.unwrap_or(0) + 1);
+        State { start_ticks: Some(start_ticks), command, cwd, command_index, sequence, ..self }
+    }
+
+    /// The state after the command finished with the exit code, None for success, which counts the failures of the
+    /// same command with the same code in a row. Without a command, such as after an empty line or in a shell with no
+    /// start hook, there is nothing to compare and the streak stays as it was.
+    pub fn finished(self, exit_code: Option<&[u8]>) -> State {
+        let Some(command) = &self.command else {
+            return self;
+        };
+        let Some(code) = exit_code else {
+            return State { failure: None, failures: None, ..self };
+        };
+        let failure = [code, b"\0".as_slice(), command].concat();
+        let failures = match self.failure.as_ref() == Some(&failure) {
+            true => self.failures.unwrap_or(0) + 1,
+            false => 1,
+        };
+        State { failure: Some(failure), failures: Some(failures), ..self }
+    }
+
+    /// What is kept once the command is over, the parts that last for the whole session.
+    fn session(&self) -> State {
+        State {
+            command_index: self.command_index,
+            profile: self.profile.clone(),
+            failure: self.failure.clone(),
+            failures: self.failures,
+            ..State::default()
+        }
+    }
+
+}
+
+fn push_record(out: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    let start = out.len();
+    out.push(tag);
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+    let sum = crc32(&out[start..]);
+    out.extend_from_slice(&sum.to_be_bytes());
+}
+
+/// Records iterates over the valid (tag, value) records in a state file body and stops at the first bad one.
+struct Records<'a>(&'a [u8]);
+
+impl<'a> Iterator for Records<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.0;
+        if rest.is_empty() {
+            return None;
+        }
+        // stop iteration on any problem by emptying the remaining input
+        self.0 = &[];
+        let (header, rest) = match rest.split_first_chunk::<3>() {
+            Some(x) => x,
+            None => {
+                debug!("state file truncated in record header");
+                return None;
+            }
+        };
+        let len = u16::from_be_bytes([header[1], header[2]]) as usize;
+        if rest.len() < len + 4 {
+            debug!("state file truncated in record with tag {}", header[0]);
+            return None;
+        }
+        let (value, rest) = rest.split_at(len);
+        let (sum, rest) = rest.split_at(4);
+        let mut expected = crc32(header.as_slice());
+        expected = crc32_update(expected, value);
+        if sum != expected.to_be_bytes() {
+            debug!("state file checksum mismatch in record with tag {}", header[0]);
+            return None;
+        }
+        self.0 = rest;
+        Some((header[0], value))
+    }
+}
+
+/// A small bitwise crc32 (IEEE) since we only ever checksum a handful of bytes.
+fn crc32(input: &[u8]) -> u32 {
+    crc32_update(0, input)
+}
+
+fn crc32_update(sum: u32, input: &[u8]) -> u32 {
+    let mut crc = !sum;
+    for b in input {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB88320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+pub fn state_dir() -> PathBuf {
+    std::env::var_os(STATE_DIR_ENVVAR)
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+/// The file name prefix for the state of a shell. The pid alone does not identify the shell, since pids are reused
+/// after a reboot and a temp dir shared by the users of a machine holds the files of all of them, so the uid and boot
+/// id are part of the name.
+fn file_prefix(uid: u32, boot_id: &str, raw_pid: &OsStr) -> String {
+    format!("NUPROMPT_{}_{}_{}", uid, boot_id, raw_pid.to_string_lossy())
+}
+
+fn current_prefix(raw_pid: &OsStr) -> String {
+    file_prefix(users::get_current_uid(), boot_id(), raw_pid)
+}
+
+/// The random id the kernel picks at boot, or empty outside Linux. There, a file from before a reboot can still be
+/// picked up when the temp dir survives the reboot.
+fn boot_id() -> &'static str {
+    static BOOT_ID: OnceLock<String> = OnceLock::new();
+    BOOT_ID.get_or_init(|| fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .map(|id| id.trim().to_owned())
+        .unwrap_or_default())
+}
+
+fn state_file_path(raw_pid: &OsStr) -> PathBuf {
+    state_dir().join(format!("{}_prev_start", current_prefix(raw_pid)))
+}
+
+/// Take the advisory lock that guards the state file of the pid. The lock lives in a separate file because the state
+/// file itself is replaced on every write. Returns None if the lock could not be taken in time.
+fn lock(raw_pid: &OsStr) -> Result<Option<File>, anyhow::Error> {
+    let dir = state_dir();
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+    let f = File::options().create(true).truncate(false).write(true)
+        .open(dir.join(format!("{}.lock", current_prefix(raw_pid))))?;
+    let start = Instant::now();
+    loop {
+        // SAFETY: the fd is valid for the lifetime of the file.
+        if unsafe { libc::flock(f.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            return Ok(Some(f));
+        }
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EWOULDBLOCK) {
+            return Err(err.into());
+        }
+        if start.elapsed() > LOCK_WAIT {
+            debug!("timed out waiting for the state lock");
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+pub fn read(raw_pid: &OsStr) -> Result<State, anyhow::Error> {
+    read_file(&state_file_path(raw_pid))
+}
+
+fn read_file(path: &Path) -> Result<State, anyhow::Error> {
+    State::decode(&fs::read(path)?)
+}
+
+/// Replace the state of the pid with whatever the function returns for the current state, all under the lock.
+pub fn modify(raw_pid: &OsStr, f: impl FnOnce(State) -> State) -> Result<(), anyhow::Error> {
+    crate::fault::check("state_write")?;
+    let _lock = lock(raw_pid)?;
+    let st = read(raw_pid).unwrap_or_default();
+    write(raw_pid, &f(st))
+}
+
+/// Read the state file for the given pid, count the exit code of the command towards the failure streak, and clear
+/// everything that belongs to a single command, keeping only what lasts for the whole session.
+pub fn take(raw_pid: &OsStr, exit_code: Option<&[u8]>) -> Result<State, anyhow::Error> {
+    crate::fault::check("state_read")?;
+    let _lock = lock(raw_pid)?;
+    let st = read(raw_pid)?.finished(exit_code);
+    // skip the write when the previous prompt already cleared the command, such as after an empty line
+    if st.start_ticks.is_some() || st.command.is_some() || st.cwd.is_some() {
+        write(raw_pid, &st.session())?;
+    }
+    Ok(st)
+}
+
+/// Write the state through a temporary file and a rename so that a reader never sees a partial file. Callers hold the
+/// lock.
+fn write(raw_pid: &OsStr, st: &State) -> Result<(), anyhow::Error> {
+    write_file(&state_file_path(raw_pid), st)
+}
+
+fn write_file(path: &Path, st: &State) -> Result<(), anyhow::Error> {
+    let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&tmp, st.encode())?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the start hook once for each command, all in the same prompt cycle, then take the state like ps1 does.
+    fn run_line(st: State, sequence: Option<u64>, commands: &[&str]) -> State {
+        let mut st = st;
+        for (ticks, command) in commands.iter().enumerate() {
+            st = st.started(100 + ticks as u64, sequence, Some(command.as_bytes().to_vec()), None);
+        }
+        st
+    }
+
+    fn take_session(st: &State) -> State {
+        st.session()
+    }
+
+    #[test]
+    fn started_keeps_first_start_of_pipeline() {
+        let st = run_line(State::default(), Some(7), &["make 2>&1 | grep error | head", "grep error", "head"]);
+        assert_eq!(st.start_ticks, Some(100));
+        assert_eq!(st.command.as_deref(), Some(b"make 2>&1 | grep error | head".as_slice()));
+        assert_eq!(st.command_index, Some(1));
+    }
+
+    #[test]
+    fn started_keeps_first_start_of_loop() {
+        let line = "for f in *.log; do gzip \"$f\"; done";
+        let st = run_line(State::default(), Some(3), &[line, "gzip a.log", "gzip b.log", "gzip c.log"]);
+        assert_eq!(st.start_ticks, Some(100));
+        assert_eq!(st.command.as_deref(), Some(line.as_bytes()));
+    }
+
+    #[test]
+    fn started_keeps_first_start_of_here_doc() {
+        let line = "cat <<EOF | wc -l\none\ntwo\nEOF";
+        let st = run_line(State::default(), Some(12), &[line, "wc -l"]);
+        assert_eq!(st.start_ticks, Some(100));
+        assert_eq!(st.command.as_deref(), Some(line.as_bytes()));
+    }
+
+    #[test]
+    fn started_counts_each_prompt_cycle() {
+        let st = run_line(State::default(), Some(1), &["true && false", "false"]);
+        let st = run_line(take_session(&st), Some(2), &["sleep 1; sleep 2", "sleep 2"]);
+        assert_eq!(st.command.as_deref(), Some(b"sleep 1; sleep 2".as_slice()));
+        assert_eq!(st.command_index, Some(2));
+        // a new cycle replaces a start that ps1 never consumed, such as when the shell was interrupted
+        let st = run_line(st, Some(3), &["ls"]);
+        assert_eq!(st.command.as_deref(), Some(b"ls".as_slice()));
+        assert_eq!(st.command_index, Some(3));
+    }
+
+    #[test]
+    fn started_without_sequence_takes_every_start() {
+        let st = run_line(State::default(), None, &["a | b", "b"]);
+        assert_eq!(st.start_ticks, Some(101));
+        assert_eq!(st.command.as_deref(), Some(b"b".as_slice()));
+    }
+
+    #[test]
+    fn finished_counts_the_same_failure_in_a_row() {
+        let finish = |st: &State, command: &str, code: Option<&str>| {
+            run_line(take_session(st), None, &[command]).finished(code.map(str::as_bytes))
+        };
+        let st = finish(&State::default(), "make", Some("2"));
+        let st = finish(&st, "make", Some("2"));
+        let st = finish(&st, "make", Some("2"));
+        assert_eq!(st.failures, Some(3));
+        // an empty line runs no command and leaves the streak alone
+        assert_eq!(take_session(&st).finished(Some(b"2")).failures, Some(3));
+        assert_eq!(finish(&st, "make", Some("1")).failures, Some(1));
+        assert_eq!(finish(&st, "make -k", Some("2")).failures, Some(1));
+        let st = finish(&st, "make", None);
+        assert_eq!((st.failure, st.failures), (None, None));
+    }
+
+    /// Two users of a shared temp dir, or two boots, with shells of the same pid never see each other's state.
+    #[test]
+    fn pid_collision_keeps_state_apart() {
+        let dir = std::env::temp_dir().join(format!("nuprompt-state-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let pid = OsStr::new("4242");
+        let path = |uid, boot_id| dir.join(format!("{}_prev_start", file_prefix(uid, boot_id, pid)));
+        let boot = "6f1c2b4e-11d0-4c5e-9a53-0b1d2f3a4c5d";
+        write_file(&path(1000, boot), &State { start_ticks: Some(1), ..State::default() }).unwrap();
+
+        assert!(read_file(&path(1001, boot)).is_err(), "another user with the same pid");
+        assert!(read_file(&path(1000, "0a9b8c7d-0000-4000-8000-000000000000")).is_err(), "the same pid after a reboot");
+        assert_eq!(read_file(&path(1000, boot)).unwrap().start_ticks, Some(1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        let st = State { start_ticks: Some(42), command: Some(b"ls".to_vec()), cwd: Some(b"/tmp".to_vec()), command_index: Some(9), sequence: Some(5), profile: Some(b"demo".to_vec()), failure: Some(b"1\0make".to_vec()), failures: Some(2) };
+        let decoded = State::decode(&st.encode()).unwrap();
+        assert_eq!(decoded.start_ticks, Some(42));
+        assert_eq!(decoded.command.as_deref(), Some(b"ls".as_slice()));
+        assert_eq!(decoded.cwd.as_deref(), Some(b"/tmp".as_slice()));
+        assert_eq!(decoded.command_index, Some(9));
+        assert_eq!(decoded.sequence, Some(5));
+        assert_eq!(decoded.profile.as_deref(), Some(b"demo".as_slice()));
+        assert_eq!(decoded.failure.as_deref(), Some(b"1\0make".as_slice()));
+        assert_eq!(decoded.failures, Some(2));
+    }
+
+    /// What the prompt gets for a state file, which falls back to the default state when the file can't be decoded.
+    fn decoded(contents: &[u8]) -> State {
+        State::decode(contents).unwrap_or_default()
+    }
+
+    #[test]
+    fn decode_empty_or_headerless() {
+        assert_eq!(decoded(b""), State::default());
+        assert_eq!(decoded(b"NU"), State::default());
+        assert_eq!(decoded(b"NUP"), State::default());
+        // shorter than the legacy start ticks, and not one of our files at all
+        assert_eq!(decoded(&[0, 0, 0, 42]), State::default());
+        assert_eq!(decoded(b"something else entirely"), State::default());
+    }
+
+    #[test]
+    fn decode_legacy() {
+        assert_eq!(decoded(&42u64.to_be_bytes()), State { start_ticks: Some(42), ..State::default() });
+        assert_eq!(decoded(&42u64.to_be_bytes()[1..]), State::default());
+    }
+
+    #[test]
+    fn decode_unknown_version() {
+        let mut contents = State { start_ticks: Some(42), ..State::default() }.encode();
+        contents[MAGIC.len()] = VERSION + 1;
+        assert!(State::decode(&contents).is_err());
+        assert_eq!(decoded(&contents), State::default());
+    }
+
+    #[test]
+    fn decode_truncated_record() {
+        let contents = State { start_ticks: Some(42), ..State::default() }.encode();
+        // every cut inside the record, from the middle of the header to the last byte of the checksum
+        for len in MAGIC.len() + 1..contents.len() {
+            assert_eq!(decoded(&contents[..len]), State::default(), "cut at {}", len);
+        }
+        // the records before a truncated one are kept
+        let contents = State { start_ticks: Some(42), command: Some(b"make".to_vec()), ..State::default() }.encode();
+        assert_eq!(decoded(&contents[..contents.len() - 2]), State { start_ticks: Some(42), ..State::default() });
+    }
+
+    #[test]
+    fn decode_bad_checksum() {
+        let mut contents = State { start_ticks: Some(42), ..State::default() }.encode();
+        let last = contents.len() - 1;
+        contents[last] ^= 0xff;
+        assert_eq!(decoded(&contents), State::default());
+        // a flipped bit in the value is caught the same way
+        let mut contents = State { command: Some(b"make".to_vec()), ..State::default() }.encode();
+        contents[MAGIC.len() + 1 + 3] ^= 0x01;
+        assert_eq!(decoded(&contents), State::default());
+    }
+}
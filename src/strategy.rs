@@ -0,0 +1,247 @@
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+use git2::Repository;
+use log::debug;
+use crate::cache;
+use crate::gitcli;
+
+/// Force a strategy for every repo: full, index, or head. The `nuprompt.strategy` git config of a repo takes precedence.
+const STRATEGY_ENVVAR: &str = "NUPROMPT_STATUS_STRATEGY";
+const STRATEGY_CONFIG: &str = "nuprompt.strategy";
+/// Repos with at least this many index entries only compare HEAD with the index.
+const MEDIUM_ENVVAR: &str = "NUPROMPT_STATUS_MEDIUM";
+/// Repos with at least this many index entries skip the status scan entirely.
+const LARGE_ENVVAR: &str = "NUPROMPT_STATUS_LARGE";
+/// A scan slower than this many milliseconds downgrades the strategy for the repo.
+const BUDGET_ENVVAR: &str = "NUPROMPT_STATUS_BUDGET_MS";
+
+const DEFAULT_MEDIUM: usize = 20_000;
+const DEFAULT_LARGE: usize = 200_000;
+const DEFAULT_BUDGET: Duration = Duration::from_millis(200);
+
+/// How long a remembered decision is trusted before the repo is measured again.
+const DECISION_TTL: Duration = Duration::from_secs(24 * 3600);
+
+/// Strategy is how much of the status we compute for a repo, from most to least expensive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// compare HEAD, the index, and the worktree
+    Full,
+    /// compare HEAD with the index without refreshing it, which never touches the worktree
+    IndexOnly,
+    /// only read HEAD
+    HeadOnly,
+}
+
+impl Strategy {
+
+    fn from_name(name: &str) -> Option<Strategy> {
+        match name {
+            "full" => Some(Strategy::Full),
+            "index" => Some(Strategy::IndexOnly),
+            "head" => Some(Strategy::HeadOnly),
+            _ => None,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Strategy::Full => b'f',
+            Strategy::IndexOnly => b'i',
+            Strategy::HeadOnly => b'h',
+        }
+    }
+
+    fn from_byte(b: u8) -> Option<Strategy> {
+        match b {
+            b'f' => Some(Strategy::Full),
+            b'i' => Some(Strategy::IndexOnly),
+            b'h' => Some(Strategy::HeadOnly),
+            _ => None,
+        }
+    }
+
+    /// the next cheaper strategy
+    fn downgrade(self) -> Strategy {
+        match self {
+            Strategy::Full => Strategy::IndexOnly,
+            Strategy::IndexOnly | Strategy::HeadOnly => Strategy::HeadOnly,
+        }
+    }
+
+}
+
+const CACHE_NAMESPACE: &str = "strategy";
+
+fn env_usize(name: &str, default: usize) -> usize {
+    std::env::var(name).ok().and_then(|v| v.trim().parse().ok()).unwrap_or(default)
+}
+
+/// Pick the strategy for the repo. A strategy configured for the repo or forced for all of them wins, then a recent
+/// remembered decision, then a full scan when git keeps a status cache, and otherwise the size of the index decides.
+pub fn choose(r: &Repository) -> Strategy {
+    let configured = r.config().ok().and_then(|c| c.get_string(STRATEGY_CONFIG).ok());
+    let forced = configured.or_else(|| std::env::var(STRATEGY_ENVVAR).ok());
+    let workdir = r.workdir();
+    let sizes = (env_usize(MEDIUM_ENVVAR, DEFAULT_MEDIUM), env_usize(LARGE_ENVVAR, DEFAULT_LARGE));
+    let decision = decide(
+        forced.as_deref(),
+        || {
+            let remembered = cache::get(CACHE_NAMESPACE, workdir?.as_os_str().as_bytes(), Some(DECISION_TTL))?;
+            remembered.first().copied().and_then(Strategy::from_byte)
+        },
+        // a bare repo has no worktree to scan
+        || workdir.is_some() && gitcli::uses_status_cache(r),
+        || match r.index() {
+            Ok(index) => workdir.map(|_| index.len()),
+            Err(e) => {
+                debug!("could not read index: {}", e);
+                None
+            },
+        },
+        sizes,
+    );
+    match decision {
+        Decision::Kept(s) => s,
+        Decision::Chosen(s) => {
+            if let Some(workdir) = workdir {
+                remember(workdir, s);
+            }
+            s
+        },
+    }
+}
+
+/// Decision is the strategy for a repo and whether it is a new one that should be remembered.
+#[derive(Debug, PartialEq, Eq)]
+enum Decision {
+    /// forced, remembered, or a stopgap, so there is nothing to save
+    Kept(Strategy),
+    Chosen(Strategy),
+}
+
+/// The strategy from what is known about the repo. Each input is only looked at when no earlier one decided, since
+/// reading the cache, the config, and the index all cost something. The sizes are the medium and large index sizes.
+fn decide(
+    forced: Option<&str>,
+    remembered: impl FnOnce() -> Option<Strategy>,
+    status_cache: impl FnOnce() -> bool,
+    entries: impl FnOnce() -> Option<usize>,
+    (medium, large): (usize, usize),
+) -> Decision {
+    if let Some(forced) = forced {
+        match Strategy::from_name(forced.trim()) {
+            Some(s) => return Decision::Kept(s),
+            None => debug!("ignoring unknown status strategy {:?}", forced),
+        }
+    }
+    if let Some(s) = remembered() {
+        debug!("using remembered status strategy {:?}", s);
+        return Decision::Kept(s);
+    }
+    // the budget still downgrades the repo if the caches turn out not to help
+    if status_cache() {
+        debug!("git keeps a status cache, chose status strategy {:?}", Strategy::Full);
+        return Decision::Chosen(Strategy::Full);
+    }
+    // an index that can't be read now may well be readable on the next prompt, so this is not remembered
+    let Some(entries) = entries() else {
+        return Decision::Kept(Strategy::HeadOnly);
+    };
+    let s = if entries >= large {
+        Strategy::HeadOnly
+    } else if entries >= medium {
+        Strategy::IndexOnly
+    } else {
+        Strategy::Full
+    };
+    debug!("index has {} entries, chose status strategy {:?}", entries, s);
+    Decision::Chosen(s)
+}
+
+/// Downgrade the remembered strategy for the repo if the scan took longer than the budget.
+pub fn observe(r: &Repository, used: Strategy, took: Duration) {
+    if let Some(workdir) = r.workdir() {
+        observe_workdir(workdir, used, took);
+    }
+}
+
+/// Downgrade the remembered strategy for the worktree if the scan took longer than the budget, for when the repo is
+/// no longer at hand.
+pub fn observe_workdir(workdir: &Path, used: Strategy, took: Duration) {
+    let budget = std::env::var(BUDGET_ENVVAR).ok()
+        .and_then(|v| v.trim().parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_BUDGET);
+    if let Some(s) = downgraded(used, took, budget) {
+        remember(workdir, s);
+    }
+}
+
+/// The strategy to use instead once a scan with the used one took longer than the budget.
+fn downgraded(used: Strategy, took: Duration, budget: Duration) -> Option<Strategy> {
+    if took <= budget {
+        return None;
+    }
+    debug!("status scan took {:?} which is over the budget of {:?}", took, budget);
+    Some(used.downgrade())
+}
+
+fn remember(workdir: &Path, s: Strategy) {
+    if let Err(e) = cache::put(CACHE_NAMESPACE, workdir.as_os_str().as_bytes(), &[s.to_byte()]) {
+        debug!("failed to remember status strategy: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZES: (usize, usize) = (DEFAULT_MEDIUM, DEFAULT_LARGE);
+
+    fn by_size(entries: usize, sizes: (usize, usize)) -> Decision {
+        decide(None, || None, || false, || Some(entries), sizes)
+    }
+
+    #[test]
+    fn index_size_thresholds() {
+        assert_eq!(by_size(0, SIZES), Decision::Chosen(Strategy::Full));
+        assert_eq!(by_size(DEFAULT_MEDIUM - 1, SIZES), Decision::Chosen(Strategy::Full));
+        assert_eq!(by_size(DEFAULT_MEDIUM, SIZES), Decision::Chosen(Strategy::IndexOnly));
+        assert_eq!(by_size(DEFAULT_LARGE - 1, SIZES), Decision::Chosen(Strategy::IndexOnly));
+        assert_eq!(by_size(DEFAULT_LARGE, SIZES), Decision::Chosen(Strategy::HeadOnly));
+        assert_eq!(by_size(50, (10, 100)), Decision::Chosen(Strategy::IndexOnly));
+        // an unreadable index, or none at all in a bare repo, gets the cheapest strategy
+        assert_eq!(decide(None, || None, || false, || None, SIZES), Decision::Kept(Strategy::HeadOnly));
+    }
+
+    #[test]
+    fn configured_strategy_wins() {
+        let unused = || -> Option<usize> { panic!("the index is not needed") };
+        let remembered = || Some(Strategy::Full);
+        assert_eq!(decide(Some(" index "), remembered, || true, unused, SIZES), Decision::Kept(Strategy::IndexOnly));
+        assert_eq!(decide(Some("head"), || None, || true, unused, SIZES), Decision::Kept(Strategy::HeadOnly));
+        assert_eq!(decide(Some("bogus"), || None, || false, || Some(1), SIZES), Decision::Chosen(Strategy::Full));
+    }
+
+    #[test]
+    fn remembered_and_status_cache() {
+        let unused = || -> Option<usize> { panic!("the index is not needed") };
+        let remembered = || Some(Strategy::IndexOnly);
+        assert_eq!(decide(None, remembered, || true, unused, SIZES), Decision::Kept(Strategy::IndexOnly));
+        assert_eq!(decide(None, || None, || true, unused, SIZES), Decision::Chosen(Strategy::Full));
+        let stored = Strategy::from_byte(Strategy::HeadOnly.to_byte());
+        assert_eq!(decide(None, || stored, || false, unused, SIZES), Decision::Kept(Strategy::HeadOnly));
+    }
+
+    #[test]
+    fn slow_scans_downgrade() {
+        let ms = Duration::from_millis;
+        assert_eq!(downgraded(Strategy::Full, ms(201), DEFAULT_BUDGET), Some(Strategy::IndexOnly));
+        assert_eq!(downgraded(Strategy::IndexOnly, ms(500), DEFAULT_BUDGET), Some(Strategy::HeadOnly));
+        assert_eq!(downgraded(Strategy::HeadOnly, ms(500), DEFAULT_BUDGET), Some(Strategy::HeadOnly));
+        assert_eq!(downgraded(Strategy::Full, ms(200), DEFAULT_BUDGET), None);
+        assert_eq!(downgraded(Strategy::Full, ms(30), ms(20)), Some(Strategy::IndexOnly));
+    }
+}
@@ -0,0 +1,237 @@
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use log::debug;
+use crate::cache;
+use crate::process::output_with_timeout;
+
+/// Set to show pending package updates and whether the system needs a reboot.
+pub const UPDATES_ENVVAR: &str = "NUPROMPT_SYSTEM_UPDATES";
+
+/// Package managers are slow to ask, so the answer is refreshed in the background at most this often.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+const CHECK_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Created by Debian and Ubuntu when an installed update needs a reboot.
+const REBOOT_REQUIRED_PATHS: &[&str] = &["/run/reboot-required", "/var/run/reboot-required"];
+
+/// Updates describes the pending system updates.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Updates {
+    pub reboot_required: bool,
+    /// the number of packages with updates, if a known package manager answered
+    pub pending: Option<usize>,
+}
+
+impl Updates {
+
+    /// The cache is the reboot flag and the count, or - when no package manager answered, e.g. `0 12`.
+    fn encode(&self) -> String {
+        let pending = self.pending.map_or(String::from("-"), |n| n.to_string());
+        format!("{} {}\n", self.reboot_required as u8, pending)
+    }
+
+    fn decode(contents: &str) -> Option<Updates> {
+        let (reboot, pending) = contents.trim().split_once(' ')?;
+        Some(Updates {
+            reboot_required: reboot == "1",
+            pending: match pending {
+                "-" => None,
+                n => Some(n.parse().ok()?),
+            },
+        })
+    }
+
+}
+
+/// The updates of the machine are a single entry in the cache.
+const CACHE_NAMESPACE: &str = "updates";
+const CACHE_KEY: &[u8] = b"system";
+
+/// Return the last known updates and start a background refresh when they are more than an hour old. The reboot flag
+/// is a single stat, so it is always checked on the spot.
+pub fn cached() -> Option<Updates> {
+    let contents = cache::get(CACHE_NAMESPACE, CACHE_KEY, Some(REFRESH_INTERVAL)).or_else(|| {
+        let stale = cache::get(CACHE_NAMESPACE, CACHE_KEY, None);
+        if let Err(e) = claim_refresh(stale.as_deref()).and_then(|_| spawn_refresh()) {
+            debug!("could not start updates refresh: {}", e);
+        }
+        stale
+    });
+    let mut updates = contents.and_then(|c| Updates::decode(&String::from_utf8_lossy(&c))).unwrap_or_default();
+    updates.reboot_required |= reboot_required();
+    Some(updates).filter(|u| u.reboot_required || u.pending.is_some_and(|n| n > 0))
+}
+
+/// Write the last known updates again so that the prompts shown while the refresh runs find a fresh entry and don't
+/// start another one.
+fn claim_refresh(stale: Option<&[u8]>) -> Result<(), anyhow::Error> {
+    cache::put(CACHE_NAMESPACE, CACHE_KEY, stale.unwrap_or_default())
+}
+
+fn spawn_refresh() -> Result<(), anyhow::Error> {
+    let mut cmd = Command::new(std::env::current_exe()?);
+    cmd.arg("updates")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // SAFETY: setsid is async-signal-safe. We detach so the refresh is not tied to the terminal session.
+    unsafe {
+        cmd.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    let child = cmd.spawn()?;
+    debug!("spawned updates refresh pid {}", child.id());
+    Ok(())
+}
+
+fn reboot_required() -> bool {
+    REBOOT_REQUIRED_PATHS.iter().any(|p| Path::new(p).exists())
+}
+
+/// Ask the package manager for pending updates, write the cache, and print the result. This is what the background
+/// refresh runs.
+pub fn refresh() -> Result<(), anyhow::Error> {
+    let updates = Updates { reboot_required: reboot_required(), pending: pending_packages() };
+    cache::put(CACHE_NAMESPACE, CACHE_KEY, updates.encode().as_bytes())?;
+    match updates.pending {
+        Some(n) => println!("{} pending updates", n),
+        None => println!("no known package manager"),
+    }
+    if updates.reboot_required {
+        println!("reboot required");
+    }
+    Ok(())
+}
+
+/// A package manager command and how to count the updates from its exit code and stdout.
+type Check = (&'static str, &'static [&'static str], fn(i32, &str) -> Option<usize>);
+
+/// Count the upgradable packages with the first package manager that is installed. None of these need root or touch
+/// the package database.
+fn pending_packages() -> Option<usize> {
+    let checks: &[Check] = &[
+        ("apt-get", &["-s", "-o", "Debug::NoLocking=true", "upgrade"], apt_get_pending),
+        ("dnf", &["check-update", "-q"], dnf_pending),
+        ("checkupdates", &[], checkupdates_pending),
+        ("apk", &["list", "-u"], apk_pending),
+    ];
+    for (program, args, parse) in checks {
+        let output = match output_with_timeout(Command::new(program).args(*args), CHECK_TIMEOUT) {
+            Ok(output) => output,
+            Err(e) => {
+                debug!("could not run {}: {}", program, e);
+                continue;
+            }
+        };
+        let count = parse(output.status.code().unwrap_or(-1), &String::from_utf8_lossy(&output.stdout));
+        debug!("{} reports {:?} pending updates", program, count);
+        if count.is_some() {
+            return count;
+        }
+    }
+    None
+}
+
+/// A simulated upgrade prints one Inst line per package.
+fn apt_get_pending(code: i32, out: &str) -> Option<usize> {
+    Some(out.lines().filter(|l| l.starts_with("Inst ")).count()).filter(|_| code == 0)
+}
+
+/// Exits with 100 and lists the packages when there are updates.
+fn dnf_pending(code: i32, out: &str) -> Option<usize> {
+    match code {
+        0 => Some(0),
+        100 => Some(out.lines().filter(|l| l.split_whitespace().count() == 3).count()),
+        _ => None,
+    }
+}
+
+/// Exits with 2 when there are no updates.
+fn checkupdates_pending(code: i32, out: &str) -> Option<usize> {
+    match code {
+        0 => Some(out.lines().filter(|l| !l.trim().is_empty()).count()),
+        2 => Some(0),
+        _ => None,
+    }
+}
+
+fn apk_pending(code: i32, out: &str) -> Option<usize> {
+    Some(out.lines().filter(|l| !l.trim().is_empty()).count()).filter(|_| code == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apt_get_output() {
+        let out = "\
+Reading package lists...
+Building dependency tree...
+Reading state information...
+Calculating upgrade...
+The following packages will be upgraded:
+  curl libcurl4
+2 upgraded, 0 newly installed, 0 to remove and 0 not upgraded.
+Inst libcurl4 [7.81.0-1ubuntu1.15] (7.81.0-1ubuntu1.16 Ubuntu:22.04/jammy-updates [amd64]) []
+Inst curl [7.81.0-1ubuntu1.15] (7.81.0-1ubuntu1.16 Ubuntu:22.04/jammy-updates [amd64])
+Conf libcurl4 (7.81.0-1ubuntu1.16 Ubuntu:22.04/jammy-updates [amd64])
+Conf curl (7.81.0-1ubuntu1.16 Ubuntu:22.04/jammy-updates [amd64])
+";
+        assert_eq!(apt_get_pending(0, out), Some(2));
+        assert_eq!(apt_get_pending(0, "Reading package lists...\n0 upgraded, 0 newly installed\n"), Some(0));
+        assert_eq!(apt_get_pending(100, out), None);
+    }
+
+    #[test]
+    fn dnf_output() {
+        let out = "\
+curl.x86_64                         8.2.1-3.fc39                    updates
+kernel.x86_64                       6.8.9-100.fc39                  updates
+Obsoleting Packages
+";
+        assert_eq!(dnf_pending(100, out), Some(2));
+        assert_eq!(dnf_pending(0, ""), Some(0));
+        assert_eq!(dnf_pending(1, "Error: Failed to download metadata for repo 'updates'\n"), None);
+    }
+
+    #[test]
+    fn checkupdates_output() {
+        let out = "curl 8.7.1-1 -> 8.8.0-1\nlinux 6.9.1.arch1-1 -> 6.9.2.arch1-1\n\n";
+        assert_eq!(checkupdates_pending(0, out), Some(2));
+        assert_eq!(checkupdates_pending(2, ""), Some(0));
+        assert_eq!(checkupdates_pending(1, "==> ERROR: Cannot fetch updates\n"), None);
+    }
+
+    #[test]
+    fn apk_output() {
+        let out = "\
+curl-8.9.0-r0 x86_64 {curl} (curl) [upgradable from: curl-8.8.0-r0]
+musl-1.2.5-r1 x86_64 {musl} (MIT) [upgradable from: musl-1.2.5-r0]
+";
+        assert_eq!(apk_pending(0, out), Some(2));
+        assert_eq!(apk_pending(0, ""), Some(0));
+        assert_eq!(apk_pending(1, out), None);
+    }
+
+    #[test]
+    fn cache_round_trip() {
+        let cases = [
+            Updates { reboot_required: false, pending: None },
+            Updates { reboot_required: true, pending: None },
+            Updates { reboot_required: false, pending: Some(0) },
+            Updates { reboot_required: true, pending: Some(12) },
+        ];
+        for updates in cases {
+            assert_eq!(Updates::decode(&updates.encode()), Some(updates));
+        }
+        assert_eq!(Updates::encode(&Updates { reboot_required: true, pending: Some(12) }), "1 12\n");
+        assert_eq!(Updates::decode(""), None);
+        assert_eq!(Updates::decode("0"), None);
+        assert_eq!(Updates::decode("0 many"), None);
+    }
+}
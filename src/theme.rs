@@ -0,0 +1,294 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use log::debug;
+use termcolor::{Color, ColorSpec};
+use crate::layout::Part;
+
+/// `light` or `dark`. When unset, the bash and zsh init asks the terminal for its background and exports the answer.
+pub const BG_ENVVAR: &str = "NUPROMPT_BG";
+
+/// Pick a theme by name, which is a set of part colors under `[theme.<name>]` in the config file, or in
+/// `$NUPROMPT_THEME_<NAME>_<PART>` variables. `inherit` names the theme it builds on, such as `default` for the built in
+/// colors, so that it only has to list what it changes. A theme that inherits nothing must give every part a color.
+pub const THEME_ENVVAR: &str = "NUPROMPT_THEME";
+
+/// The built in colors, which every chain of inheritance ends at.
+const DEFAULT_THEME: &str = "default";
+
+/// How long to wait for the terminal to answer the background query. Terminals that answer do so well within this.
+const QUERY_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Background {
+    #[default]
+    Dark,
+    Light,
+}
+
+impl Background {
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Background::Dark => "dark",
+            Background::Light => "light",
+        }
+    }
+
+    /// The background from the environment, assuming dark when it's unset or unknown.
+    pub fn from_env() -> Background {
+        match std::env::var(BG_ENVVAR).as_deref().map(str::trim) {
+            Ok("light") => Background::Light,
+            Ok("dark") | Err(_) => Background::Dark,
+            Ok(other) => {
+                debug!("ignoring unknown {} {:?}", BG_ENVVAR, other);
+                Background::Dark
+            },
+        }
+    }
+
+}
+
+/// `$NUPROMPT_COLOR_<PART>` replaces the colors of a part of the prompt while keeping their other styles, e.g.
+/// `NUPROMPT_COLOR_GIT=blue`. These win over the colors of the theme.
+pub fn part_colors() -> Vec<(Part, Color)> {
+    let mut colors: Vec<(Part, Color)> = Part::ALL.iter().filter_map(|part| {
+        let name = format!("NUPROMPT_COLOR_{}", part.name().to_ascii_uppercase());
+        let raw = std::env::var(&name).ok()?;
+        match Color::from_str(raw.trim()) {
+            Ok(color) => Some((*part, color)),
+            Err(e) => {
+                debug!("ignoring invalid {}: {}", name, e);
+                None
+            },
+        }
+    }).collect();
+    if let Some(name) = theme_name() {
+        let (theme, problems) = resolve_theme(&name, &theme_vars());
+        problems.iter().for_each(|p| debug!("{}", p));
+        for (part, color) in theme {
+            if !colors.iter().any(|(p, _)| *p == part) {
+                colors.push((part, color));
+            }
+        }
+    }
+    colors
+}
+
+fn theme_name() -> Option<String> {
+    std::env::var(THEME_ENVVAR).ok().map(|n| n.trim().to_owned()).filter(|n| !n.is_empty() && n != DEFAULT_THEME)
+}
+
+fn theme_vars() -> Vec<(String, String)> {
+    std::env::vars_os()
+        .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+        .filter(|(k, _)| k.starts_with("NUPROMPT_THEME_"))
+        .collect()
+}
+
+/// What is wrong with the picked theme, each naming the theme and key at fault, for `nuprompt doctor` and the init.
+pub fn problems() -> Vec<String> {
+    theme_name().map(|name| resolve_theme(&name, &theme_vars()).1).unwrap_or_default()
+}
+
+/// Follow the theme and the ones it inherits, with the colors of a theme winning over those it inherits. A key that
+/// is wrong is skipped and reported, and the rest of the theme still applies.
+fn resolve_theme(name: &str, vars: &[(String, String)]) -> (Vec<(Part, Color)>, Vec<String>) {
+    let mut colors: Vec<(Part, Color)> = vec![];
+    let mut problems = vec![];
+    let mut seen: Vec<String> = vec![];
+    let mut current = name.to_owned();
+    while current != DEFAULT_THEME {
+        if seen.contains(&current) {
+            problems.push(format!("theme {:?} inherits itself through {}", current, seen.join(" -> ")));
+            break;
+        }
+        let prefix = format!("NUPROMPT_THEME_{}_", current.to_ascii_uppercase());
+        let mut keys: Vec<(String, &str)> = vars.iter()
+            .filter_map(|(k, v)| Some((k.strip_prefix(&prefix)?.to_ascii_lowercase(), v.trim())))
+            .collect();
+        if keys.is_empty() {
+            problems.push(match seen.last() {
+                Some(child) => format!("theme {:?} has inherit = {:?}, which is not a theme", child, current),
+                None => format!("{} {:?} is not a theme", THEME_ENVVAR, current),
+            });
+            break;
+        }
+        // the environment has no order, so the problems are sorted to come out the same every time
+        keys.sort();
+        let mut inherit = None;
+        for (key, value) in keys {
+            if key == "inherit" {
+                inherit = Some(value.to_owned());
+                continue;
+            }
+            let Some(part) = Part::find(&key) else {
+                problems.push(format!("theme {:?} has unknown key {:?}, expected inherit or a part such as git", current, key));
+                continue;
+            };
+            match Color::from_str(value) {
+                Ok(color) if !colors.iter().any(|(p, _)| *p == part) => colors.push((part, color)),
+                Ok(_) => {},
+                Err(e) => problems.push(format!("theme {:?} has an invalid color for {:?}: {}", current, key, e)),
+            }
+        }
+        seen.push(current);
+        match inherit {
+            Some(parent) => current = parent,
+            None => {
+                let missing: Vec<&str> = Part::ALL.iter().filter(|p| !colors.iter().any(|(c, _)| c == *p)).map(|p| p.name()).collect();
+                if !missing.is_empty() {
+                    problems.push(format!("theme {:?} has no color for {}, set them or add inherit = \"default\"", seen[seen.len() - 1], missing.join(", ")));
+                }
+                break;
+            },
+        }
+    }
+    (colors, problems)
+}
+
+/// Change the color so that it stays readable on the background. Yellow and white are barely visible on a light
+/// background and the intense variants of every color are washed out, so those are swapped for darker colors.
+pub fn adapt(spec: &ColorSpec, bg: Background) -> ColorSpec {
+    let mut out = spec.clone();
+    if bg == Background::Light {
+        out.set_fg(spec.fg().map(|c| match c {
+            Color::Yellow => Color::Ansi256(136),
+            Color::White => Color::Black,
+            c => *c,
+        }));
+        out.set_intense(false);
+    }
+    out
+}
+
+/// Ask the terminal for its background color with OSC 11 and decide whether it is light. Returns None when there is
+/// no terminal or it doesn't answer in time.
+pub fn query() -> Option<Background> {
+    let mut tty = File::options().read(true).write(true).open("/dev/tty").ok()?;
+    let fd = tty.as_raw_fd();
+    // SAFETY: termios is plain data that tcgetattr fills in, and the fd is open for the duration.
+    let original = unsafe {
+        let mut t: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut t) != 0 {
+            return None;
+        }
+        t
+    };
+    // no line buffering or echo, so that the answer can be read as it arrives without showing up on screen
+    let mut raw = original;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+    raw.c_cc[libc::VMIN] = 0;
+    raw.c_cc[libc::VTIME] = 0;
+    // SAFETY: raw is a valid termios for this fd.
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+        return None;
+    }
+    let answer = tty.write_all(b"\x1b]11;?\x07").ok().and_then(|_| read_answer(&mut tty));
+    // SAFETY: original came from tcgetattr on the same fd.
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+    let bg = answer.as_deref().and_then(parse_answer);
+    debug!("terminal background answer {:?} is {:?}", answer.map(|a| a.escape_ascii().to_string()), bg);
+    bg
+}
+
+/// Read until the BEL or ST that ends the answer, or the timeout.
+fn read_answer(tty: &mut File) -> Option<Vec<u8>> {
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let mut answer = vec![];
+    while !answer.ends_with(b"\x07") && !answer.ends_with(b"\x1b\\") {
+        let left = deadline.checked_duration_since(Instant::now())?;
+        let mut pfd = libc::pollfd { fd: tty.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        // SAFETY: pfd is a single valid pollfd.
+        if unsafe { libc::poll(&mut pfd, 1, left.as_millis() as libc::c_int) } <= 0 {
+            return None;
+        }
+        let mut buf = [0u8; 64];
+        match tty.read(&mut buf) {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => answer.extend_from_slice(&buf[..n]),
+        }
+    }
+    Some(answer)
+}
+
+/// Parse an answer like `ESC ] 11 ; rgb:ffff/ffff/dddd BEL`, where each channel has one to four hex digits.
+fn parse_answer(answer: &[u8]) -> Option<Background> {
+    let answer = std::str::from_utf8(answer).ok()?;
+    let rgb = answer.split_once("]11;rgb:")?.1.trim_end_matches(['\x07', '\x1b', '\\']);
+    let mut channels = rgb.split('/').map(|c| {
+        let max = 16f64.powi(c.len() as i32) - 1.0;
+        Some(u32::from_str_radix(c, 16).ok()? as f64 / max).filter(|_| (1..=4).contains(&c.len()))
+    });
+    let (r, g, b) = (channels.next()??, channels.next()??, channels.next()??);
+    // relative luminance, without the gamma curve since only the side of the midpoint matters
+    let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    Some(if luminance > 0.5 { Background::Light } else { Background::Dark })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_answer_examples() {
+        assert_eq!(parse_answer(b"\x1b]11;rgb:ffff/ffff/ffff\x07"), Some(Background::Light));
+        assert_eq!(parse_answer(b"\x1b]11;rgb:1e1e/1e1e/2e2e\x1b\\"), Some(Background::Dark));
+        assert_eq!(parse_answer(b"\x1b]11;rgb:fd/f6/e3\x07"), Some(Background::Light));
+        assert_eq!(parse_answer(b"\x1b]11;rgb:0/0/0\x07"), Some(Background::Dark));
+        assert_eq!(parse_answer(b"\x1b]11;rgb:00/00\x07"), None);
+        assert_eq!(parse_answer(b"\x1b]11;rgb:12345/0/0\x07"), None);
+        assert_eq!(parse_answer(b"garbage"), None);
+    }
+
+    fn resolved(name: &str, vars: &[(&str, &str)]) -> (Vec<(Part, Color)>, Vec<String>) {
+        let vars: Vec<(String, String)> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        resolve_theme(name, &vars)
+    }
+
+    #[test]
+    fn theme_inheritance() {
+        let vars = [
+            ("NUPROMPT_THEME_BASE_INHERIT", "default"),
+            ("NUPROMPT_THEME_BASE_GIT", "blue"),
+            ("NUPROMPT_THEME_BASE_CWD", "green"),
+            ("NUPROMPT_THEME_MINE_INHERIT", "base"),
+            ("NUPROMPT_THEME_MINE_CWD", "208"),
+        ];
+        let (colors, problems) = resolved("mine", &vars);
+        assert_eq!(colors, [(Part::Cwd, Color::Ansi256(208)), (Part::Git, Color::Blue)]);
+        assert_eq!(problems, Vec::<String>::new());
+    }
+
+    #[test]
+    fn theme_problems_name_the_key() {
+        let vars = [
+            ("NUPROMPT_THEME_MINE_INHERIT", "default"),
+            ("NUPROMPT_THEME_MINE_GTI", "blue"),
+            ("NUPROMPT_THEME_MINE_CWD", "mauve"),
+            ("NUPROMPT_THEME_MINE_USER", "red"),
+        ];
+        let (colors, problems) = resolved("mine", &vars);
+        assert_eq!(colors, [(Part::User, Color::Red)]);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].contains("\"cwd\"") && problems[0].contains("mauve"), "{}", problems[0]);
+        assert!(problems[1].contains("unknown key \"gti\""), "{}", problems[1]);
+
+        let (_, problems) = resolved("full", &[("NUPROMPT_THEME_FULL_GIT", "blue")]);
+        assert!(problems[0].starts_with("theme \"full\" has no color for audit, history,"), "{}", problems[0]);
+        let (_, problems) = resolved("a", &[("NUPROMPT_THEME_A_INHERIT", "b"), ("NUPROMPT_THEME_B_INHERIT", "a")]);
+        assert_eq!(problems, ["theme \"a\" inherits itself through a -> b"]);
+        let (_, problems) = resolved("a", &[("NUPROMPT_THEME_A_INHERIT", "missing")]);
+        assert_eq!(problems, ["theme \"a\" has inherit = \"missing\", which is not a theme"]);
+        assert_eq!(resolved("nope", &[]).1, ["NUPROMPT_THEME \"nope\" is not a theme"]);
+    }
+
+    #[test]
+    fn adapt_examples() {
+        let yellow = ColorSpec::new().set_fg(Some(Color::Yellow)).set_intense(true).clone();
+        assert_eq!(adapt(&yellow, Background::Dark), yellow);
+        assert_eq!(adapt(&yellow, Background::Light), ColorSpec::new().set_fg(Some(Color::Ansi256(136))).clone());
+    }
+}
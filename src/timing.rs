@@ -0,0 +1,72 @@
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use log::debug;
+use crate::watchdog;
+
+/// Timings records how long each phase of a prompt took so that slow prompts can be diagnosed.
+pub struct Timings {
+    start: Instant,
+    spans: Vec<(&'static str, Duration)>,
+    /// the wall clock time of the start, so that the traces of separate prompts share a timeline
+    #[cfg(feature = "trace")]
+    started_at: std::time::SystemTime,
+    /// when each phase started, from the start
+    #[cfg(feature = "trace")]
+    offsets: Vec<Duration>,
+}
+
+impl Timings {
+
+    pub fn new() -> Timings {
+        Timings {
+            start: Instant::now(),
+            spans: Vec::with_capacity(16),
+            #[cfg(feature = "trace")]
+            started_at: std::time::SystemTime::now(),
+            #[cfg(feature = "trace")]
+            offsets: Vec::with_capacity(16),
+        }
+    }
+
+    /// Run the function as the named phase and record its duration, and whether the prompt ran out of time in it.
+    pub fn span<T>(&mut self, name: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        watchdog::enter(name);
+        let out = f();
+        watchdog::leave(name, start);
+        let elapsed = start.elapsed();
+        debug!("phase {} took {}us", name, elapsed.as_micros());
+        self.spans.push((name, elapsed));
+        #[cfg(feature = "trace")]
+        self.offsets.push(start - self.start);
+        out
+    }
+
+    /// The phases in the order they finished.
+    pub fn spans(&self) -> &[(&'static str, Duration)] {
+        &self.spans
+    }
+
+    /// The phases as names with their start in microseconds since the unix epoch and their duration in microseconds,
+    /// after a `ps1` phase that covers the whole prompt so far.
+    #[cfg(feature = "trace")]
+    pub fn trace_spans(&self) -> Vec<(&'static str, u128, u128)> {
+        let epoch = self.started_at.duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        let mut out = vec![("ps1", epoch.as_micros(), self.start.elapsed().as_micros())];
+        for ((name, d), offset) in self.spans.iter().zip(&self.offsets) {
+            out.push((name, (epoch + *offset).as_micros(), d.as_micros()));
+        }
+        out
+    }
+
+    /// A single logfmt style line of the phase durations in microseconds, in the order the phases finished.
+    pub fn summary(&self) -> String {
+        let mut out = String::from("nuprompt_timings_us");
+        for (name, d) in &self.spans {
+            let _ = write!(out, " {}={}", name, d.as_micros());
+        }
+        let _ = write!(out, " total={}", self.start.elapsed().as_micros());
+        out
+    }
+
+}
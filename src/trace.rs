@@ -0,0 +1,51 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use log::debug;
+use crate::publish::{field, json_string};
+use crate::timing::Timings;
+
+/// The file to append a trace of every prompt to, in the chrome trace format that Perfetto and chrome://tracing open.
+pub const TRACE_FILE_ENVVAR: &str = "NUPROMPT_TRACE_FILE";
+
+/// Append the phases of the prompt to the trace file, if there is one. A failure only means a missing trace.
+pub fn write(timings: &Timings) {
+    let Some(path) = std::env::var_os(TRACE_FILE_ENVVAR).filter(|p| !p.is_empty()) else {
+        return;
+    };
+    let result = OpenOptions::new().create(true).append(true).open(&path).and_then(|mut file| {
+        // the format lets the array go unclosed, which is what allows every prompt to append to the same file
+        let mut out = if file.metadata()?.len() == 0 { String::from("[\n") } else { String::new() };
+        for (name, ts, dur) in timings.trace_spans() {
+            out.push_str(&event(name, ts, dur, std::process::id()));
+            out.push_str(",\n");
+        }
+        file.write_all(out.as_bytes())
+    });
+    if let Err(e) = result {
+        debug!("could not write trace to {:?}: {}", path, e);
+    }
+}
+
+/// A complete event, which has both a start and a duration. Each prompt is its own process, so the pid keeps them
+/// apart.
+fn event(name: &str, ts: u128, dur: u128, pid: u32) -> String {
+    let mut out = String::from("{");
+    field(&mut out, "name", &json_string(name));
+    field(&mut out, "ph", &json_string("X"));
+    field(&mut out, "ts", &ts.to_string());
+    field(&mut out, "dur", &dur.to_string());
+    field(&mut out, "pid", &pid.to_string());
+    field(&mut out, "tid", &pid.to_string());
+    out.push('}');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_format() {
+        assert_eq!(event("git_status", 1700000000000123, 456, 42), r#"{"name":"git_status","ph":"X","ts":1700000000000123,"dur":456,"pid":42,"tid":42}"#);
+    }
+}
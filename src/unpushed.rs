@@ -0,0 +1,114 @@
+use std::os::unix::ffi::OsStrExt;
+use std::time::Duration;
+use git2::{BranchType, Oid, Repository};
+use log::debug;
+use crate::cache;
+use crate::host::fnv1a;
+
+/// Set to show how many local branches have commits that were never pushed.
+pub const UNPUSHED_ENVVAR: &str = "NUPROMPT_UNPUSHED";
+/// Set to show when the HEAD commit is not on any remote-tracking ref.
+pub const HEAD_ENVVAR: &str = "NUPROMPT_HEAD_PUSHED";
+/// How long a count is trusted before the branches are compared again.
+const TTL_ENVVAR: &str = "NUPROMPT_UNPUSHED_TTL_SECS";
+const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+/// The number of local branches that are ahead of their upstream or have none. Comparing every branch walks their
+/// history, so the count is remembered per repo for a few minutes. Repos without remotes have nothing to push to
+/// and count zero.
+pub fn count(r: &Repository) -> usize {
+    let Some(workdir) = r.workdir() else {
+        return 0;
+    };
+    let ttl = std::env::var(TTL_ENVVAR).ok()
+        .and_then(|v| v.trim().parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_TTL);
+    let key = workdir.as_os_str().as_bytes();
+    if let Some(n) = cache::get("unpushed", key, Some(ttl)).and_then(|c| String::from_utf8(c).ok()?.trim().parse().ok()) {
+        debug!("using remembered unpushed branch count {}", n);
+        return n;
+    }
+    let n = compute(r).unwrap_or_else(|e| {
+        debug!("could not count unpushed branches: {}", e);
+        0
+    });
+    if let Err(e) = cache::put("unpushed", key, n.to_string().as_bytes()) {
+        debug!("failed to remember unpushed branch count: {}", e);
+    }
+    n
+}
+
+fn compute(r: &Repository) -> Result<usize, git2::Error> {
+    if r.remotes()?.is_empty() {
+        return Ok(0);
+    }
+    let mut n = 0;
+    for branch in r.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        let Some(local) = branch.get().target() else {
+            continue;
+        };
+        let unpushed = match branch.upstream().ok().and_then(|u| u.get().target()) {
+            Some(upstream) => r.graph_ahead_behind(local, upstream)?.0 > 0,
+            None => true,
+        };
+        if unpushed {
+            debug!("branch {:?} has unpushed commits", branch.name_bytes().map(String::from_utf8_lossy));
+            n += 1;
+        }
+    }
+    Ok(n)
+}
+
+/// Whether the HEAD commit is missing from every remote-tracking ref, which works without an upstream. The answer only
+/// changes when HEAD or a remote ref moves, so it is remembered along with a hash of them and only walked again after
+/// a commit, checkout, fetch, or push. Repos without remote refs have nowhere to push to and answer false.
+pub fn head_unpushed(r: &Repository) -> bool {
+    let (Some(workdir), Ok(head)) = (r.workdir(), r.head()) else {
+        return false;
+    };
+    let Some(head) = head.target() else {
+        return false;
+    };
+    let remotes: Vec<Oid> = match r.references_glob("refs/remotes/*") {
+        Ok(refs) => refs.flatten().filter_map(|r| r.target()).collect(),
+        Err(e) => {
+            debug!("could not list remote refs: {}", e);
+            return false;
+        }
+    };
+    if remotes.is_empty() {
+        return false;
+    }
+    let mut key = head.as_bytes().to_vec();
+    remotes.iter().for_each(|oid| key.extend_from_slice(oid.as_bytes()));
+    let fingerprint = format!("{:016x}", fnv1a(&key));
+    let cached = cache::get("headpushed", workdir.as_os_str().as_bytes(), None)
+        .and_then(|c| String::from_utf8(c).ok()?.strip_prefix(&fingerprint)?.trim().parse::<u8>().ok());
+    if let Some(answer) = cached {
+        debug!("using remembered head unpushed {}", answer);
+        return answer == 1;
+    }
+    let unpushed = walk_unpushed(r, head, &remotes).unwrap_or_else(|e| {
+        debug!("could not check whether head is pushed: {}", e);
+        false
+    });
+    if let Err(e) = cache::put("headpushed", workdir.as_os_str().as_bytes(), format!("{} {}", fingerprint, unpushed as u8).as_bytes()) {
+        debug!("failed to remember whether head is pushed: {}", e);
+    }
+    unpushed
+}
+
+/// Walk from HEAD while hiding everything reachable from the remote refs. Anything left is a commit no remote has.
+fn walk_unpushed(r: &Repository, head: Oid, remotes: &[Oid]) -> Result<bool, git2::Error> {
+    let mut walk = r.revwalk()?;
+    walk.push(head)?;
+    for oid in remotes {
+        // a remote ref may point at a tag or an object we don't have, which can't hide anything
+        if let Err(e) = walk.hide(*oid) {
+            debug!("could not hide {}: {}", oid, e);
+        }
+    }
+    Ok(walk.next().transpose()?.is_some())
+}
@@ -0,0 +1,242 @@
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+use anyhow::{anyhow, Context};
+use log::debug;
+use crate::host::fnv1a;
+use crate::process::output_with_timeout;
+
+/// Releases are the version tags of the source repo, with a binary for each target attached to them.
+const REPOSITORY: &str = env!("CARGO_PKG_REPOSITORY");
+
+/// The minisign public key that signs the `SHA256SUMS` of each release, set when the binary is built. A build without
+/// one refuses to install updates, since there would be nothing to check them against.
+const SIGNING_KEY: Option<&str> = option_env!("NUPROMPT_UPDATE_KEY");
+
+/// Each release holds `nuprompt-<target>` for every target it is built for, the sums of those, and the signature of the
+/// sums.
+const SUMS_ASSET: &str = "SHA256SUMS";
+const SIGNATURE_ASSET: &str = "SHA256SUMS.minisig";
+
+const LS_REMOTE_TIMEOUT: Duration = Duration::from_secs(15);
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(15);
+
+type Version = (u64, u64, u64);
+
+fn parse_version(raw: &str) -> Option<Version> {
+    let mut parts = raw.strip_prefix('v').unwrap_or(raw).splitn(3, '.').map(|p| p.parse().ok());
+    Some((parts.next()??, parts.next()??, parts.next()??))
+}
+
+/// Find the newest release tag and the commit it points at.
+fn latest_release() -> Result<Option<(Version, String, String)>, anyhow::Error> {
+    let output = output_with_timeout(Command::new("git").args(["ls-remote", "--tags", "--refs", REPOSITORY]), LS_REMOTE_TIMEOUT)
+        .context("git ls-remote")?;
+    if !output.status.success() {
+        return Err(anyhow!("git ls-remote {} failed with {}", REPOSITORY, output.status));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines()
+        .filter_map(|line| {
+            let (rev, reference) = line.split_once('\t')?;
+            let tag = reference.strip_prefix("refs/tags/")?;
+            Some((parse_version(tag)?, tag.to_owned(), rev.to_owned()))
+        })
+        .max_by_key(|(v, _, _)| *v))
+}
+
+/// Check for a newer release and, unless only checking, download its binary for this target and replace the running
+/// executable.
+///
+/// Nothing is installed unless it is verified: the sums of the release must carry a valid minisign signature by the
+/// key the running binary was built with, and the downloaded binary must match its sum. The new binary is renamed over
+/// the current one so that a shell starting a prompt at the same moment sees either the old or the new binary, never
+/// half of one.
+pub fn run(check_only: bool) -> Result<(), anyhow::Error> {
+    let current = parse_version(crate::VERSION).ok_or_else(|| anyhow!("cannot parse own version {}", crate::VERSION))?;
+    let Some((latest, tag, rev)) = latest_release()? else {
+        println!("no releases found in {}", REPOSITORY);
+        return Ok(());
+    };
+    if latest <= current {
+        println!("nuprompt {} is up to date", crate::VERSION);
+        return Ok(());
+    }
+    println!("nuprompt {} is available at {} ({}), this is {}", tag, REPOSITORY, rev, crate::VERSION);
+    if check_only {
+        return Ok(());
+    }
+    let key = SIGNING_KEY.filter(|k| !k.trim().is_empty())
+        .ok_or_else(|| anyhow!("this build has no update signing key, so updates can't be verified and are not installed"))?;
+
+    let exe = std::env::current_exe()?.canonicalize()?;
+    let dir = exe.parent().ok_or_else(|| anyhow!("executable {:?} has no parent directory", exe))?;
+    let root = download_root()?;
+    let result = download_verified(&root, &tag, key).and_then(|binary| replace(&binary, dir, &exe));
+    let _ = fs::remove_dir_all(&root);
+    result?;
+    println!("updated {:?} to {}", exe, tag);
+    Ok(())
+}
+
+/// A new directory only we can get into, with a name that can't be guessed, so that no one else can swap the download
+/// between its check and its install in a shared temp dir.
+fn download_root() -> Result<PathBuf, anyhow::Error> {
+    let mut attempt = 0;
+    loop {
+        let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let seed = format!("{}:{}:{}", std::process::id(), nanos, attempt);
+        let root = std::env::temp_dir().join(format!("nuprompt-update-{:016x}", fnv1a(seed.as_bytes())));
+        match fs::DirBuilder::new().mode(0o700).create(&root) {
+            Ok(()) => return Ok(root),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempt < 8 => attempt += 1,
+            Err(e) => return Err(e).with_context(|| format!("create download dir {:?}", root)),
+        }
+    }
+}
+
+fn asset_name() -> String {
+    format!("nuprompt-{}", env!("NUPROMPT_BUILD_TARGET"))
+}
+
+/// Download the binary of the release for this target, the sums, and their signature, and return the path of the
+/// binary once the signature and its sum check out.
+fn download_verified(root: &Path, tag: &str, key: &str) -> Result<PathBuf, anyhow::Error> {
+    let asset = asset_name();
+    let sums = download(root, tag, SUMS_ASSET)?;
+    let signature = download(root, tag, SIGNATURE_ASSET)?;
+    let output = output_with_timeout(Command::new("minisign")
+        .args(["-V", "-q", "-P", key, "-m"]).arg(&sums).arg("-x").arg(&signature), VERIFY_TIMEOUT)
+        .context("minisign, which is needed to verify updates")?;
+    if !output.status.success() {
+        return Err(anyhow!("the signature of the {} of {} is not valid, not installing it", SUMS_ASSET, tag));
+    }
+    let expected = expected_sum(&fs::read_to_string(&sums)?, &asset)
+        .ok_or_else(|| anyhow!("{} of {} has no sum for {}, which may not be built for this target", SUMS_ASSET, tag, asset))?;
+    let binary = download(root, tag, &asset)?;
+    let actual = hex(&sha256(&fs::read(&binary)?));
+    if actual != expected {
+        return Err(anyhow!("{} of {} has sha256 {} instead of {}, not installing it", asset, tag, actual, expected));
+    }
+    debug!("verified {} of {} with sha256 {}", asset, tag, actual);
+    Ok(binary)
+}
+
+fn download(root: &Path, tag: &str, name: &str) -> Result<PathBuf, anyhow::Error> {
+    let url = format!("{}/releases/download/{}/{}", REPOSITORY, tag, name);
+    debug!("downloading {}", url);
+    let output = output_with_timeout(Command::new("curl").args(["-fsSL", "--proto", "=https", "--tlsv1.2"]).arg(&url), DOWNLOAD_TIMEOUT)
+        .context("curl")?;
+    if !output.status.success() {
+        return Err(anyhow!("downloading {} failed with {}", url, output.status));
+    }
+    let path = root.join(name);
+    fs::File::options().write(true).create_new(true).mode(0o700).open(&path)
+        .and_then(|mut f| f.write_all(&output.stdout))
+        .with_context(|| format!("write {:?}", path))?;
+    Ok(path)
+}
+
+/// The sum for the file in the output of `sha256sum`, where a `*` before the name marks binary mode.
+fn expected_sum(sums: &str, name: &str) -> Option<String> {
+    sums.lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(_, file)| file.trim_start().trim_start_matches('*') == name)
+        .map(|(sum, _)| sum.to_ascii_lowercase())
+        .filter(|sum| sum.len() == 64 && sum.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 as in FIPS 180-4, which is all the checksum needs and not worth a dependency.
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut h: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for block in padded.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh.wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            (hh, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+        }
+        for (x, y) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *x = x.wrapping_add(y);
+        }
+    }
+    let mut out = [0u8; 32];
+    for (chunk, word) in out.chunks_exact_mut(4).zip(h) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+fn replace(binary: &Path, dir: &Path, exe: &Path) -> Result<(), anyhow::Error> {
+    let staged = dir.join(format!(".nuprompt-update-{}", std::process::id()));
+    fs::copy(binary, &staged).with_context(|| format!("copy new binary to {:?}", staged))?;
+    if let Err(e) = fs::rename(&staged, exe) {
+        let _ = fs::remove_file(&staged);
+        return Err(e).with_context(|| format!("replace {:?}", exe));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_vectors() {
+        assert_eq!(hex(&sha256(b"")), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex(&sha256(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            hex(&sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        );
+        assert_eq!(hex(&sha256(&[b'a'; 1000])), "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3");
+    }
+
+    #[test]
+    fn expected_sum_examples() {
+        let a = "a".repeat(64);
+        let b = "B".repeat(64);
+        let sums = format!("{}  nuprompt-x86_64-unknown-linux-musl\n{} *nuprompt-aarch64-apple-darwin\nbogus  nuprompt-short\n", a, b);
+        assert_eq!(expected_sum(&sums, "nuprompt-x86_64-unknown-linux-musl"), Some(a));
+        assert_eq!(expected_sum(&sums, "nuprompt-aarch64-apple-darwin"), Some("b".repeat(64)));
+        assert_eq!(expected_sum(&sums, "nuprompt-short"), None);
+        assert_eq!(expected_sum(&sums, "nuprompt-riscv64gc-unknown-linux-gnu"), None);
+    }
+}
@@ -0,0 +1,51 @@
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use crate::config::env_map;
+
+/// A comma separated list of `username=alias` pairs, e.g. `benjamin.meier=bm`, for the names to show differently.
+const ALIASES_ENVVAR: &str = "NUPROMPT_USER_ALIASES";
+/// Set to shorten the usernames without an alias that are made of several words to their initials.
+const INITIALS_ENVVAR: &str = "NUPROMPT_USER_INITIALS";
+
+/// The username as it should be shown. An alias wins over the initials.
+pub fn display(username: &OsStr) -> OsString {
+    let aliases = env_map(ALIASES_ENVVAR);
+    if let Some((_, alias)) = aliases.iter().find(|(name, _)| name.as_bytes() == username.as_bytes()) {
+        return OsString::from(alias);
+    }
+    if std::env::var_os(INITIALS_ENVVAR).is_some() {
+        return OsString::from_vec(initials(username.as_bytes()));
+    }
+    username.to_owned()
+}
+
+/// The first letter of each word, where words are split by the separators common in corporate usernames. A name of a
+/// single word is left alone, since one letter says too little.
+fn initials(username: &[u8]) -> Vec<u8> {
+    let words: Vec<&[u8]> = username.split(|b| matches!(b, b'.' | b'_' | b'-')).filter(|w| !w.is_empty()).collect();
+    if words.len() < 2 {
+        return username.to_vec();
+    }
+    let mut out = vec![];
+    for word in words {
+        // a whole character, so that a name starting with a multi-byte letter keeps it
+        let len = std::str::from_utf8(word).ok().and_then(|w| w.chars().next()).map_or(1, char::len_utf8);
+        out.extend_from_slice(&word[..len]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initials_examples() {
+        assert_eq!(initials(b"benjamin.meier"), b"bm");
+        assert_eq!(initials(b"j_van-der.berg"), b"jvdb");
+        assert_eq!(initials("\u{e9}mile.zola".as_bytes()), "\u{e9}z".as_bytes());
+        assert_eq!(initials(b"ben"), b"ben");
+        assert_eq!(initials(b".hidden."), b".hidden.");
+        assert_eq!(initials(b"1000:1000"), b"1000:1000");
+    }
+}
@@ -0,0 +1,36 @@
+use crate::publish::{field, json_array, json_string};
+
+/// The commit the binary was built from, which is empty when it was built outside of a checkout.
+const COMMIT: &str = env!("NUPROMPT_BUILD_COMMIT");
+const DATE: &str = env!("NUPROMPT_BUILD_DATE");
+const TARGET: &str = env!("NUPROMPT_BUILD_TARGET");
+/// The comma separated cargo features the binary was built with.
+const FEATURES: &str = env!("NUPROMPT_BUILD_FEATURES");
+
+fn features() -> impl Iterator<Item = &'static str> {
+    FEATURES.split(',').filter(|f| !f.is_empty())
+}
+
+/// Print the version and where the binary was built from, either as a line for people or as a JSON object for bug
+/// reports and scripts.
+pub fn print(json: bool) {
+    if json {
+        let mut out = String::from("{");
+        field(&mut out, "version", &json_string(crate::VERSION));
+        field(&mut out, "commit", &Some(COMMIT).filter(|c| !c.is_empty()).map_or("null".to_owned(), json_string));
+        field(&mut out, "build_date", &json_string(DATE));
+        field(&mut out, "features", &json_array(features()));
+        field(&mut out, "target", &json_string(TARGET));
+        out.push('}');
+        println!("{}", out);
+        return;
+    }
+    match COMMIT.get(..12) {
+        Some(short) => println!("nuprompt {} ({} {}) {}", crate::VERSION, short, DATE, TARGET),
+        None => println!("nuprompt {} ({}) {}", crate::VERSION, DATE, TARGET),
+    }
+    let features: Vec<&str> = features().collect();
+    if !features.is_empty() {
+        println!("features: {}", features.join(", "));
+    }
+}
@@ -0,0 +1,117 @@
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+use anyhow::anyhow;
+use git2::{Repository, StatusOptions};
+use log::debug;
+use crate::git::GitBits;
+use crate::{cache, fstype, git, strategy};
+
+/// The snippet that adds `nuprompt warm` to the hooks of the repo in the current directory. It appends to existing
+/// hooks rather than replacing them, and runs in the background so that git itself isn't slowed down.
+pub const HOOKS_SNIPPET: &str = r#"hooks="$(git rev-parse --git-path hooks)" && mkdir -p "$hooks" && for h in post-checkout post-merge post-index-change; do
+    [ -s "$hooks/$h" ] || echo '#!/bin/sh' > "$hooks/$h"
+    grep -q 'nuprompt warm' "$hooks/$h" || echo '(nuprompt warm >/dev/null 2>&1 &)' >> "$hooks/$h"
+    chmod +x "$hooks/$h"
+done"#;
+
+/// The status a warm computed is left for the next prompt in the repo, keyed by the workdir. It holds only while the
+/// index and HEAD are as the warm left them, and since edits to the worktree show in neither, only briefly and once.
+const CACHE_NAMESPACE: &str = "warm";
+const STATUS_TTL: Duration = Duration::from_secs(30);
+
+/// Scan the repo containing the path so that the next prompt is fast. The scan refreshes the stat information in the
+/// index, which is what makes the first status after a large checkout or merge slow, updates the remembered status
+/// strategy, and leaves the status for the next prompt to take. When a watcher is running for the repo, its cache is
+/// refreshed too.
+pub fn run(path: &Path) -> Result<(), anyhow::Error> {
+    let r = git::open(path)?;
+    let workdir = r.workdir().ok_or_else(|| anyhow!("repository has no working directory"))?.to_path_buf();
+    let used = strategy::choose(&r);
+    let include_untracked = !fstype::detect(&workdir).is_some_and(|k| k.is_network());
+    // the index of a repo that belongs to someone else is theirs to refresh
+    let foreign = git::foreign(&r);
+    if foreign {
+        debug!("not refreshing the index of {:?}, which belongs to another user", workdir);
+    }
+    if used != strategy::Strategy::HeadOnly && !foreign {
+        r.statuses(Some(StatusOptions::new()
+            .include_untracked(include_untracked && used == strategy::Strategy::Full)
+            .exclude_submodules(true)
+            .update_index(true)))?;
+    }
+    // the index is fresh now, so this is the scan the prompt would run
+    let start = Instant::now();
+    let gb = GitBits::from_repo(&r, include_untracked, used)?;
+    let took = start.elapsed();
+    debug!("warmed {:?} with {:?} in {:?}", workdir, used, took);
+    strategy::observe(&r, used, took);
+    if let Some(mut contents) = fingerprint(&r, include_untracked) {
+        contents.extend_from_slice(&gb.encode());
+        if let Err(e) = cache::put(CACHE_NAMESPACE, workdir.as_os_str().as_bytes(), &contents) {
+            debug!("failed to store the warmed status: {}", e);
+        }
+    }
+    #[cfg(target_os = "linux")]
+    crate::watch::refresh_if_watched(&r, &workdir)?;
+    Ok(())
+}
+
+/// The status the last warm left for the repo, if the repo still looks the way it did then. It is removed as it is
+/// taken.
+pub fn take(r: &Repository, workdir: &Path, include_untracked: bool) -> Option<GitBits> {
+    let key = workdir.as_os_str().as_bytes();
+    let contents = cache::get(CACHE_NAMESPACE, key, Some(STATUS_TTL))?;
+    cache::remove(CACHE_NAMESPACE, key);
+    let gb = contents.strip_prefix(fingerprint(r, include_untracked)?.as_slice()).and_then(GitBits::decode);
+    if gb.is_none() {
+        debug!("ignoring the warmed status of {:?}, which the repo has moved on from", workdir);
+    }
+    gb
+}
+
+/// What must not have changed for a warmed status to hold: the index, where HEAD points, and whether untracked files
+/// were counted.
+fn fingerprint(r: &Repository, include_untracked: bool) -> Option<Vec<u8>> {
+    let index = fs::metadata(r.path().join("index")).ok()?;
+    let mtime = index.modified().ok()?.duration_since(SystemTime::UNIX_EPOCH).ok()?.as_nanos();
+    let head = r.head().ok();
+    let name = head.as_ref().and_then(|h| h.name()).unwrap_or_default();
+    let target = head.as_ref().and_then(|h| h.target()).map(|o| o.to_string()).unwrap_or_default();
+    Some(format!("{}\n{}\n{}\n{}\n{}\n", mtime, index.len(), name, target, include_untracked).into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warmed_status_is_taken_once() {
+        let dir = std::env::temp_dir().join(format!("nuprompt-warm-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let r = Repository::init(&dir).unwrap();
+        fs::write(dir.join("tracked"), "a").unwrap();
+        let mut index = r.index().unwrap();
+        index.add_path(Path::new("tracked")).unwrap();
+        index.write().unwrap();
+        let tree = r.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        r.commit(Some("HEAD"), &sig, &sig, "initial", &tree, &[]).unwrap();
+        fs::write(dir.join("tracked"), "changed").unwrap();
+        fs::write(dir.join("untracked"), "b").unwrap();
+        let workdir = r.workdir().unwrap();
+
+        run(&dir).unwrap();
+        let gb = take(&r, workdir, true).expect("the warmed status");
+        assert_eq!((gb.worktree_modified, gb.untracked_files, gb.partial), (1, 1, false));
+        assert!(take(&r, workdir, true).is_none());
+
+        // staging a file after the warm leaves its status behind
+        run(&dir).unwrap();
+        index.add_path(Path::new("untracked")).unwrap();
+        index.write().unwrap();
+        assert!(take(&r, workdir, true).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
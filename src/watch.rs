@@ -0,0 +1,347 @@
+use std::ffi::{CString, OsStr};
+use std::fs;
+use std::fs::File;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Context};
+use git2::Repository;
+use log::debug;
+use crate::cache;
+use crate::fstype;
+use crate::git;
+use crate::git::GitBits;
+use crate::host::fnv1a;
+use crate::state;
+use crate::gitcli;
+use crate::strategy;
+
+pub const WATCH_ENVVAR: &str = "NUPROMPT_WATCH";
+const WATCH_IDLE_ENVVAR: &str = "NUPROMPT_WATCH_IDLE_SECS";
+
+/// How long a watcher stays alive without seeing any file system events in its repo.
+const DEFAULT_IDLE: Duration = Duration::from_secs(3600);
+
+/// How long to wait for more events before rescanning. Git operations tend to produce bursts of events.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// The files of the watcher for a repo live in the state dir, by uid, since the watcher of another user who works in the
+/// same checkout must not lock ours out.
+fn file_prefix(workdir: &Path) -> PathBuf {
+    let hash = fnv1a(workdir.as_os_str().as_bytes());
+    state::state_dir().join(format!("NUPROMPT_{}_watch_{:016x}", users::get_current_uid(), hash))
+}
+
+const CACHE_NAMESPACE: &str = "watch";
+
+fn lock_path(workdir: &Path) -> PathBuf {
+    file_prefix(workdir).with_extension("lock")
+}
+
+/// Try to take the exclusive lock that marks a live watcher for the given repo. Returns None if another process has it.
+fn try_lock(workdir: &Path) -> Result<Option<File>, anyhow::Error> {
+    // a lock left in place as a symlink by someone else is not followed
+    let f = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(lock_path(workdir))?;
+    // SAFETY: the fd is valid for the lifetime of the file.
+    if unsafe { libc::flock(f.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let err = std::io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::EWOULDBLOCK) => Ok(None),
+            _ => Err(err.into()),
+        };
+    }
+    Ok(Some(f))
+}
+
+/// Return the precomputed status for the repo if a watcher is alive and has a current answer. Otherwise start a watcher
+/// in the background so that the next prompt can use it.
+pub fn cached_or_spawn(workdir: &Path) -> Option<GitBits> {
+    match try_lock(workdir) {
+        Ok(None) => match cache::get(CACHE_NAMESPACE, workdir.as_os_str().as_bytes(), None) {
+            Some(contents) => {
+                let gb = GitBits::decode(&contents);
+                if gb.is_none() {
+                    debug!("ignoring invalid watch cache for {:?}", workdir);
+                }
+                gb
+            },
+            None => {
+                debug!("watcher is alive but has no cache for {:?}", workdir);
+                None
+            }
+        },
+        // we got the lock so nobody is watching yet, drop it and start a watcher
+        Ok(Some(lock)) => {
+            drop(lock);
+            if let Err(e) = spawn(workdir) {
+                debug!("failed to spawn watcher for {:?}: {}", workdir, e);
+            }
+            None
+        },
+        Err(e) => {
+            debug!("failed to check watcher lock for {:?}: {}", workdir, e);
+            None
+        }
+    }
+}
+
+/// Rewrite the cache of a live watcher straight away, without waiting for it to notice the change.
+pub fn refresh_if_watched(r: &Repository, workdir: &Path) -> Result<(), anyhow::Error> {
+    if try_lock(workdir)?.is_none() {
+        let include_untracked = !fstype::detect(workdir).is_some_and(|k| k.is_network());
+        write_cache(workdir, &scan(r, include_untracked)?)?;
+    }
+    Ok(())
+}
+
+/// Scan the status the way a prompt would, with the strategy and backend chosen for the repo, since a repo big enough
+/// to have a watcher is the kind that can't afford a full scan on every change.
+fn scan(r: &Repository, include_untracked: bool) -> Result<GitBits, anyhow::Error> {
+    let used = strategy::choose(r);
+    let start = Instant::now();
+    let gb = gitcli::status(r, include_untracked, used)?;
+    strategy::observe(r, used, start.elapsed());
+    Ok(gb)
+}
+
+fn spawn(workdir: &Path) -> Result<(), anyhow::Error> {
+    let mut cmd = Command::new(std::env::current_exe()?);
+    cmd.arg("watch").arg(workdir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // SAFETY: setsid is async-signal-safe. We detach so the watcher is not tied to the terminal session.
+    unsafe {
+        cmd.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    let child = cmd.spawn()?;
+    debug!("spawned watcher pid {} for {:?}", child.id(), workdir);
+    Ok(())
+}
+
+fn write_cache(workdir: &Path, gb: &GitBits) -> Result<(), anyhow::Error> {
+    cache::put(CACHE_NAMESPACE, workdir.as_os_str().as_bytes(), &gb.encode())
+}
+
+/// Inotify owns the inotify instance used to watch a repo.
+struct Inotify {
+    fd: File,
+}
+
+const WATCH_MASK: u32 = libc::IN_CREATE | libc::IN_DELETE | libc::IN_MODIFY | libc::IN_ATTRIB
+    | libc::IN_MOVED_FROM | libc::IN_MOVED_TO | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF;
+
+impl Inotify {
+
+    fn new() -> Result<Inotify, anyhow::Error> {
+        // SAFETY: inotify_init1 has no preconditions and we take ownership of the returned fd.
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("inotify_init1");
+        }
+        // SAFETY: the fd was just created and is owned by nothing else.
+        Ok(Inotify { fd: unsafe { File::from_raw_fd(fd) } })
+    }
+
+    fn add(&self, dir: &Path) -> Result<(), anyhow::Error> {
+        let c = CString::new(dir.as_os_str().as_bytes())?;
+        // SAFETY: the path is a valid nul-terminated string.
+        if unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), c.as_ptr(), WATCH_MASK) } < 0 {
+            return Err(std::io::Error::last_os_error()).with_context(|| format!("inotify_add_watch {:?}", dir));
+        }
+        Ok(())
+    }
+
+    /// Watch the directory and every non-ignored directory below it.
+    fn add_tree(&self, r: &Repository, dir: &Path) {
+        if let Err(e) = self.add(dir) {
+            debug!("{}", e);
+            return;
+        }
+        let entries = match fs::read_dir(dir) {
+            Ok(e) => e,
+            Err(e) => {
+                debug!("failed to list {:?}: {}", dir, e);
+                return;
+            }
+        };
+        for entry in entries.flatten() {
+            let p = entry.path();
+            if !entry.file_type().is_ok_and(|t| t.is_dir()) || p.file_name() == Some(OsStr::new(".git")) {
+                continue;
+            }
+            if r.is_path_ignored(&p).unwrap_or(false) {
+                continue;
+            }
+            self.add_tree(r, &p);
+        }
+    }
+
+    /// Wait up to the timeout for events. Returns whether any arrived and whether one of them created a directory that
+    /// now needs watching too.
+    fn wait(&self, timeout: Duration) -> Result<(bool, bool), anyhow::Error> {
+        let mut pfd = libc::pollfd { fd: self.fd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        // SAFETY: pfd is a single valid pollfd.
+        let n = unsafe { libc::poll(&mut pfd, 1, ms) };
+        if n < 0 {
+            return Err(std::io::Error::last_os_error()).context("poll");
+        } else if n == 0 {
+            return Ok((false, false));
+        }
+        let mut buf = [0u8; 4096];
+        // SAFETY: the buffer is valid for its length.
+        let len = unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if len < 0 {
+            return Err(std::io::Error::last_os_error()).context("read inotify");
+        }
+        Ok((true, created_dir(&buf[..len as usize])))
+    }
+
+}
+
+/// Whether one of the events read from inotify created a directory or moved one in.
+fn created_dir(events: &[u8]) -> bool {
+    let header = size_of::<libc::inotify_event>();
+    let (mut offset, mut new_dir) = (0, false);
+    while offset + header <= events.len() {
+        // SAFETY: the kernel writes whole events and we checked the header fits. read_unaligned handles alignment.
+        let ev: libc::inotify_event = unsafe { std::ptr::read_unaligned(events[offset..].as_ptr() as *const _) };
+        if ev.mask & libc::IN_ISDIR != 0 && ev.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+            new_dir = true;
+        }
+        offset += header + ev.len as usize;
+    }
+    new_dir
+
+}
+
+/// Run the watcher for the repo containing the given path until it has been idle for too long.
+pub fn run(path: &OsStr) -> Result<(), anyhow::Error> {
+    let r = git::open(path)?;
+    let workdir = r.workdir().ok_or_else(|| anyhow!("repository has no working directory"))?.to_path_buf();
+    let _lock = match try_lock(&workdir)? {
+        Some(l) => l,
+        None => {
+            debug!("another watcher is already running for {:?}", workdir);
+            return Ok(());
+        }
+    };
+    let idle = std::env::var(WATCH_IDLE_ENVVAR).ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_IDLE);
+
+    let build = |r: &Repository| -> Result<Inotify, anyhow::Error> {
+        let ino = Inotify::new()?;
+        ino.add_tree(r, &workdir);
+        // the git dir itself holds HEAD and the index, while refs change on commit and reset.
+        ino.add(r.path())?;
+        ino.add_tree(r, &r.path().join("refs"));
+        Ok(ino)
+    };
+    let include_untracked = !fstype::detect(&workdir).is_some_and(|k| k.is_network());
+    let mut ino = build(&r)?;
+    let mut last_event = Instant::now();
+    loop {
+        write_cache(&workdir, &scan(&r, include_untracked)?)?;
+        debug!("wrote watch cache for {:?}", workdir);
+        let (mut any, mut new_dir) = ino.wait(idle.saturating_sub(last_event.elapsed()))?;
+        if !any {
+            debug!("watcher idle for {:?}, exiting", idle);
+            cache::remove(CACHE_NAMESPACE, workdir.as_os_str().as_bytes());
+            return Ok(());
+        }
+        // invalidate the cache straight away so prompts don't see a stale answer while we rescan
+        cache::remove(CACHE_NAMESPACE, workdir.as_os_str().as_bytes());
+        while any {
+            let (a, n) = ino.wait(DEBOUNCE)?;
+            any = a;
+            new_dir |= n;
+        }
+        if !workdir.exists() {
+            return Ok(());
+        }
+        if new_dir {
+            ino = build(&r)?;
+        }
+        last_event = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nuprompt-watch-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn wait_reports_new_dirs() {
+        let dir = test_dir("inotify");
+        let ino = Inotify::new().unwrap();
+        ino.add(&dir).unwrap();
+        assert_eq!(ino.wait(Duration::ZERO).unwrap(), (false, false));
+        fs::write(dir.join("file"), "a").unwrap();
+        assert_eq!(ino.wait(Duration::from_secs(1)).unwrap(), (true, false));
+        fs::create_dir(dir.join("sub")).unwrap();
+        assert_eq!(ino.wait(Duration::from_secs(1)).unwrap(), (true, true));
+        fs::rename(dir.join("sub"), dir.join("moved")).unwrap();
+        assert_eq!(ino.wait(Duration::from_secs(1)).unwrap(), (true, true));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn created_dir_walks_every_event() {
+        let event = |mask: u32, name: &[u8]| {
+            let ev = libc::inotify_event { wd: 1, mask, cookie: 0, len: name.len() as u32 };
+            // SAFETY: inotify_event is plain data without padding.
+            let header = unsafe {
+                std::slice::from_raw_parts((&ev as *const libc::inotify_event).cast::<u8>(), size_of::<libc::inotify_event>())
+            };
+            [header, name].concat()
+        };
+        let file = event(libc::IN_CREATE, b"file\0\0\0\0");
+        let dir = event(libc::IN_CREATE | libc::IN_ISDIR, b"dir\0");
+        let gone = event(libc::IN_DELETE | libc::IN_ISDIR, b"old\0");
+        assert!(!created_dir(&file));
+        assert!(created_dir(&[file.clone(), dir.clone()].concat()));
+        assert!(!created_dir(&[gone, file.clone()].concat()));
+        // a truncated trailing event is not read past the end
+        assert!(!created_dir(&[file, dir[..8].to_vec()].concat()));
+    }
+
+    /// While a watcher holds the lock, a prompt takes its answer from the cache and never spawns another one.
+    #[test]
+    fn cached_status_while_locked() {
+        let workdir = test_dir("handoff");
+        let lock = try_lock(&workdir).unwrap().expect("the lock is free");
+        assert!(try_lock(&workdir).unwrap().is_none());
+        assert!(cached_or_spawn(&workdir).is_none());
+        let gb = GitBits { head_ref: b"topic".to_vec(), worktree_modified: 2, partial: true, ..GitBits::default() };
+        write_cache(&workdir, &gb).unwrap();
+        let cached = cached_or_spawn(&workdir).expect("the cached status");
+        assert_eq!((cached.head_ref, cached.worktree_modified, cached.partial), (b"topic".to_vec(), 2, true));
+        drop(lock);
+        assert!(try_lock(&workdir).unwrap().is_some());
+        cache::remove(CACHE_NAMESPACE, workdir.as_os_str().as_bytes());
+        fs::remove_dir_all(&workdir).unwrap();
+    }
+}
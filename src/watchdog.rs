@@ -0,0 +1,214 @@
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use log::debug;
+use crate::cache;
+
+/// The most time in milliseconds a prompt may take, or 0 to wait for as long as it takes. Once it is up, the segments
+/// that haven't run yet are skipped and the git status threads stop between directories, so the prompt comes out with
+/// whatever was gathered by then.
+pub const LIMIT_ENVVAR: &str = "NUPROMPT_PROMPT_LIMIT_MS";
+const DEFAULT_LIMIT: Duration = Duration::from_millis(500);
+
+/// A step that can't be interrupted, such as a single libgit2 status call, gets this much longer to finish. After that
+/// the prompt as it was before any segment ran is printed and the process exits.
+const KILL_GRACE: Duration = Duration::from_millis(100);
+
+/// The phase each directory last ran out of time in is kept in the cache, for `nuprompt doctor`.
+const CACHE_NAMESPACE: &str = "watchdog";
+
+static DEADLINE: OnceLock<Instant> = OnceLock::new();
+static WATCH: Mutex<Watch> = Mutex::new(Watch::new());
+
+/// A hook is told the phase the process is killed in and how long that phase ran.
+type Hook = Box<dyn FnOnce(&'static str, Duration) + Send>;
+
+struct Watch {
+    /// the phases that have started and not yet finished with when they started, innermost last
+    running: Vec<(&'static str, Instant)>,
+    /// the phase that was running when the time ran out
+    culprit: Option<&'static str>,
+    /// printed in place of the prompt when the process is killed, taken once the real prompt is on its way
+    fallback: Option<Vec<u8>>,
+    /// what must still be saved when the process is killed, by the id of its guard
+    hooks: Vec<(u64, Hook)>,
+    next_hook: u64,
+}
+
+impl Watch {
+
+    const fn new() -> Watch {
+        Watch { running: vec![], culprit: None, fallback: None, hooks: vec![], next_hook: 0 }
+    }
+
+    /// Take the fallback prompt and run the hooks, or None once the real prompt is on its way.
+    fn kill(&mut self, cwd: Option<&Path>, now: Instant) -> Option<Vec<u8>> {
+        let fallback = self.fallback.take()?;
+        let (phase, took) = self.running.last().map_or(("unknown", Duration::ZERO), |(p, started)| (*p, now - *started));
+        debug!("prompt killed in phase {} after it ran for {:?}", phase, took);
+        if let Some(cwd) = cwd {
+            record(cwd, phase);
+        }
+        for (_, hook) in self.hooks.drain(..) {
+            hook(phase, took);
+        }
+        Some(fallback)
+    }
+
+}
+
+/// KillHook keeps a hook registered until it is dropped.
+pub struct KillHook(Option<u64>);
+
+impl Drop for KillHook {
+    fn drop(&mut self) {
+        if let Some(id) = self.0 {
+            watch().hooks.retain(|(i, _)| *i != id);
+        }
+    }
+}
+
+/// Run the hook if the process is killed while the guard is alive, so that what only gets saved once a phase finishes,
+/// like the latency of a segment, is saved anyway. Nothing is registered while the watchdog is not armed.
+pub fn on_kill(hook: impl FnOnce(&'static str, Duration) + Send + 'static) -> KillHook {
+    if DEADLINE.get().is_none() {
+        return KillHook(None);
+    }
+    let mut w = watch();
+    let id = w.next_hook;
+    w.next_hook += 1;
+    w.hooks.push((id, Box::new(hook)));
+    KillHook(Some(id))
+}
+
+fn watch() -> std::sync::MutexGuard<'static, Watch> {
+    WATCH.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn limit() -> Option<Duration> {
+    let limit = match std::env::var(LIMIT_ENVVAR) {
+        Ok(raw) => match raw.trim().parse() {
+            Ok(ms) => Duration::from_millis(ms),
+            Err(e) => {
+                debug!("ignoring invalid {} {:?}: {}", LIMIT_ENVVAR, raw, e);
+                DEFAULT_LIMIT
+            },
+        },
+        Err(_) => DEFAULT_LIMIT,
+    };
+    (!limit.is_zero()).then_some(limit)
+}
+
+/// Start the clock, with the prompt to print if the work can't be stopped in time.
+pub fn arm(limit: Duration, fallback: Vec<u8>, cwd: Option<&Path>) {
+    let deadline = Instant::now() + limit;
+    if DEADLINE.set(deadline).is_err() {
+        return;
+    }
+    watch().fallback = Some(fallback);
+    let cwd = cwd.map(Path::to_path_buf);
+    let spawned = std::thread::Builder::new().name(String::from("watchdog")).spawn(move || {
+        std::thread::sleep((deadline + KILL_GRACE).saturating_duration_since(Instant::now()));
+        // holding the lock until the exit keeps the real prompt from being printed as well
+        let mut w = watch();
+        let Some(fallback) = w.kill(cwd.as_deref(), Instant::now()) else {
+            return;
+        };
+        debug!("prompt killed {:?} after the limit of {:?}", KILL_GRACE, limit);
+        let mut out = std::io::stdout().lock();
+        let _ = out.write_all(&fallback).and_then(|_| out.flush());
+        std::process::exit(0);
+    });
+    if let Err(e) = spawned {
+        debug!("could not start the prompt watchdog: {}", e);
+    }
+}
+
+/// Called before the real prompt is printed, after which the process is never killed.
+pub fn disarm() {
+    let mut w = watch();
+    w.fallback = None;
+    w.hooks.clear();
+}
+
+/// Whether the time for the prompt is up, so that the remaining work should be abandoned.
+pub fn expired() -> bool {
+    DEADLINE.get().is_some_and(|d| Instant::now() >= *d)
+}
+
+pub fn enter(phase: &'static str) {
+    if DEADLINE.get().is_some() {
+        watch().running.push((phase, Instant::now()));
+    }
+}
+
+/// Mark the phase as finished, blaming it if the time ran out while it ran.
+pub fn leave(phase: &'static str, started: Instant) {
+    let Some(deadline) = DEADLINE.get() else {
+        return;
+    };
+    let mut w = watch();
+    w.running.pop();
+    if w.culprit.is_none() && ran_out_during(*deadline, started, Instant::now()) {
+        debug!("phase {} ran past the prompt limit, skipping the rest", phase);
+        w.culprit = Some(phase);
+    }
+}
+
+fn ran_out_during(deadline: Instant, started: Instant, finished: Instant) -> bool {
+    started < deadline && deadline <= finished
+}
+
+/// The phase that used up the time of this prompt.
+pub fn culprit() -> Option<&'static str> {
+    watch().culprit
+}
+
+pub fn record(cwd: &Path, phase: &str) {
+    if let Err(e) = cache::put(CACHE_NAMESPACE, cwd.as_os_str().as_bytes(), phase.as_bytes()) {
+        debug!("failed to record the phase that ran out of time: {}", e);
+    }
+}
+
+/// The phase the last prompt that ran out of time in the directory was in.
+pub fn last_cut_off(cwd: &Path) -> Option<String> {
+    cache::get(CACHE_NAMESPACE, cwd.as_os_str().as_bytes(), None).and_then(|v| String::from_utf8(v).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ran_out_during_examples() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        assert!(ran_out_during(start + ms(500), start + ms(400), start + ms(600)));
+        assert!(ran_out_during(start + ms(500), start + ms(400), start + ms(500)));
+        assert!(!ran_out_during(start + ms(500), start + ms(100), start + ms(400)));
+        // a phase that starts after the time is up was never going to make it, and isn't the one to blame
+        assert!(!ran_out_during(start + ms(500), start + ms(500), start + ms(600)));
+    }
+
+    #[test]
+    fn kill_runs_hooks_once() {
+        use std::sync::{Arc, Mutex};
+        let start = Instant::now();
+        let told: Arc<Mutex<Vec<(&'static str, Duration)>>> = Arc::default();
+        let mut w = Watch::new();
+        w.fallback = Some(b"PS1='> '".to_vec());
+        w.running = vec![("git_open", start), ("git_status", start + Duration::from_millis(100))];
+        for _ in 0..2 {
+            let told = told.clone();
+            w.hooks.push((0, Box::new(move |phase, took| told.lock().unwrap().push((phase, took)))));
+        }
+        assert_eq!(w.kill(None, start + Duration::from_millis(600)).as_deref(), Some(b"PS1='> '".as_slice()));
+        let expected = ("git_status", Duration::from_millis(500));
+        assert_eq!(*told.lock().unwrap(), [expected, expected]);
+        // the real prompt is already on its way once the fallback is gone
+        assert_eq!(w.kill(None, start + Duration::from_millis(700)), None);
+        assert_eq!(told.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,121 @@
+use unicode_width::UnicodeWidthStr;
+
+/// Compute the number of terminal columns the text occupies. ANSI escape sequences (CSI such as colors, and OSC such
+/// as the window title) take no space, wide CJK characters and emoji take two columns, and combining marks and other
+/// control characters take none. Bytes that are not valid UTF-8 are counted as one column each, which is how most
+/// terminals show the replacement character.
+pub fn visible_width(text: &[u8]) -> usize {
+    let mut width = 0;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let end = rest.iter().position(|b| *b == 0x1b).unwrap_or(rest.len());
+        width += plain_width(&rest[..end]);
+        rest = skip_escape(&rest[end..]);
+    }
+    width
+}
+
+/// The width of the terminal, which the shell's stderr or stdin is still attached to while our stdout is captured.
+/// `$COLUMNS` is only used when neither is a terminal, since shells don't usually export it.
+pub fn terminal_columns() -> Option<usize> {
+    for fd in [libc::STDERR_FILENO, libc::STDIN_FILENO] {
+        // SAFETY: winsize is plain data that the ioctl fills in.
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+            return Some(size.ws_col as usize);
+        }
+    }
+    std::env::var("COLUMNS").ok()?.trim().parse().ok().filter(|c| *c > 0)
+}
+
+/// Width of text without escape sequences.
+fn plain_width(text: &[u8]) -> usize {
+    let mut width = 0;
+    for chunk in text.utf8_chunks() {
+        width += chunk.valid()
+            .split(|c: char| c.is_control())
+            .map(UnicodeWidthStr::width)
+            .sum::<usize>();
+        width += chunk.invalid().len();
+    }
+    width
+}
+
+/// Skip the escape sequence at the start of the input and return what follows it. An unterminated sequence swallows
+/// the rest of the input, just as it would in the terminal.
+fn skip_escape(input: &[u8]) -> &[u8] {
+    match input {
+        // CSI: parameter and intermediate bytes, then a single final byte
+        [0x1b, b'[', rest @ ..] => match rest.iter().position(|b| (0x40..=0x7e).contains(b)) {
+            Some(i) => &rest[i + 1..],
+            None => &[],
+        },
+        // OSC: ends with BEL or ST (ESC \)
+        [0x1b, b']', rest @ ..] => {
+            let mut i = 0;
+            while i < rest.len() {
+                match rest[i] {
+                    0x07 => return &rest[i + 1..],
+                    0x1b if rest.get(i + 1) == Some(&b'\\') => return &rest[i + 2..],
+                    _ => i += 1,
+                }
+            }
+            &[]
+        },
+        // any other escape is intermediate bytes followed by a single final byte, like ESC ( B
+        [0x1b, rest @ ..] => match rest.iter().position(|b| !(0x20..=0x2f).contains(b)) {
+            Some(i) => &rest[i + 1..],
+            None => &[],
+        },
+        _ => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn next(seed: &mut u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed
+    }
+
+    /// A mix of ascii, combining marks, CJK, emoji, and zero width joiners.
+    const SAMPLES: &[&str] = &["a", "Z", " ", "/", "é", "e\u{301}", "漢", "字", "ｱ", "한", "🦀", "👍🏽", "👨\u{200d}👩\u{200d}👧", "\u{200b}", "⟫", "…", "\t"];
+
+    const ESCAPES: &[&str] = &["\x1b[0m", "\x1b[1m\x1b[38;5;10m", "\x1b[31m", "\x1b]0;/some/title\x07", "\x1b]0;title\x1b\\", "\x1b(B"];
+
+    #[test]
+    fn visible_width_examples() {
+        assert_eq!(visible_width(b""), 0);
+        assert_eq!(visible_width(b"[root main ~/src > "), 19);
+        assert_eq!(visible_width("漢字".as_bytes()), 4);
+        assert_eq!(visible_width("\x1b[31m1\x1b[0m 🦀".as_bytes()), 4);
+        assert_eq!(visible_width(b"\x1b]0;a long title\x07ok"), 2);
+        assert_eq!(visible_width(b"a\xffb"), 3);
+        assert_eq!(visible_width(b"unterminated\x1b[31"), 12);
+    }
+
+    /// Inserting escape sequences anywhere between the pieces of a string never changes its width, and the width
+    /// always matches unicode-width for the plain string.
+    #[test]
+    fn visible_width_matches_unicode_width() {
+        let mut seed = 0x2545F4914F6CDD1D;
+        for _ in 0..5_000 {
+            let (mut plain, mut styled) = (String::new(), String::new());
+            for _ in 0..(next(&mut seed) % 12) {
+                if next(&mut seed) % 3 == 0 {
+                    styled.push_str(ESCAPES[(next(&mut seed) % ESCAPES.len() as u64) as usize]);
+                }
+                let sample = SAMPLES[(next(&mut seed) % SAMPLES.len() as u64) as usize];
+                plain.push_str(sample);
+                styled.push_str(sample);
+            }
+            let expected: usize = plain.split(|c: char| c.is_control()).map(UnicodeWidthStr::width).sum();
+            assert_eq!(visible_width(plain.as_bytes()), expected, "{:?}", plain);
+            assert_eq!(visible_width(styled.as_bytes()), expected, "{:?}", styled);
+        }
+    }
+}
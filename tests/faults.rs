@@ -0,0 +1,114 @@
+//! Run the binary with each internal operation made to fail through $NUPROMPT_FAULT and check that the prompt still
+//! comes out and makes sense.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const OPERATIONS: &[&str] = &["cwd", "config", "state_read", "state_write", "git_open", "git_status", "cache", "hostname"];
+
+struct Sandbox {
+    root: PathBuf,
+    repo: PathBuf,
+}
+
+impl Sandbox {
+
+    /// A home with a config file and a repo on a branch, so that every operation has something to do.
+    fn new(name: &str) -> Sandbox {
+        let root = std::env::temp_dir().join(format!("nuprompt-faults-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let repo = root.join("repo");
+        std::fs::create_dir_all(root.join("config/nuprompt")).unwrap();
+        std::fs::create_dir_all(root.join("state")).unwrap();
+        std::fs::write(root.join("config/nuprompt/config.toml"), "show_command = true\n").unwrap();
+        let r = git2::Repository::init(&repo).unwrap();
+        std::fs::write(repo.join("file"), "contents").unwrap();
+        let mut index = r.index().unwrap();
+        index.add_path(Path::new("file")).unwrap();
+        index.write().unwrap();
+        let tree = r.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        r.commit(Some("refs/heads/topic"), &sig, &sig, "initial", &tree, &[]).unwrap();
+        r.set_head("refs/heads/topic").unwrap();
+        Sandbox { root, repo }
+    }
+
+    fn run(&self, fault: &str, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_nuprompt"))
+            .args(args)
+            .current_dir(&self.repo)
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", &self.root)
+            .env("XDG_CONFIG_HOME", self.root.join("config"))
+            .env("NUPROMPT_STATE_DIR", self.root.join("state"))
+            .env("PWD", &self.repo)
+            .env("LANG", "C")
+            .env("NUPROMPT_FAULT", fault)
+            .output()
+            .unwrap()
+    }
+
+    /// Start and finish a command the way the bash hooks do, and return the prompt.
+    fn prompt(&self, fault: &str) -> String {
+        let ps0 = self.run(fault, &["ps0", "4242", "make test"]);
+        assert!(ps0.status.success(), "ps0 failed with {}: {}", fault, String::from_utf8_lossy(&ps0.stderr));
+        assert!(ps0.stderr.is_empty(), "ps0 complained with {}: {}", fault, String::from_utf8_lossy(&ps0.stderr));
+        let ps1 = self.run(fault, &["ps1", "4242", "1", "--color=never"]);
+        assert!(ps1.status.success(), "ps1 failed with {}: {}", fault, String::from_utf8_lossy(&ps1.stderr));
+        String::from_utf8(ps1.stdout).unwrap()
+    }
+
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+#[test]
+fn prompt_survives_each_fault() {
+    let healthy = Sandbox::new("healthy").prompt("");
+    assert!(healthy.contains(" topic "), "no branch in {:?}", healthy);
+    assert!(healthy.contains("make test"), "no command in {:?}", healthy);
+    for fault in OPERATIONS {
+        // a sandbox each, since a failed state read leaves the command behind for the next prompt
+        let prompt = Sandbox::new(fault).prompt(fault);
+        assert!(prompt.starts_with("PS1='[1 "), "{} lost the exit code: {:?}", fault, prompt);
+        assert!(prompt.ends_with("> '"), "{} broke the prompt end: {:?}", fault, prompt);
+        assert!(prompt.contains("repo "), "{} lost the path: {:?}", fault, prompt);
+        // the parts that don't depend on the failed operation are still there
+        let has_branch = prompt.contains(" topic ");
+        assert_eq!(has_branch, !["git_open", "git_status"].contains(fault), "{}: {:?}", fault, prompt);
+        let has_command = prompt.contains("make test");
+        assert_eq!(has_command, !["config", "state_read", "state_write"].contains(fault), "{}: {:?}", fault, prompt);
+    }
+}
+
+#[test]
+fn prompt_survives_every_fault_at_once() {
+    let sandbox = Sandbox::new("all");
+    let prompt = sandbox.prompt(&OPERATIONS.join(","));
+    assert!(prompt.starts_with("PS1='[1 "), "{:?}", prompt);
+    assert!(prompt.ends_with("repo > '"), "{:?}", prompt);
+}
+
+#[test]
+fn stalled_prompt_is_cut_short() {
+    let sandbox = Sandbox::new("stall");
+    let started = std::time::Instant::now();
+    let prompt = sandbox.prompt("git_stall");
+    // the stall lasts two seconds, the prompt limit and the grace after it well under one
+    assert!(started.elapsed() < std::time::Duration::from_millis(1500), "took {:?}", started.elapsed());
+    assert!(prompt.starts_with("PS1='[1 "), "{:?}", prompt);
+    assert!(prompt.ends_with("repo > '"), "{:?}", prompt);
+    assert!(!prompt.contains(" topic "), "{:?}", prompt);
+    let doctor = String::from_utf8(sandbox.run("", &["doctor"]).stdout).unwrap();
+    assert!(doctor.contains("ran out of time was in git_status"), "{:?}", doctor);
+    // the kill downgraded the repo to a scan that leaves the stalling worktree alone, so the branch is back
+    let started = std::time::Instant::now();
+    let prompt = sandbox.prompt("git_stall");
+    assert!(started.elapsed() < std::time::Duration::from_millis(500), "took {:?}", started.elapsed());
+    assert!(prompt.contains(" topic"), "{:?}", prompt);
+}